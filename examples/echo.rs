@@ -1,3 +1,5 @@
+#![allow(clippy::single_match, clippy::unused_io_amount)]
+
 use pnet::packet::{
     ip::IpNextHeaderProtocols,
     ipv4::{self, Ipv4Packet, MutableIpv4Packet},
@@ -1,14 +1,9 @@
-use pnet::packet::{
-    ip::IpNextHeaderProtocols,
-    ipv4::{self, Ipv4Packet, MutableIpv4Packet},
-    udp::{MutableUdpPacket, UdpPacket},
-    MutablePacket, Packet,
-};
+use pnet::packet::{ip::IpNextHeaderProtocols, ipv4::Ipv4Packet, Packet};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
-use tun_rs::{OsTun, Tun, TunConfig};
+use tun_rs::{checksum, OsTun, Tun, TunConfig};
 
 fn init_tracing() {
     tracing_subscriber::FmtSubscriber::builder()
@@ -17,27 +12,18 @@ fn init_tracing() {
         .init();
 }
 
-fn handle_packet(ip: &Ipv4Packet, udp: &UdpPacket) -> MutableIpv4Packet<'static> {
-    let mut new_packet =
-        MutableIpv4Packet::owned(vec![0; ip.packet().len()]).expect("failed to create packet");
-    new_packet.clone_from(ip);
-
-    new_packet.set_identification(0);
-    new_packet.set_source(ip.get_destination());
-    new_packet.set_destination(ip.get_source());
-
-    let mut udp_vec: Vec<u8> = vec![0; udp.packet().len()];
-    let mut new_udp = MutableUdpPacket::new(&mut udp_vec[..]).expect("failed to create udp packet");
-    new_udp.clone_from(udp);
-
-    new_udp.set_source(udp.get_destination());
-    new_udp.set_destination(udp.get_source());
+fn handle_packet(ip: &Ipv4Packet) -> Vec<u8> {
+    let mut pkt = ip.packet().to_vec();
 
-    new_packet.set_payload(new_udp.packet());
+    // swapping endpoints leaves the checksums intact, only the identification needs
+    // an incremental update
+    checksum::swap_endpoints(&mut pkt).expect("failed to swap endpoints");
+    let csum = u16::from_be_bytes([pkt[10], pkt[11]]);
+    let csum = checksum::update_u16(csum, ip.get_identification(), 0);
+    pkt[4..6].copy_from_slice(&[0, 0]);
+    pkt[10..12].copy_from_slice(&csum.to_be_bytes());
 
-    let checksum = ipv4::checksum(&new_packet.to_immutable());
-    new_packet.set_checksum(checksum);
-    new_packet
+    pkt
 }
 
 fn main() {
@@ -78,11 +64,8 @@ fn main() {
                 if let Some(ip) = Ipv4Packet::new(&buf[..size]) {
                     match ip.get_next_level_protocol() {
                         IpNextHeaderProtocols::Udp => {
-                            if let Some(udp) = UdpPacket::new(ip.payload()) {
-                                let pkt = handle_packet(&ip, &udp);
-                                tun.write_packet(pkt.packet(), pi)
-                                    .expect("failed to write packet");
-                            }
+                            let pkt = handle_packet(&ip);
+                            tun.write_packet(&pkt, pi).expect("failed to write packet");
                         }
                         _ => { /* ignore other protocols */ }
                     }
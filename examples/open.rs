@@ -2,6 +2,8 @@
 //!
 //! Reads from the tunnel device and prints any TCP packets received to the terminal
 
+#![allow(clippy::single_match, clippy::unused_io_amount)]
+
 use pnet::packet::{
     ip::IpNextHeaderProtocols,
     ipv4::Ipv4Packet,
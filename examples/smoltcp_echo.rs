@@ -0,0 +1,89 @@
+//! Userspace TCP Echo Server Example
+//!
+//! Runs a smoltcp TCP/IP stack on top of a tunnel device and echos any data sent to
+//! `192.168.90.2:7` back to the sender (e.g., `nc 192.168.90.2 7`)
+
+use smoltcp::{
+    iface::{Config, Interface, SocketSet},
+    socket::tcp,
+    time::Instant,
+    wire::{HardwareAddress, IpAddress, IpCidr},
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tun_rs::{phy, Tun, TunConfig, TunDevice};
+
+const ECHO_PORT: u16 = 7;
+
+fn init_tracing() {
+    tracing_subscriber::FmtSubscriber::builder()
+        .pretty()
+        .with_max_level(tracing::Level::DEBUG)
+        .init();
+}
+
+fn main() {
+    init_tracing();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    ctrlc::set_handler({
+        let stop = stop.clone();
+        move || stop.store(true, Ordering::Relaxed)
+    })
+    .expect("failed to set ctrl-c handler");
+
+    // the host side of the tunnel is `192.168.90.1`, smoltcp answers on `192.168.90.2`
+    let cfg = TunConfig::default().ip([192, 168, 90, 1], 24);
+
+    #[cfg(target_os = "linux")]
+    let cfg = cfg.name("smol0");
+
+    let mut tun = TunDevice::create(cfg).expect("failed to build tun device");
+    tun.up().expect("failed to set tun as up");
+
+    let mut iface = Interface::new(Config::new(HardwareAddress::Ip), &mut tun, Instant::now());
+    iface.update_ip_addrs(|addrs| {
+        addrs
+            .push(IpCidr::new(IpAddress::v4(192, 168, 90, 2), 24))
+            .expect("failed to assign ip address");
+    });
+
+    let socket = tcp::Socket::new(
+        tcp::SocketBuffer::new(vec![0; 4096]),
+        tcp::SocketBuffer::new(vec![0; 4096]),
+    );
+    let mut sockets = SocketSet::new(vec![]);
+    let handle = sockets.add(socket);
+
+    println!("waiting for ctrl-c event...");
+
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        iface.poll(now, &mut tun, &mut sockets);
+
+        let socket = sockets.get_mut::<tcp::Socket>(handle);
+        if !socket.is_open() {
+            socket.listen(ECHO_PORT).expect("failed to listen");
+        }
+
+        if socket.may_recv() {
+            let data = socket
+                .recv(|buf| (buf.len(), buf.to_vec()))
+                .expect("failed to receive data");
+
+            if !data.is_empty() && socket.can_send() {
+                tracing::info!("echoing {} bytes", data.len());
+                socket.send_slice(&data).expect("failed to send data");
+            }
+        } else if socket.may_send() {
+            // remote side has closed the connection
+            socket.close();
+        }
+
+        phy::wait(&tun, iface.poll_delay(now, &sockets)).expect("failed to wait on tun device");
+    }
+
+    println!("caught ctrl-c, qutting");
+}
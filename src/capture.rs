@@ -0,0 +1,492 @@
+//! Packet capture of tunnel traffic
+//!
+//! [`Capture`] wraps any [`Tun`] implementor and records every packet read from or
+//! written to the device in [pcapng] format, so traffic can be inspected with tools such
+//! as Wireshark or tcpdump.
+//!
+//! Packet directions are recorded from the point of view of the interface (matching a
+//! capture taken on the interface itself):
+//! * packets returned by `read_packet` were sent by the host and are marked outbound
+//! * packets passed to `write_packet` are received by the host and are marked inbound
+//!
+//! [pcapng]: https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-01.html
+
+use crate::{Tun, TunError};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_EPB_FLAGS: u16 = 2;
+
+/// Link-layer type of the captured packets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkType {
+    /// Raw IPv4 / IPv6 packets (TUN devices)
+    Raw,
+
+    /// Ethernet frames (TAP devices)
+    Ethernet,
+}
+
+impl LinkType {
+    fn value(self) -> u16 {
+        match self {
+            LinkType::Raw => 101,
+            LinkType::Ethernet => 1,
+        }
+    }
+}
+
+/// Direction of a captured packet, relative to the interface
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Packet received by the host (written to the tun device)
+    Inbound,
+
+    /// Packet sent by the host (read from the tun device)
+    Outbound,
+}
+
+impl Direction {
+    fn flags(self) -> u32 {
+        match self {
+            Direction::Inbound => 0b01,
+            Direction::Outbound => 0b10,
+        }
+    }
+}
+
+/// Writes packets to a stream in pcapng format
+#[derive(Debug)]
+pub struct PcapngWriter<W: Write> {
+    // underlying stream
+    writer: W,
+
+    // number of bytes written to the stream
+    written: u64,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Creates a new writer, writing the section header and interface description
+    ///
+    /// # Arguments
+    /// * `writer` - Stream to write the capture to
+    /// * `link_type` - Link-layer type of the captured packets
+    ///
+    /// # Errors
+    /// * I/O if the headers fail to write
+    pub fn new(writer: W, link_type: LinkType) -> io::Result<Self> {
+        let mut pcap = Self { writer, written: 0 };
+
+        // section header block
+        let mut body = Vec::with_capacity(16);
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        pcap.write_block(BLOCK_SECTION_HEADER, &body)?;
+
+        // interface description block (snaplen of 0 means no limit)
+        let mut body = Vec::with_capacity(8);
+        body.extend_from_slice(&link_type.value().to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        pcap.write_block(BLOCK_INTERFACE_DESCRIPTION, &body)?;
+
+        Ok(pcap)
+    }
+
+    /// Records a single packet
+    ///
+    /// # Arguments
+    /// * `pkt` - Packet data
+    /// * `direction` - Direction the packet travelled
+    /// * `timestamp` - Time the packet was seen
+    ///
+    /// # Errors
+    /// * I/O if the packet fails to write
+    pub fn write_packet(
+        &mut self,
+        pkt: &[u8],
+        direction: Direction,
+        timestamp: SystemTime,
+    ) -> io::Result<()> {
+        // default interface timestamp resolution is microseconds
+        let ts = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or_default();
+
+        let padding = (4 - pkt.len() % 4) % 4;
+        let mut body = Vec::with_capacity(32 + pkt.len() + padding);
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ts as u32).to_le_bytes());
+        body.extend_from_slice(&(pkt.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(pkt.len() as u32).to_le_bytes());
+        body.extend_from_slice(pkt);
+        body.resize(body.len() + padding, 0);
+
+        // epb_flags option (direction) followed by end of options
+        body.extend_from_slice(&OPT_EPB_FLAGS.to_le_bytes());
+        body.extend_from_slice(&4u16.to_le_bytes());
+        body.extend_from_slice(&direction.flags().to_le_bytes());
+        body.extend_from_slice(&OPT_END_OF_OPT.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+
+        self.write_block(BLOCK_ENHANCED_PACKET, &body)
+    }
+
+    /// Returns the number of bytes written to the stream
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Flushes the underlying stream
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Consumes this writer, returning the underlying stream
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let len = (body.len() + 12) as u32;
+        self.writer.write_all(&block_type.to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(body)?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.written += len as u64;
+        Ok(())
+    }
+}
+
+/// Policy used to rotate capture files (ring buffer)
+#[derive(Clone, Debug)]
+pub struct RingPolicy {
+    // base path of capture files (files are named `<path>.<n>`)
+    path: PathBuf,
+
+    // size after which a new file is started
+    file_size: u64,
+
+    // maximum number of files to keep
+    file_count: usize,
+}
+
+impl RingPolicy {
+    /// Creates a new rotation policy (10 files of 10 MiB each by default)
+    ///
+    /// # Arguments
+    /// * `path` - Base path of the capture files, a sequence number is appended to each
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            file_size: 10 * 1024 * 1024,
+            file_count: 10,
+        }
+    }
+
+    /// Sets the size (in bytes) after which a new capture file is started
+    pub fn file_size(mut self, bytes: u64) -> Self {
+        self.file_size = bytes;
+        self
+    }
+
+    /// Sets the maximum number of capture files to keep (oldest are deleted first)
+    pub fn file_count(mut self, count: usize) -> Self {
+        self.file_count = count.max(1);
+        self
+    }
+
+    fn file_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+}
+
+/// Destination of captured packets
+#[derive(Debug)]
+enum Output<W: Write> {
+    Stream(PcapngWriter<W>),
+    Ring {
+        policy: RingPolicy,
+        link_type: LinkType,
+        index: usize,
+        writer: PcapngWriter<BufWriter<File>>,
+    },
+}
+
+impl<W: Write> Output<W> {
+    fn write_packet(&mut self, pkt: &[u8], direction: Direction) -> io::Result<()> {
+        let now = SystemTime::now();
+        match self {
+            Output::Stream(writer) => writer.write_packet(pkt, direction, now),
+            Output::Ring {
+                policy,
+                link_type,
+                index,
+                writer,
+            } => {
+                if writer.written() >= policy.file_size {
+                    writer.flush()?;
+                    *index += 1;
+                    *writer = open_ring_file(&policy.file_path(*index), *link_type)?;
+
+                    if let Some(old) = index.checked_sub(policy.file_count) {
+                        if let Err(error) = fs::remove_file(policy.file_path(old)) {
+                            tracing::warn!(?error, "failed to remove old capture file");
+                        }
+                    }
+                }
+
+                writer.write_packet(pkt, direction, now)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stream(writer) => writer.flush(),
+            Output::Ring { writer, .. } => writer.flush(),
+        }
+    }
+}
+
+fn open_ring_file(path: &Path, link_type: LinkType) -> io::Result<PcapngWriter<BufWriter<File>>> {
+    PcapngWriter::new(BufWriter::new(File::create(path)?), link_type)
+}
+
+/// A tun device wrapper that records all traffic to a pcapng capture
+///
+/// Failing to record a packet does not fail the read or write, the error is logged
+/// instead.
+#[derive(Debug)]
+pub struct Capture<T: Tun, W: Write = BufWriter<File>> {
+    // wrapped device
+    tun: T,
+
+    // capture destination
+    output: Mutex<Output<W>>,
+}
+
+impl<T: Tun> Capture<T> {
+    /// Wraps `tun`, recording all traffic to the file at `path`
+    ///
+    /// # Arguments
+    /// * `tun` - Device to wrap
+    /// * `path` - Capture file to create (truncated if it exists)
+    /// * `link_type` - Link-layer type of the device
+    ///
+    /// # Errors
+    /// * I/O if the capture file fails to open
+    pub fn create(tun: T, path: impl AsRef<Path>, link_type: LinkType) -> Result<Self, TunError> {
+        let file = BufWriter::new(File::create(path)?);
+        Self::new(tun, file, link_type)
+    }
+
+    /// Wraps `tun`, recording all traffic to a rotating set of files
+    ///
+    /// # Arguments
+    /// * `tun` - Device to wrap
+    /// * `policy` - Rotation policy of the capture files
+    /// * `link_type` - Link-layer type of the device
+    ///
+    /// # Errors
+    /// * I/O if the first capture file fails to open
+    pub fn ring(tun: T, policy: RingPolicy, link_type: LinkType) -> Result<Self, TunError> {
+        let writer = open_ring_file(&policy.file_path(0), link_type)?;
+        Ok(Self {
+            tun,
+            output: Mutex::new(Output::Ring {
+                policy,
+                link_type,
+                index: 0,
+                writer,
+            }),
+        })
+    }
+}
+
+impl<T: Tun, W: Write> Capture<T, W> {
+    /// Wraps `tun`, recording all traffic to `writer`
+    ///
+    /// # Arguments
+    /// * `tun` - Device to wrap
+    /// * `writer` - Stream to write the capture to
+    /// * `link_type` - Link-layer type of the device
+    ///
+    /// # Errors
+    /// * I/O if the capture headers fail to write
+    pub fn new(tun: T, writer: W, link_type: LinkType) -> Result<Self, TunError> {
+        Ok(Self {
+            tun,
+            output: Mutex::new(Output::Stream(PcapngWriter::new(writer, link_type)?)),
+        })
+    }
+
+    /// Returns a reference to the wrapped device
+    pub fn get_ref(&self) -> &T {
+        &self.tun
+    }
+
+    /// Flushes any buffered capture data
+    ///
+    /// # Errors
+    /// * I/O if the capture fails to flush
+    pub fn flush(&self) -> io::Result<()> {
+        self.output
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .flush()
+    }
+
+    /// Consumes the capture, returning the wrapped device and the capture stream (if not
+    /// writing to a ring of files)
+    pub fn into_parts(self) -> (T, Option<W>) {
+        let output = self.output.into_inner().unwrap_or_else(|e| e.into_inner());
+        match output {
+            Output::Stream(writer) => (self.tun, Some(writer.into_inner())),
+            Output::Ring { mut writer, .. } => {
+                if let Err(error) = writer.flush() {
+                    tracing::warn!(?error, "failed to flush capture file");
+                }
+                (self.tun, None)
+            }
+        }
+    }
+
+    fn record(&self, pkt: &[u8], direction: Direction) {
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(error) = output.write_packet(pkt, direction) {
+            tracing::warn!(?error, "failed to record packet");
+        }
+    }
+}
+
+impl<T: Tun, W: Write> Tun for Capture<T, W> {
+    type PktInfo = T::PktInfo;
+
+    fn up(&self) -> Result<(), TunError> {
+        self.tun.up()
+    }
+
+    fn down(&self) -> Result<(), TunError> {
+        self.tun.down()
+    }
+
+    fn read_packet(&self, buf: &mut [u8]) -> Result<(usize, Self::PktInfo), TunError> {
+        let (n, pi) = self.tun.read_packet(buf)?;
+        self.record(&buf[..n], Direction::Outbound);
+        Ok((n, pi))
+    }
+
+    fn write_packet(&self, buf: &[u8], pi: Self::PktInfo) -> Result<usize, io::Error> {
+        let n = self.tun.write_packet(buf, pi)?;
+        self.record(buf, Direction::Inbound);
+        Ok(n)
+    }
+
+    fn blank_pktinfo(&self) -> Self::PktInfo {
+        self.tun.blank_pktinfo()
+    }
+
+    fn poll_readable(&self, timeout: Duration) -> Result<bool, TunError> {
+        self.tun.poll_readable(timeout)
+    }
+}
+
+#[cfg(all(test, feature = "channel"))]
+mod tests {
+    use super::*;
+    use crate::{ChannelTun, TunConfig};
+
+    /// Returns (block type, body) for every block in a capture
+    fn blocks(data: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut blocks = Vec::new();
+        let mut off = 0;
+        while off < data.len() {
+            let ty = u32::from_le_bytes(data[off..off + 4].try_into().unwrap());
+            let len = u32::from_le_bytes(data[off + 4..off + 8].try_into().unwrap()) as usize;
+            let trailer = u32::from_le_bytes(data[off + len - 4..off + len].try_into().unwrap());
+            assert_eq!(len as u32, trailer);
+            assert_eq!(len % 4, 0);
+            blocks.push((ty, &data[off + 8..off + len - 4]));
+            off += len;
+        }
+        blocks
+    }
+
+    #[test]
+    fn records_both_directions() {
+        let (local, peer) = ChannelTun::create("cap0", TunConfig::default()).unwrap();
+        let cap = Capture::new(local, Vec::new(), LinkType::Raw).unwrap();
+
+        peer.write_packet(b"outbound", ()).unwrap();
+        let mut buf = [0u8; 64];
+        cap.read_packet(&mut buf).unwrap();
+        cap.write_packet(b"inbound!!", ()).unwrap();
+
+        let (_, data) = cap.into_parts();
+        let data = data.unwrap();
+        let blocks = blocks(&data);
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[0].0, BLOCK_SECTION_HEADER);
+        assert_eq!(blocks[1].0, BLOCK_INTERFACE_DESCRIPTION);
+        assert_eq!(blocks[1].1[..2], 101u16.to_le_bytes());
+
+        for (block, pkt, flags) in [
+            (&blocks[2], &b"outbound"[..], 0b10),
+            (&blocks[3], b"inbound!!", 0b01),
+        ] {
+            let (ty, body) = block;
+            assert_eq!(*ty, BLOCK_ENHANCED_PACKET);
+            let len = u32::from_le_bytes(body[12..16].try_into().unwrap()) as usize;
+            assert_eq!(&body[20..20 + len], pkt);
+
+            let opts = &body[20 + ((len + 3) & !3)..];
+            assert_eq!(opts[..4], [2, 0, 4, 0]);
+            assert_eq!(opts[4..8], (flags as u32).to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn ring_rotation() {
+        let dir = std::env::temp_dir().join(format!("tun-rs-capture-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("ring.pcapng");
+
+        let (local, _peer) = ChannelTun::create("cap0", TunConfig::default()).unwrap();
+        let policy = RingPolicy::new(&base).file_size(100).file_count(2);
+        let cap = Capture::ring(local, policy.clone(), LinkType::Raw).unwrap();
+        for _ in 0..6 {
+            cap.write_packet(&[0u8; 64], ()).unwrap();
+        }
+        cap.into_parts();
+
+        // 6 packets of ~100 bytes each -> 6 files, only the last 2 are kept
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["ring.pcapng.4", "ring.pcapng.5"]);
+
+        let data = fs::read(policy.file_path(5)).unwrap();
+        assert_eq!(blocks(&data).len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
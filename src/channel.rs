@@ -1,13 +1,17 @@
 //! A channel-based device that can be used for testing
 
 use crate::{Tun, TunConfig, TunError};
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::{Receiver, Select, Sender};
 use std::{
     cmp,
     io::{self, Read, Write},
     net::IpAddr,
+    time::Duration,
 };
 
+/// A pair of in-memory tun devices connected by channels
+///
+/// Any packet written to one end of the pair can be read from the other end.
 #[derive(Debug)]
 pub struct ChannelTun {
     // IP address assigned to this channel
@@ -23,6 +27,11 @@ pub struct ChannelTun {
     rx_buffer: Vec<u8>,
 }
 
+/// Error returned when the other end of the channel pair has been dropped
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "channel tun peer disconnected")
+}
+
 impl Read for ChannelTun {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         // check if buffered data exists
@@ -34,7 +43,7 @@ impl Read for ChannelTun {
             return Ok(amt);
         }
 
-        let mut data = self.rx.recv().unwrap();
+        let mut data = self.rx.recv().map_err(|_| disconnected())?;
         let len = {
             let to_copy = cmp::min(data.len(), buf.len());
             let iter = data.drain(..to_copy);
@@ -55,7 +64,7 @@ impl Read for ChannelTun {
 impl Write for ChannelTun {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let len = buf.len();
-        self.tx.send(buf.to_vec()).unwrap();
+        self.tx.send(buf.to_vec()).map_err(|_| disconnected())?;
         Ok(len)
     }
 
@@ -66,8 +75,6 @@ impl Write for ChannelTun {
 }
 
 impl Tun for ChannelTun {
-    type Reader = ();
-    type Writer = ();
     type PktInfo = ();
 
     fn up(&self) -> Result<(), TunError> {
@@ -80,29 +87,44 @@ impl Tun for ChannelTun {
         Ok(())
     }
 
-    fn split(&self) -> (Self::Reader, Self::Writer) {
-        ((), ())
+    /// Reads the next packet sent by the peer
+    ///
+    /// Like a real tun device, if `buf` is too small to hold the packet, the remaining
+    /// bytes are discarded.
+    fn read_packet(&self, buf: &mut [u8]) -> Result<(usize, Self::PktInfo), TunError> {
+        let pkt = self.rx.recv().map_err(|_| disconnected())?;
+        let len = cmp::min(pkt.len(), buf.len());
+        buf[..len].copy_from_slice(&pkt[..len]);
+        Ok((len, ()))
     }
 
-    fn read_packet(&self, _buf: &mut [u8]) -> Result<Self::PktInfo, TunError> {
-        // TODO implement this
-        Ok(())
+    fn write_packet(&self, buf: &[u8], _pi: Self::PktInfo) -> Result<usize, io::Error> {
+        self.tx.send(buf.to_vec()).map_err(|_| disconnected())?;
+        Ok(buf.len())
     }
 
-    fn write_packet(&self, _buf: &[u8], _af: u32) -> Result<usize, io::Error> {
-        // TODO implement this
-        Ok(0)
+    fn blank_pktinfo(&self) -> Self::PktInfo {}
+
+    fn poll_readable(&self, timeout: Duration) -> Result<bool, TunError> {
+        let mut select = Select::new();
+        select.recv(&self.rx);
+        Ok(select.ready_timeout(timeout).is_ok())
     }
 }
 
 impl ChannelTun {
     /// Creates a new ChannelTun pair
-    pub fn create(name: &str, _cfg: TunConfig) -> Result<(Self, Self), TunError> {
+    ///
+    /// # Arguments
+    /// * `name` - Name to assign to both ends of the pair
+    /// * `cfg` - Tunnel configuration (only the ip is used)
+    pub fn create(name: &str, cfg: TunConfig) -> Result<(Self, Self), TunError> {
         let (tx0, rx0) = crossbeam_channel::unbounded();
         let (tx1, rx1) = crossbeam_channel::unbounded();
+        let ip = cfg.ip.map(|(ip, _)| ip);
 
         let chan_a = Self {
-            ip: None,
+            ip,
             name: name.to_owned(),
             rx_buffer: Vec::new(),
             tx: tx0,
@@ -110,7 +132,7 @@ impl ChannelTun {
         };
 
         let chan_b = Self {
-            ip: None,
+            ip,
             name: name.to_owned(),
             rx_buffer: Vec::new(),
             tx: tx1,
@@ -119,6 +141,22 @@ impl ChannelTun {
 
         Ok((chan_a, chan_b))
     }
+
+    /// Returns the name of this channel
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the ip address assigned to this channel (if any)
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.ip
+    }
+
+    /// Returns the next packet sent by the peer without blocking
+    #[cfg(feature = "smoltcp")]
+    pub(crate) fn try_recv(&self) -> Option<Vec<u8>> {
+        self.rx.try_recv().ok()
+    }
 }
 
 #[cfg(test)]
@@ -167,7 +205,7 @@ mod tests {
 
         let tx_msg = "Hello, there";
         local
-            .write(tx_msg.as_bytes())
+            .write_all(tx_msg.as_bytes())
             .expect("failed to write message via local channel tun");
 
         let mut rx_msg = [0u8; 10];
@@ -193,7 +231,7 @@ mod tests {
 
         let tx_msg = "Hello, there";
         local
-            .write(tx_msg.as_bytes())
+            .write_all(tx_msg.as_bytes())
             .expect("failed to write message via local channel tun");
 
         let mut rx_msg = [0u8; 100];
@@ -204,4 +242,40 @@ mod tests {
         assert_eq!(12, n);
         assert_eq!(rx_msg[..n], tx_msg.as_bytes()[..]);
     }
+
+    #[test]
+    fn packet_boundaries() {
+        let (local, peer) = ChannelTun::create("dummy0", TunConfig::default())
+            .expect("failed to create channel tun device");
+
+        local
+            .write_packet(b"first", ())
+            .expect("failed to write packet via local channel tun");
+        local
+            .write_packet(b"second", ())
+            .expect("failed to write packet via local channel tun");
+
+        let mut buf = [0u8; 4];
+        let (n, _) = peer
+            .read_packet(&mut buf)
+            .expect("failed to read packet via peer channel tun");
+        assert_eq!(buf[..n], b"firs"[..]);
+
+        // remainder of a truncated packet is discarded
+        let mut buf = [0u8; 100];
+        let (n, _) = peer
+            .read_packet(&mut buf)
+            .expect("failed to read packet via peer channel tun");
+        assert_eq!(buf[..n], b"second"[..]);
+    }
+
+    #[test]
+    fn peer_disconnected() {
+        let (local, peer) = ChannelTun::create("dummy0", TunConfig::default())
+            .expect("failed to create channel tun device");
+        drop(peer);
+
+        let err = local.write_packet(b"hello", ()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
 }
@@ -0,0 +1,611 @@
+//! Internet checksum utilities
+//!
+//! Provides the one's complement checksums used by IPv4, TCP, UDP, ICMP and ICMPv6,
+//! along with incremental updates ([RFC 1624]) for rewriting fields of a packet
+//! without recomputing the checksum over the entire payload.
+//!
+//! All checksum values are returned in host byte order and should be written into the
+//! packet in network byte order (i.e., `to_be_bytes()`)
+//!
+//! [RFC 1624]: https://www.rfc-editor.org/rfc/rfc1624
+
+use crate::TunError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// IP protocol number for ICMP
+pub const PROTO_ICMP: u8 = 1;
+
+/// IP protocol number for TCP
+pub const PROTO_TCP: u8 = 6;
+
+/// IP protocol number for UDP
+pub const PROTO_UDP: u8 = 17;
+
+/// IP protocol number for ICMPv6
+pub const PROTO_ICMPV6: u8 = 58;
+
+/// Offset of the checksum field in an IPv4 header
+const IPV4_CHECKSUM_OFFSET: usize = 10;
+
+/// Length of the fixed IPv6 header
+const IPV6_HEADER_LEN: usize = 40;
+
+/// Adds `data` to a running one's complement sum
+///
+/// Data is summed as big-endian 16-bit words. If `data` has an odd length, it is padded
+/// with a trailing zero byte.
+///
+/// # Arguments
+/// * `data` - Bytes to add to the sum
+/// * `initial` - Running sum (use `0` to start a new sum)
+pub fn sum(data: &[u8], initial: u32) -> u32 {
+    let mut sum = initial as u64;
+    let mut chunks = data.chunks_exact(2);
+    for word in &mut chunks {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u64;
+    }
+
+    if let [last] = chunks.remainder() {
+        sum += u16::from_be_bytes([*last, 0]) as u64;
+    }
+
+    fold(sum) as u32
+}
+
+/// Folds a running sum into 16 bits and returns its one's complement
+///
+/// # Arguments
+/// * `sum` - Running sum produced by [`sum`]
+pub fn finish(sum: u32) -> u16 {
+    !fold(sum as u64)
+}
+
+/// Computes the internet checksum over `data`
+///
+/// # Arguments
+/// * `data` - Bytes to checksum
+pub fn compute(data: &[u8]) -> u16 {
+    finish(sum(data, 0))
+}
+
+/// Computes the checksum of an IPv4 header
+///
+/// The existing checksum field is treated as zero, so the header does not need to be
+/// cleared first.
+///
+/// # Arguments
+/// * `header` - IPv4 header (including options)
+pub fn ipv4_header(header: &[u8]) -> u16 {
+    let sum = self::sum(&header[..IPV4_CHECKSUM_OFFSET], 0);
+    finish(self::sum(&header[IPV4_CHECKSUM_OFFSET + 2..], sum))
+}
+
+/// Computes the running sum of an IPv4 pseudo-header
+///
+/// # Arguments
+/// * `src` - Source address
+/// * `dst` - Destination address
+/// * `proto` - IP protocol number of the upper-layer payload
+/// * `len` - Length of the upper-layer header and data
+pub fn ipv4_pseudo_header(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, len: u16) -> u32 {
+    let sum = self::sum(&src.octets(), 0);
+    let sum = self::sum(&dst.octets(), sum);
+    let sum = self::sum(&[0, proto], sum);
+    self::sum(&len.to_be_bytes(), sum)
+}
+
+/// Computes the running sum of an IPv6 pseudo-header
+///
+/// # Arguments
+/// * `src` - Source address
+/// * `dst` - Destination address
+/// * `next_header` - Protocol number of the upper-layer payload
+/// * `len` - Length of the upper-layer header and data
+pub fn ipv6_pseudo_header(src: &Ipv6Addr, dst: &Ipv6Addr, next_header: u8, len: u32) -> u32 {
+    let sum = self::sum(&src.octets(), 0);
+    let sum = self::sum(&dst.octets(), sum);
+    let sum = self::sum(&len.to_be_bytes(), sum);
+    self::sum(&[0, 0, 0, next_header], sum)
+}
+
+/// Computes the checksum of a TCP segment
+///
+/// The existing checksum field is treated as zero.
+///
+/// # Arguments
+/// * `pseudo` - Pseudo-header sum (see [`ipv4_pseudo_header`] / [`ipv6_pseudo_header`])
+/// * `segment` - TCP header and payload
+pub fn tcp(pseudo: u32, segment: &[u8]) -> u16 {
+    skip_field(pseudo, segment, 16)
+}
+
+/// Computes the checksum of a UDP datagram
+///
+/// The existing checksum field is treated as zero. A computed checksum of zero is
+/// returned as `0xFFFF`, as zero indicates no checksum over IPv4.
+///
+/// # Arguments
+/// * `pseudo` - Pseudo-header sum (see [`ipv4_pseudo_header`] / [`ipv6_pseudo_header`])
+/// * `datagram` - UDP header and payload
+pub fn udp(pseudo: u32, datagram: &[u8]) -> u16 {
+    match skip_field(pseudo, datagram, 6) {
+        0 => 0xFFFF,
+        csum => csum,
+    }
+}
+
+/// Computes the checksum of an ICMP (IPv4) message
+///
+/// The existing checksum field is treated as zero.
+///
+/// # Arguments
+/// * `msg` - ICMP header and payload
+pub fn icmp(msg: &[u8]) -> u16 {
+    skip_field(0, msg, 2)
+}
+
+/// Computes the checksum of an ICMPv6 message
+///
+/// The existing checksum field is treated as zero.
+///
+/// # Arguments
+/// * `pseudo` - Pseudo-header sum (see [`ipv6_pseudo_header`])
+/// * `msg` - ICMPv6 header and payload
+pub fn icmpv6(pseudo: u32, msg: &[u8]) -> u16 {
+    skip_field(pseudo, msg, 2)
+}
+
+/// Incrementally updates a checksum after replacing `old` with `new` (RFC 1624, eqn. 3)
+///
+/// Both slices must have the same, even, length and be aligned on a 16-bit boundary
+/// relative to the start of the checksummed data.
+///
+/// # Arguments
+/// * `checksum` - Checksum currently stored in the packet
+/// * `old` - Bytes being replaced
+/// * `new` - Replacement bytes
+pub fn update(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    debug_assert_eq!(old.len(), new.len(), "replacement length mismatch");
+    debug_assert_eq!(old.len() % 2, 0, "replacement must be 16-bit aligned");
+
+    let mut sum = !checksum as u64;
+    for (old, new) in old.chunks_exact(2).zip(new.chunks_exact(2)) {
+        sum += !u16::from_be_bytes([old[0], old[1]]) as u64;
+        sum += u16::from_be_bytes([new[0], new[1]]) as u64;
+    }
+
+    !fold(sum)
+}
+
+/// Incrementally updates a checksum after replacing the 16-bit word `old` with `new`
+///
+/// # Arguments
+/// * `checksum` - Checksum currently stored in the packet
+/// * `old` - Word being replaced
+/// * `new` - Replacement word
+pub fn update_u16(checksum: u16, old: u16, new: u16) -> u16 {
+    update(checksum, &old.to_be_bytes(), &new.to_be_bytes())
+}
+
+/// Recomputes every checksum in an IP packet
+///
+/// Updates the IPv4 header checksum (if IPv4) and the TCP, UDP, ICMP or ICMPv6 checksum
+/// of the payload. Fragments other than the first are left untouched beyond the IPv4
+/// header, as the transport checksum covers the reassembled payload.
+///
+/// # Arguments
+/// * `packet` - IPv4 or IPv6 packet (without packet info)
+///
+/// # Errors
+/// * `packet` is not a well-formed IPv4 or IPv6 packet
+pub fn fill(packet: &mut [u8]) -> Result<(), TunError> {
+    let layout = Layout::parse(packet)?;
+
+    if layout.version == 4 {
+        let csum = ipv4_header(&packet[..layout.header_len]);
+        packet[IPV4_CHECKSUM_OFFSET..IPV4_CHECKSUM_OFFSET + 2].copy_from_slice(&csum.to_be_bytes());
+    }
+
+    let offset = match layout.transport_checksum() {
+        Some(offset) => offset,
+        None => return Ok(()),
+    };
+
+    let pseudo = layout.pseudo_header(packet);
+    let payload = &packet[layout.header_len..layout.total_len];
+    let csum = match layout.proto {
+        PROTO_TCP => tcp(pseudo, payload),
+        PROTO_UDP => udp(pseudo, payload),
+        PROTO_ICMP => icmp(payload),
+        PROTO_ICMPV6 => icmpv6(pseudo, payload),
+        _ => return Ok(()),
+    };
+
+    packet[offset..offset + 2].copy_from_slice(&csum.to_be_bytes());
+    Ok(())
+}
+
+/// Rewrites the source address of an IP packet, incrementally updating checksums
+///
+/// # Arguments
+/// * `packet` - IPv4 or IPv6 packet (without packet info)
+/// * `ip` - New source address (must match the packet's IP version)
+///
+/// # Errors
+/// * `packet` is malformed or `ip` does not match the packet's IP version
+pub fn set_source(packet: &mut [u8], ip: IpAddr) -> Result<(), TunError> {
+    let layout = Layout::parse(packet)?;
+    let (start, end) = layout.source();
+    rewrite_addr(packet, &layout, start..end, ip)
+}
+
+/// Rewrites the destination address of an IP packet, incrementally updating checksums
+///
+/// # Arguments
+/// * `packet` - IPv4 or IPv6 packet (without packet info)
+/// * `ip` - New destination address (must match the packet's IP version)
+///
+/// # Errors
+/// * `packet` is malformed or `ip` does not match the packet's IP version
+pub fn set_destination(packet: &mut [u8], ip: IpAddr) -> Result<(), TunError> {
+    let layout = Layout::parse(packet)?;
+    let (start, end) = layout.destination();
+    rewrite_addr(packet, &layout, start..end, ip)
+}
+
+/// Rewrites the TCP/UDP source port of an IP packet, incrementally updating checksums
+///
+/// # Arguments
+/// * `packet` - IPv4 or IPv6 packet (without packet info)
+/// * `port` - New source port
+///
+/// # Errors
+/// * `packet` is malformed or does not contain a TCP/UDP header
+pub fn set_source_port(packet: &mut [u8], port: u16) -> Result<(), TunError> {
+    rewrite_port(packet, 0, port)
+}
+
+/// Rewrites the TCP/UDP destination port of an IP packet, incrementally updating checksums
+///
+/// # Arguments
+/// * `packet` - IPv4 or IPv6 packet (without packet info)
+/// * `port` - New destination port
+///
+/// # Errors
+/// * `packet` is malformed or does not contain a TCP/UDP header
+pub fn set_destination_port(packet: &mut [u8], port: u16) -> Result<(), TunError> {
+    rewrite_port(packet, 2, port)
+}
+
+/// Swaps the source and destination addresses (and ports, if TCP/UDP) of an IP packet
+///
+/// The one's complement sum is commutative, so no checksum changes are required.
+///
+/// # Arguments
+/// * `packet` - IPv4 or IPv6 packet (without packet info)
+///
+/// # Errors
+/// * `packet` is malformed
+pub fn swap_endpoints(packet: &mut [u8]) -> Result<(), TunError> {
+    let layout = Layout::parse(packet)?;
+    let (src, _) = layout.source();
+    let (dst, end) = layout.destination();
+    let len = end - dst;
+    for i in 0..len {
+        packet.swap(src + i, dst + i);
+    }
+
+    if let Some(offset) = layout.ports() {
+        for i in 0..2 {
+            packet.swap(offset + i, offset + 2 + i);
+        }
+    }
+
+    Ok(())
+}
+
+/// Computes the checksum over `data`, treating the 16-bit field at `offset` as zero
+fn skip_field(initial: u32, data: &[u8], offset: usize) -> u16 {
+    if data.len() < offset + 2 {
+        return finish(sum(data, initial));
+    }
+
+    let sum = self::sum(&data[..offset], initial);
+    finish(self::sum(&data[offset + 2..], sum))
+}
+
+/// Folds carries in a running sum back into the low 16 bits
+fn fold(mut sum: u64) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// Replaces a 16-bit checksum stored at `offset` after `old` was replaced by `new`
+fn patch(packet: &mut [u8], offset: usize, old: &[u8], new: &[u8]) {
+    let csum = u16::from_be_bytes([packet[offset], packet[offset + 1]]);
+    let csum = update(csum, old, new);
+    packet[offset..offset + 2].copy_from_slice(&csum.to_be_bytes());
+}
+
+fn rewrite_addr(
+    packet: &mut [u8],
+    layout: &Layout,
+    range: std::ops::Range<usize>,
+    ip: IpAddr,
+) -> Result<(), TunError> {
+    let new = match (layout.version, ip) {
+        (4, IpAddr::V4(ip)) => ip.octets().to_vec(),
+        (6, IpAddr::V6(ip)) => ip.octets().to_vec(),
+        _ => return Err(TunError::InvalidPacket("address family mismatch")),
+    };
+    let old = packet[range.clone()].to_vec();
+
+    if layout.version == 4 {
+        patch(packet, IPV4_CHECKSUM_OFFSET, &old, &new);
+    }
+
+    if let Some(offset) = layout.transport_checksum() {
+        let zero_udp = layout.proto == PROTO_UDP && packet[offset..offset + 2] == [0, 0];
+        if layout.proto != PROTO_ICMP && !zero_udp {
+            patch(packet, offset, &old, &new);
+            layout.fix_udp_zero(packet);
+        }
+    }
+
+    packet[range].copy_from_slice(&new);
+    Ok(())
+}
+
+fn rewrite_port(packet: &mut [u8], field: usize, port: u16) -> Result<(), TunError> {
+    let layout = Layout::parse(packet)?;
+    let offset = layout.ports().ok_or(TunError::InvalidPacket(
+        "packet does not contain a tcp/udp header",
+    ))? + field;
+
+    let old = [packet[offset], packet[offset + 1]];
+    let new = port.to_be_bytes();
+
+    if let Some(csum) = layout.transport_checksum() {
+        if !(layout.proto == PROTO_UDP && packet[csum..csum + 2] == [0, 0]) {
+            patch(packet, csum, &old, &new);
+            layout.fix_udp_zero(packet);
+        }
+    }
+
+    packet[offset..offset + 2].copy_from_slice(&new);
+    Ok(())
+}
+
+/// Location of the fields within an IP packet needed to compute checksums
+#[derive(Debug)]
+struct Layout {
+    /// IP version (4 or 6)
+    version: u8,
+
+    /// Length of the IP header (IPv6 extension headers are not followed)
+    header_len: usize,
+
+    /// Total length of the packet, as reported by the IP header
+    total_len: usize,
+
+    /// Upper-layer protocol number
+    proto: u8,
+
+    /// True if the transport header is present (i.e., not a trailing fragment)
+    has_transport: bool,
+}
+
+impl Layout {
+    fn parse(packet: &[u8]) -> Result<Self, TunError> {
+        let version = packet.first().ok_or(TunError::NotEnoughData)? >> 4;
+        let layout = match version {
+            4 => {
+                if packet.len() < 20 {
+                    return Err(TunError::NotEnoughData);
+                }
+
+                let header_len = ((packet[0] & 0x0F) as usize) * 4;
+                let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+                let frag_offset = u16::from_be_bytes([packet[6], packet[7]]) & 0x1FFF;
+                if header_len < 20 || total_len < header_len {
+                    return Err(TunError::InvalidPacket("invalid ipv4 header length"));
+                }
+
+                Self {
+                    version,
+                    header_len,
+                    total_len,
+                    proto: packet[9],
+                    has_transport: frag_offset == 0,
+                }
+            }
+            6 => {
+                if packet.len() < IPV6_HEADER_LEN {
+                    return Err(TunError::NotEnoughData);
+                }
+
+                let payload_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+                Self {
+                    version,
+                    header_len: IPV6_HEADER_LEN,
+                    total_len: IPV6_HEADER_LEN + payload_len,
+                    proto: packet[6],
+                    has_transport: true,
+                }
+            }
+            _ => return Err(TunError::InvalidPacket("unknown ip version")),
+        };
+
+        if packet.len() < layout.total_len {
+            return Err(TunError::NotEnoughData);
+        }
+
+        Ok(layout)
+    }
+
+    fn source(&self) -> (usize, usize) {
+        match self.version {
+            4 => (12, 16),
+            _ => (8, 24),
+        }
+    }
+
+    fn destination(&self) -> (usize, usize) {
+        match self.version {
+            4 => (16, 20),
+            _ => (24, 40),
+        }
+    }
+
+    /// Offset of the source port, if the packet carries a complete TCP/UDP header
+    fn ports(&self) -> Option<usize> {
+        let min_len = match self.proto {
+            PROTO_TCP => 20,
+            PROTO_UDP => 8,
+            _ => return None,
+        };
+
+        match self.has_transport && self.total_len >= self.header_len + min_len {
+            true => Some(self.header_len),
+            false => None,
+        }
+    }
+
+    /// Offset of the upper-layer checksum field, if present in this packet
+    fn transport_checksum(&self) -> Option<usize> {
+        let (offset, min_len) = match (self.version, self.proto) {
+            (_, PROTO_TCP) => (16, 20),
+            (_, PROTO_UDP) => (6, 8),
+            (4, PROTO_ICMP) => (2, 4),
+            (6, PROTO_ICMPV6) => (2, 4),
+            _ => return None,
+        };
+
+        match self.has_transport && self.total_len >= self.header_len + min_len {
+            true => Some(self.header_len + offset),
+            false => None,
+        }
+    }
+
+    fn pseudo_header(&self, packet: &[u8]) -> u32 {
+        let len = self.total_len - self.header_len;
+        match self.version {
+            4 => {
+                let src = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+                let dst = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+                ipv4_pseudo_header(src, dst, self.proto, len as u16)
+            }
+            _ => {
+                let mut src = [0u8; 16];
+                let mut dst = [0u8; 16];
+                src.copy_from_slice(&packet[8..24]);
+                dst.copy_from_slice(&packet[24..40]);
+                ipv6_pseudo_header(&src.into(), &dst.into(), self.proto, len as u32)
+            }
+        }
+    }
+
+    /// A UDP checksum of zero means "no checksum", so transmit all ones instead
+    fn fix_udp_zero(&self, packet: &mut [u8]) {
+        if self.proto != PROTO_UDP {
+            return;
+        }
+
+        if let Some(offset) = self.transport_checksum() {
+            if packet[offset..offset + 2] == [0, 0] {
+                packet[offset..offset + 2].copy_from_slice(&[0xFF, 0xFF]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IPv4/UDP packet: 192.168.80.1:5000 -> 192.168.80.100:7, payload "ping"
+    fn udp4_packet() -> Vec<u8> {
+        let mut pkt = vec![
+            0x45, 0x00, 0x00, 0x20, 0x12, 0x34, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 192, 168, 80,
+            1, 192, 168, 80, 100, 0x13, 0x88, 0x00, 0x07, 0x00, 0x0c, 0x00, 0x00, b'p', b'i', b'n',
+            b'g',
+        ];
+        fill(&mut pkt).expect("failed to fill checksums");
+        pkt
+    }
+
+    /// IPv6/TCP SYN packet: fd00::1:40000 -> fd00::2:80
+    fn tcp6_packet() -> Vec<u8> {
+        let mut pkt = vec![0u8; 60];
+        pkt[0] = 0x60;
+        pkt[4..6].copy_from_slice(&20u16.to_be_bytes());
+        pkt[6] = PROTO_TCP;
+        pkt[7] = 64;
+        pkt[8..24].copy_from_slice(&"fd00::1".parse::<Ipv6Addr>().unwrap().octets());
+        pkt[24..40].copy_from_slice(&"fd00::2".parse::<Ipv6Addr>().unwrap().octets());
+        pkt[40..42].copy_from_slice(&40000u16.to_be_bytes());
+        pkt[42..44].copy_from_slice(&80u16.to_be_bytes());
+        pkt[44..48].copy_from_slice(&0xdeadbeefu32.to_be_bytes());
+        pkt[52] = 0x50;
+        pkt[53] = 0x02;
+        pkt[54..56].copy_from_slice(&64240u16.to_be_bytes());
+        fill(&mut pkt).expect("failed to fill checksums");
+        pkt
+    }
+
+    #[test]
+    fn rfc1071_example() {
+        // example from RFC 1071 section 3
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(sum(&data, 0), 0xddf2);
+        assert_eq!(compute(&data), !0xddf2);
+    }
+
+    #[test]
+    fn ipv4_header_verifies() {
+        let pkt = udp4_packet();
+        assert_eq!(compute(&pkt[..20]), 0);
+        assert_eq!(ipv4_header(&pkt[..20]).to_be_bytes(), pkt[10..12]);
+    }
+
+    #[test]
+    fn incremental_matches_full() {
+        let mut pkt = udp4_packet();
+        set_source(&mut pkt, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))).unwrap();
+        set_destination_port(&mut pkt, 53).unwrap();
+
+        let mut expected = pkt.clone();
+        fill(&mut expected).unwrap();
+        assert_eq!(pkt, expected);
+
+        let mut pkt = tcp6_packet();
+        set_destination(&mut pkt, "fd00::ffff".parse().unwrap()).unwrap();
+        set_source_port(&mut pkt, 1234).unwrap();
+
+        let mut expected = pkt.clone();
+        fill(&mut expected).unwrap();
+        assert_eq!(pkt, expected);
+    }
+
+    #[test]
+    fn swap_preserves_checksums() {
+        let orig = udp4_packet();
+        let mut pkt = orig.clone();
+        swap_endpoints(&mut pkt).unwrap();
+        assert_eq!(pkt[12..16], orig[16..20]);
+        assert_eq!(pkt[20..22], orig[22..24]);
+
+        let mut expected = pkt.clone();
+        fill(&mut expected).unwrap();
+        assert_eq!(pkt, expected);
+    }
+
+    #[test]
+    fn address_family_mismatch() {
+        let mut pkt = udp4_packet();
+        let res = set_source(&mut pkt, "fd00::1".parse().unwrap());
+        assert!(matches!(res, Err(TunError::InvalidPacket(_))));
+    }
+}
@@ -0,0 +1,163 @@
+//! IP addresses with a prefix length in CIDR notation (e.g., `10.0.0.1/24`)
+
+use crate::TunError;
+use std::{fmt, net::IpAddr, str::FromStr};
+
+/// An IP address and prefix length, written as `10.0.0.1/24` or `fd00::1/64`
+///
+/// The address may have host bits set, it is the address assigned to an interface rather
+/// than the network address. When parsed without a prefix length the address is a single
+/// host (`/32` or `/128`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpCidr {
+    /// Creates a new address and prefix length pair
+    ///
+    /// # Arguments
+    /// * `addr` - IPv4 or IPv6 address
+    /// * `prefix` - Prefix length, at most 32 for IPv4 and 128 for IPv6
+    ///
+    /// # Errors
+    /// * `InvalidAddress` if the prefix length is too long for the address family
+    pub fn new(addr: impl Into<IpAddr>, prefix: u8) -> Result<Self, TunError> {
+        let addr = addr.into();
+        if prefix > max_prefix(addr) {
+            return Err(TunError::InvalidAddress {
+                value: format!("{addr}/{prefix}"),
+                reason: match addr {
+                    IpAddr::V4(_) => "prefix length exceeds 32 for IPv4",
+                    IpAddr::V6(_) => "prefix length exceeds 128 for IPv6",
+                },
+            });
+        }
+
+        Ok(Self { addr, prefix })
+    }
+
+    /// Returns the address
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Returns the prefix length
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+}
+
+/// Longest prefix length for the family of `addr`
+fn max_prefix(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = TunError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| TunError::InvalidAddress {
+            value: s.to_owned(),
+            reason,
+        };
+
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| invalid("expected an IPv4 or IPv6 address"))?;
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse()
+                .map_err(|_| invalid("prefix length must be a number"))?,
+            None => max_prefix(addr),
+        };
+
+        Self::new(addr, prefix).map_err(|e| match e {
+            TunError::InvalidAddress { reason, .. } => invalid(reason),
+            e => e,
+        })
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl From<IpCidr> for (IpAddr, u8) {
+    fn from(cidr: IpCidr) -> Self {
+        (cidr.addr, cidr.prefix)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for IpCidr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for IpCidr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn parse_cidr() {
+        let cidr: IpCidr = "10.0.0.1/24".parse().unwrap();
+        assert_eq!(cidr.addr(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(cidr.prefix(), 24);
+        assert_eq!(cidr.to_string(), "10.0.0.1/24");
+
+        let cidr: IpCidr = "fd00::1/64".parse().unwrap();
+        assert_eq!(
+            cidr.addr(),
+            IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1))
+        );
+        assert_eq!(cidr.prefix(), 64);
+
+        // bare addresses are single hosts
+        assert_eq!("10.0.0.1".parse::<IpCidr>().unwrap().prefix(), 32);
+        assert_eq!("::1".parse::<IpCidr>().unwrap().prefix(), 128);
+
+        for (value, reason) in [
+            ("10.0.0.1/33", "prefix length exceeds 32 for IPv4"),
+            ("fd00::1/129", "prefix length exceeds 128 for IPv6"),
+            ("10.0.0.1/", "prefix length must be a number"),
+            ("10.0.0.1/-1", "prefix length must be a number"),
+            ("10.0.0/24", "expected an IPv4 or IPv6 address"),
+            ("", "expected an IPv4 or IPv6 address"),
+        ] {
+            match value.parse::<IpCidr>() {
+                Err(TunError::InvalidAddress {
+                    value: v,
+                    reason: r,
+                }) => {
+                    assert_eq!((v.as_str(), r), (value, reason))
+                }
+                other => panic!("expected InvalidAddress for {value:?}, got {other:?}"),
+            }
+        }
+    }
+}
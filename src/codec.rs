@@ -0,0 +1,361 @@
+//! Framing for carrying packets over stream transports (TCP, Unix sockets, ...)
+//!
+//! Stream transports don't preserve packet boundaries, so each packet is prefixed with a
+//! small header containing its length and packet information:
+//!
+//! `| length (2) | flags (2) | protocol (2) | packet |`
+//!
+//! All header fields are in network byte order. The length covers only the packet.
+//!
+//! [`read_frame`] / [`write_frame`] work with any blocking stream, [`forward`] bridges a tun
+//! device to a stream, and `PacketCodec` (with the `tokio-codec` feature) implements
+//! `tokio_util::codec` for async transports.
+
+use crate::{DynTun, PacketInfo, TunError};
+use std::{
+    io::{self, Read, Write},
+    os::unix::io::AsRawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Size of the header prepended to each packet
+pub const FRAME_HEADER_LEN: usize = 6;
+
+/// Size of the largest packet that can be framed
+pub const MAX_FRAME_LEN: usize = u16::MAX as usize;
+
+/// How often the [`forward`] threads wake up to check whether the other direction stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn encode_header(len: usize, pi: PacketInfo) -> Result<[u8; FRAME_HEADER_LEN], TunError> {
+    let len =
+        u16::try_from(len).map_err(|_| TunError::InvalidPacket("packet too large to frame"))?;
+
+    let mut hdr = [0u8; FRAME_HEADER_LEN];
+    hdr[0..2].copy_from_slice(&len.to_be_bytes());
+    hdr[2..4].copy_from_slice(&pi.flags.to_be_bytes());
+    hdr[4..6].copy_from_slice(&pi.protocol.to_be_bytes());
+    Ok(hdr)
+}
+
+fn decode_header(hdr: &[u8]) -> (usize, PacketInfo) {
+    let len = u16::from_be_bytes([hdr[0], hdr[1]]) as usize;
+    let pi = PacketInfo {
+        flags: u16::from_be_bytes([hdr[2], hdr[3]]),
+        protocol: u16::from_be_bytes([hdr[4], hdr[5]]),
+    };
+    (len, pi)
+}
+
+/// Writes a single framed packet to a stream
+///
+/// # Arguments
+/// * `writer` - Stream to write to
+/// * `pkt` - Packet to write (at most [`MAX_FRAME_LEN`] bytes)
+/// * `pi` - Packet information to send with the packet
+///
+/// # Errors
+/// * `InvalidPacket` if the packet is too large to frame
+/// * I/O if writing to the stream fails
+pub fn write_frame<W: Write>(mut writer: W, pkt: &[u8], pi: PacketInfo) -> Result<(), TunError> {
+    let hdr = encode_header(pkt.len(), pi)?;
+
+    // single write so the header and packet aren't sent in separate segments
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + pkt.len());
+    frame.extend_from_slice(&hdr);
+    frame.extend_from_slice(pkt);
+    writer.write_all(&frame)?;
+    Ok(())
+}
+
+/// Reads a single framed packet from a stream
+///
+/// Returns `None` if the stream ended cleanly (at a frame boundary).
+///
+/// # Arguments
+/// * `reader` - Stream to read from
+/// * `buf` - Buffer to read the packet into
+///
+/// # Errors
+/// * `BufferTooSmall` if the packet doesn't fit in `buf` (the packet is discarded)
+/// * I/O if reading from the stream fails or the stream ends in the middle of a frame
+pub fn read_frame<R: Read>(
+    mut reader: R,
+    buf: &mut [u8],
+) -> Result<Option<(usize, PacketInfo)>, TunError> {
+    let mut hdr = [0u8; FRAME_HEADER_LEN];
+    let mut filled = 0;
+    while filled < FRAME_HEADER_LEN {
+        match reader.read(&mut hdr[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let (len, pi) = decode_header(&hdr);
+    if len > buf.len() {
+        // keep the stream in sync with the frame boundaries
+        io::copy(&mut reader.by_ref().take(len as u64), &mut io::sink())?;
+        return Err(TunError::BufferTooSmall);
+    }
+
+    reader.read_exact(&mut buf[..len])?;
+    Ok(Some((len, pi)))
+}
+
+/// Forwards packets between a tun device and a stream until either direction stops
+///
+/// Packets read from `tun` are framed and written to `writer`, frames read from `reader` are
+/// written to `tun`. Each direction runs on its own thread. Once the stream ends or either
+/// direction fails, the other direction is stopped (within a fraction of a second) and both
+/// threads are joined before returning, so `tun` can be reused right away (e.g., for the next
+/// connection).
+///
+/// For sockets, `reader` and `writer` are usually the same socket (e.g., using
+/// `TcpStream::try_clone`).
+///
+/// # Arguments
+/// * `tun` - Device to forward packets from / to
+/// * `reader` - Read half of the stream
+/// * `writer` - Write half of the stream
+///
+/// # Errors
+/// * I/O if reading from / writing to the device or stream fails
+pub fn forward<T, R, W>(tun: T, mut reader: R, mut writer: W) -> Result<(), TunError>
+where
+    T: DynTun + Send + Sync + 'static,
+    R: Read + AsRawFd + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let tun = Arc::new(tun);
+    let stop = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel::<Result<(), TunError>>();
+
+    let outbound = thread::Builder::new().name("codec-tx".into()).spawn({
+        let tun = Arc::clone(&tun);
+        let stop = Arc::clone(&stop);
+        let tx = tx.clone();
+        move || {
+            let mut buf = vec![0u8; MAX_FRAME_LEN];
+            let res = loop {
+                let res = match tun.poll_readable(POLL_INTERVAL) {
+                    _ if stop.load(Ordering::Relaxed) => break Ok(()),
+                    Ok(false) => continue,
+                    Ok(true) => tun
+                        .read_packet(&mut buf)
+                        .and_then(|(n, pi)| write_frame(&mut writer, &buf[..n], pi))
+                        .and_then(|_| writer.flush().map_err(TunError::from)),
+                    Err(error) => Err(error),
+                };
+                if let Err(error) = res {
+                    tracing::debug!(?error, "stopped forwarding packets to stream");
+                    break Err(error);
+                }
+            };
+            let _ = tx.send(res);
+        }
+    })?;
+
+    let inbound = thread::Builder::new().name("codec-rx".into()).spawn({
+        let stop = Arc::clone(&stop);
+        move || {
+            let mut buf = vec![0u8; MAX_FRAME_LEN];
+            let res = loop {
+                match crate::poll_fd(reader.as_raw_fd(), Some(POLL_INTERVAL)) {
+                    _ if stop.load(Ordering::Relaxed) => break Ok(()),
+                    Ok(false) => continue,
+                    Ok(true) => (),
+                    Err(error) => break Err(error.into()),
+                }
+
+                match read_frame(&mut reader, &mut buf) {
+                    Ok(Some((0, _))) => (),
+                    Ok(Some((n, pi))) => {
+                        if let Err(error) = tun.write_packet(&buf[..n], pi) {
+                            break Err(error.into());
+                        }
+                    }
+                    Ok(None) => break Ok(()),
+                    Err(error) => break Err(error),
+                }
+            };
+            let _ = tx.send(res);
+        }
+    });
+    let inbound = match inbound {
+        Ok(inbound) => inbound,
+        Err(error) => {
+            stop.store(true, Ordering::Relaxed);
+            let _ = outbound.join();
+            return Err(error.into());
+        }
+    };
+
+    // first direction to stop determines the result
+    let res = rx
+        .recv()
+        .unwrap_or_else(|_| Err(io::Error::other("forwarding thread panicked").into()));
+
+    stop.store(true, Ordering::Relaxed);
+    for thread in [outbound, inbound] {
+        let _ = thread.join();
+    }
+    res
+}
+
+#[cfg(feature = "tokio-codec")]
+mod tokio_codec {
+    use super::{decode_header, encode_header, FRAME_HEADER_LEN};
+    use crate::PacketInfo;
+    use bytes::{BufMut, BytesMut};
+    use std::io;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    /// `tokio_util` codec for framed packets
+    ///
+    /// Decodes to `(packet, packet info)` and encodes anything that can be viewed as bytes
+    /// along with its packet info.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct PacketCodec;
+
+    impl Decoder for PacketCodec {
+        type Item = (BytesMut, PacketInfo);
+        type Error = io::Error;
+
+        fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+            if src.len() < FRAME_HEADER_LEN {
+                src.reserve(FRAME_HEADER_LEN - src.len());
+                return Ok(None);
+            }
+
+            let (len, pi) = decode_header(&src[..FRAME_HEADER_LEN]);
+            if src.len() < FRAME_HEADER_LEN + len {
+                src.reserve(FRAME_HEADER_LEN + len - src.len());
+                return Ok(None);
+            }
+
+            let _ = src.split_to(FRAME_HEADER_LEN);
+            Ok(Some((src.split_to(len), pi)))
+        }
+    }
+
+    impl<B: AsRef<[u8]>> Encoder<(B, PacketInfo)> for PacketCodec {
+        type Error = io::Error;
+
+        fn encode(&mut self, item: (B, PacketInfo), dst: &mut BytesMut) -> io::Result<()> {
+            let (pkt, pi) = item;
+            let pkt = pkt.as_ref();
+            let hdr = encode_header(pkt.len(), pi)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error.to_string()))?;
+
+            dst.reserve(FRAME_HEADER_LEN + pkt.len());
+            dst.put_slice(&hdr);
+            dst.put_slice(pkt);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "tokio-codec")]
+pub use self::tokio_codec::PacketCodec;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V4: PacketInfo = PacketInfo {
+        flags: 0,
+        protocol: 0x0800,
+    };
+
+    #[test]
+    fn frame_roundtrip() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &[0x45, 1, 2], V4).unwrap();
+        write_frame(&mut stream, &[0x45; 40], V4).unwrap();
+        write_frame(&mut stream, &[0x60], PacketInfo::default()).unwrap();
+        assert_eq!(stream[..FRAME_HEADER_LEN], [0, 3, 0, 0, 0x08, 0x00]);
+
+        let mut reader = &stream[..];
+        let mut buf = [0u8; 16];
+        assert_eq!(read_frame(&mut reader, &mut buf).unwrap(), Some((3, V4)));
+        assert_eq!(buf[..3], [0x45, 1, 2]);
+
+        // oversized packets are skipped without losing the frame boundary
+        assert!(matches!(
+            read_frame(&mut reader, &mut buf),
+            Err(TunError::BufferTooSmall)
+        ));
+        assert_eq!(
+            read_frame(&mut reader, &mut buf).unwrap(),
+            Some((1, PacketInfo::default()))
+        );
+        assert_eq!(read_frame(&mut reader, &mut buf).unwrap(), None);
+
+        // stream ending mid-frame is an error
+        assert!(read_frame(&stream[..4], &mut buf).is_err());
+        assert!(write_frame(Vec::new(), &vec![0; MAX_FRAME_LEN + 1], V4).is_err());
+    }
+
+    #[cfg(feature = "channel")]
+    #[test]
+    fn forward_over_unix_socket() {
+        use crate::{ChannelTun, TunConfig};
+        use std::os::unix::net::UnixStream;
+
+        let (app, tun) = ChannelTun::create("codec0", TunConfig::default()).unwrap();
+        let (local, mut remote) = UnixStream::pair().unwrap();
+        let reader = local.try_clone().unwrap();
+        let handle = thread::spawn(move || forward(tun, reader, local).map_err(|e| e.to_string()));
+
+        // device -> stream (channel devices don't report packet info)
+        DynTun::write_packet(&app, &[0x45, 7], PacketInfo::default()).unwrap();
+        let mut buf = [0u8; 64];
+        assert_eq!(
+            read_frame(&mut remote, &mut buf).unwrap(),
+            Some((2, PacketInfo::default()))
+        );
+        assert_eq!(buf[..2], [0x45, 7]);
+
+        // stream -> device
+        write_frame(&mut remote, &[0x60, 8], PacketInfo::default()).unwrap();
+        let (n, _) = DynTun::read_packet(&app, &mut buf).unwrap();
+        assert_eq!(buf[..n], [0x60, 8]);
+
+        // closing the stream stops both directions and releases the device
+        drop(remote);
+        handle.join().unwrap().unwrap();
+        assert!(DynTun::read_packet(&app, &mut buf).is_err());
+    }
+
+    #[cfg(feature = "tokio-codec")]
+    #[test]
+    fn tokio_codec() {
+        use bytes::BytesMut;
+        use tokio_util::codec::{Decoder, Encoder};
+
+        let mut codec = PacketCodec;
+        let mut buf = BytesMut::new();
+        codec.encode((&[0x45, 1, 2][..], V4), &mut buf).unwrap();
+        codec.encode((vec![0x60], V4), &mut buf).unwrap();
+
+        // partial frames aren't decoded until complete
+        let mut partial = buf.split_to(4);
+        assert_eq!(codec.decode(&mut partial).unwrap(), None);
+        partial.unsplit(buf);
+
+        let (pkt, pi) = codec.decode(&mut partial).unwrap().unwrap();
+        assert_eq!((&pkt[..], pi), (&[0x45, 1, 2][..], V4));
+        let (pkt, _) = codec.decode(&mut partial).unwrap().unwrap();
+        assert_eq!(&pkt[..], [0x60]);
+        assert_eq!(codec.decode(&mut partial).unwrap(), None);
+    }
+}
@@ -0,0 +1,269 @@
+//! (De)serialization of `TunConfig` for the `serde` feature
+//!
+//! Configs are (de)serialized through a flat representation that uses CIDR strings for
+//! addresses and whole seconds for lifetimes, e.g. in TOML:
+//!
+//! ```toml
+//! name = "vpn%d"
+//! ip = "10.0.0.1/24"
+//! mtu = 1420
+//! up = true
+//! routes = ["10.1.0.0/16", "fd01::/64"]
+//! ```
+
+use crate::{cidr::IpCidr, AddrGenMode, Ipv6AddrOptions, RpFilter, TunConfig, TunError};
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, time::Duration};
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TunConfigRepr {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<String>,
+
+    packet_info: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtu: Option<u32>,
+
+    up: bool,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    routes: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    addr_gen_mode: Option<AddrGenMode>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disable_ipv6: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    accept_ra: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rp_filter: Option<RpFilter>,
+
+    #[serde(skip_serializing_if = "Ipv6Repr::is_default")]
+    ipv6: Ipv6Repr,
+}
+
+#[derive(Default, Deserialize, Serialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+struct Ipv6Repr {
+    nodad: bool,
+    noprefixroute: bool,
+    managetempaddr: bool,
+
+    /// Valid lifetime in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    valid_lifetime: Option<u64>,
+
+    /// Preferred lifetime in seconds, defaults to the valid lifetime
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preferred_lifetime: Option<u64>,
+}
+
+impl Ipv6Repr {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Wraps an error with the name of the config field that caused it
+fn field(field: impl Into<String>) -> impl FnOnce(TunError) -> TunError {
+    let field = field.into();
+    move |e| e.in_field(field)
+}
+
+impl TryFrom<TunConfigRepr> for TunConfig {
+    type Error = TunError;
+
+    fn try_from(repr: TunConfigRepr) -> Result<Self, Self::Error> {
+        let ip = repr
+            .ip
+            .map(|ip| ip.parse::<IpCidr>())
+            .transpose()
+            .map_err(field("ip"))?;
+
+        let peer = repr
+            .peer
+            .map(|peer| {
+                peer.trim()
+                    .parse::<IpAddr>()
+                    .map_err(|_| TunError::InvalidAddress {
+                        value: peer,
+                        reason: "expected an IPv4 or IPv6 address",
+                    })
+            })
+            .transpose()
+            .map_err(field("peer"))?;
+
+        let routes = repr
+            .routes
+            .iter()
+            .enumerate()
+            .map(|(i, route)| {
+                route
+                    .parse::<IpCidr>()
+                    .map(Into::into)
+                    .map_err(field(format!("routes[{i}]")))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let lifetimes = match (repr.ipv6.valid_lifetime, repr.ipv6.preferred_lifetime) {
+            (Some(valid), preferred) => Some((
+                Duration::from_secs(valid),
+                Duration::from_secs(preferred.unwrap_or(valid).min(valid)),
+            )),
+            (None, Some(_)) => {
+                return Err(field("ipv6.preferred_lifetime")(TunError::InvalidConfig(
+                    "requires `valid_lifetime` to be set",
+                )))
+            }
+            (None, None) => None,
+        };
+
+        Ok(TunConfig {
+            ip: ip.map(Into::into),
+            peer,
+            name: repr.name,
+            packet_info: repr.packet_info,
+            ipv6: Ipv6AddrOptions {
+                nodad: repr.ipv6.nodad,
+                noprefixroute: repr.ipv6.noprefixroute,
+                managetempaddr: repr.ipv6.managetempaddr,
+                lifetimes,
+            },
+            addr_gen_mode: repr.addr_gen_mode,
+            disable_ipv6: repr.disable_ipv6,
+            accept_ra: repr.accept_ra,
+            rp_filter: repr.rp_filter,
+            mtu: repr.mtu,
+            routes,
+            up: repr.up,
+        })
+    }
+}
+
+impl From<TunConfig> for TunConfigRepr {
+    fn from(cfg: TunConfig) -> Self {
+        let cidr = |(addr, prefix): (IpAddr, u8)| format!("{addr}/{prefix}");
+
+        TunConfigRepr {
+            name: cfg.name,
+            ip: cfg.ip.map(cidr),
+            peer: cfg.peer.map(|peer| peer.to_string()),
+            packet_info: cfg.packet_info,
+            mtu: cfg.mtu,
+            up: cfg.up,
+            routes: cfg.routes.into_iter().map(cidr).collect(),
+            addr_gen_mode: cfg.addr_gen_mode,
+            disable_ipv6: cfg.disable_ipv6,
+            accept_ra: cfg.accept_ra,
+            rp_filter: cfg.rp_filter,
+            ipv6: Ipv6Repr {
+                nodad: cfg.ipv6.nodad,
+                noprefixroute: cfg.ipv6.noprefixroute,
+                managetempaddr: cfg.ipv6.managetempaddr,
+                valid_lifetime: cfg.ipv6.lifetimes.map(|(valid, _)| valid.as_secs()),
+                preferred_lifetime: cfg.ipv6.lifetimes.map(|(_, preferred)| preferred.as_secs()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn deserialize_config() {
+        let cfg: TunConfig = serde_json::from_str(
+            r#"{
+                "name": "vpn%d",
+                "ip": "10.0.0.1/24",
+                "peer": "10.0.0.2",
+                "mtu": 1420,
+                "up": true,
+                "routes": ["10.1.0.0/16", "fd01::/64"],
+                "addr_gen_mode": "stable_privacy",
+                "rp_filter": "loose",
+                "ipv6": { "nodad": true, "valid_lifetime": 600 }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(cfg.name.as_deref(), Some("vpn%d"));
+        assert_eq!(cfg.ip, Some((Ipv4Addr::new(10, 0, 0, 1).into(), 24)));
+        assert_eq!(cfg.peer, Some(Ipv4Addr::new(10, 0, 0, 2).into()));
+        assert_eq!(cfg.mtu, Some(1420));
+        assert!(cfg.up);
+        assert!(!cfg.packet_info);
+        assert_eq!(
+            cfg.routes,
+            [
+                (Ipv4Addr::new(10, 1, 0, 0).into(), 16),
+                (Ipv6Addr::new(0xfd01, 0, 0, 0, 0, 0, 0, 0).into(), 64)
+            ]
+        );
+        assert_eq!(cfg.addr_gen_mode, Some(AddrGenMode::StablePrivacy));
+        assert_eq!(cfg.rp_filter, Some(RpFilter::Loose));
+        assert_eq!(
+            cfg.ipv6,
+            Ipv6AddrOptions::default()
+                .nodad(true)
+                .lifetimes(Duration::from_secs(600), Duration::from_secs(600))
+        );
+
+        // round trips through the same representation
+        let json = serde_json::to_string(&cfg).unwrap();
+        let again: TunConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&again).unwrap(), json);
+
+        // everything is optional
+        let cfg: TunConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(
+            serde_json::to_string(&cfg).unwrap(),
+            r#"{"packet_info":false,"up":false}"#
+        );
+    }
+
+    #[test]
+    fn errors_name_field() {
+        for (json, expected) in [
+            (
+                r#"{"ip": "10.0.0.1/33"}"#,
+                "invalid value for `ip`: invalid address \"10.0.0.1/33\": prefix length exceeds 32 for IPv4",
+            ),
+            (
+                r#"{"peer": "10.0.0.2/32"}"#,
+                "invalid value for `peer`: invalid address \"10.0.0.2/32\": expected an IPv4 or IPv6 address",
+            ),
+            (
+                r#"{"routes": ["10.1.0.0/16", "fd01::/200"]}"#,
+                "invalid value for `routes[1]`: invalid address \"fd01::/200\": prefix length exceeds 128 for IPv6",
+            ),
+            (
+                r#"{"ipv6": {"preferred_lifetime": 10}}"#,
+                "invalid value for `ipv6.preferred_lifetime`: invalid config: requires `valid_lifetime` to be set",
+            ),
+        ] {
+            let err = serde_json::from_str::<TunConfig>(json).unwrap_err();
+            assert!(
+                err.to_string().starts_with(expected),
+                "{json}: unexpected error {err}"
+            );
+        }
+
+        // typos are reported instead of silently ignored
+        let err = serde_json::from_str::<TunConfig>(r#"{"adress": "10.0.0.1/24"}"#).unwrap_err();
+        assert!(err.to_string().contains("unknown field `adress`"), "{err}");
+    }
+}
@@ -0,0 +1,602 @@
+//! Packet encryption
+//!
+//! [`EncryptedTun`] wraps a tun device so packets leave `read_packet` encrypted and are
+//! decrypted before they reach the wrapped device's `write_packet`. Placed in front of a
+//! forwarding loop (e.g., [`UdpTunnel`](crate::tunnel::UdpTunnel)), this keeps tunnel
+//! payloads confidential and authenticated.
+//!
+//! Encrypted packets have the following layout:
+//!
+//! `| key id (1) | counter (8) | ciphertext | tag (16) |`
+//!
+//! The key id and counter are authenticated (but not encrypted). The counter is unique per
+//! key and sender and, along with the sender's [`Role`], forms the nonce. Received counters
+//! are checked against a sliding [`ReplayWindow`] so duplicated packets are rejected.
+//!
+//! AEAD implementations (`ChaCha20Poly1305` and `Aes256Gcm`) are available with the
+//! `crypto` feature. Other ciphers can be used by implementing [`Cipher`].
+
+use crate::{DynTun, PacketInfo, Tun, TunError};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+/// Size of the nonce passed to a [`Cipher`]
+pub const NONCE_LEN: usize = 12;
+
+/// Size of the authentication tag produced by a [`Cipher`]
+pub const TAG_LEN: usize = 16;
+
+/// Size of the header prepended to each encrypted packet
+pub const HEADER_LEN: usize = 9;
+
+/// Number of bytes encryption adds to each packet
+pub const OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+
+/// Number of counters tracked by a [`ReplayWindow`]
+pub const REPLAY_WINDOW_SIZE: u64 = 1024;
+
+const WINDOW_WORDS: usize = (REPLAY_WINDOW_SIZE / 64) as usize;
+
+/// An authenticated encryption algorithm
+pub trait Cipher: Send + Sync {
+    /// Encrypts `data` in place, returning the authentication tag
+    ///
+    /// # Arguments
+    /// * `nonce` - Unique nonce (never reused with the same key)
+    /// * `aad` - Additional data to authenticate
+    /// * `data` - Plaintext to encrypt
+    ///
+    /// # Errors
+    /// * Encryption failed
+    fn seal(
+        &self,
+        nonce: &[u8; NONCE_LEN],
+        aad: &[u8],
+        data: &mut [u8],
+    ) -> Result<[u8; TAG_LEN], TunError>;
+
+    /// Decrypts `data` in place after verifying `tag`
+    ///
+    /// # Arguments
+    /// * `nonce` - Nonce used to encrypt the data
+    /// * `aad` - Additional data that was authenticated
+    /// * `data` - Ciphertext to decrypt
+    /// * `tag` - Authentication tag produced by [`seal`](Cipher::seal)
+    ///
+    /// # Errors
+    /// * `DecryptionFailed` if the ciphertext, additional data or tag has been tampered with
+    fn open(
+        &self,
+        nonce: &[u8; NONCE_LEN],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &[u8; TAG_LEN],
+    ) -> Result<(), TunError>;
+}
+
+#[cfg(feature = "crypto")]
+mod aead_ciphers {
+    use super::{Cipher, NONCE_LEN, TAG_LEN};
+    use crate::TunError;
+    use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+
+    macro_rules! aead_cipher {
+        ($(#[$doc:meta])* $name:ident, $inner:ty) => {
+            $(#[$doc])*
+            pub struct $name($inner);
+
+            impl $name {
+                /// Creates a new cipher using a 256-bit key
+                ///
+                /// # Arguments
+                /// * `key` - Secret key shared with the peer
+                pub fn new(key: &[u8; 32]) -> Self {
+                    Self(<$inner>::new(key.into()))
+                }
+            }
+
+            impl std::fmt::Debug for $name {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    // never print key material
+                    f.write_str(stringify!($name))
+                }
+            }
+
+            impl Cipher for $name {
+                fn seal(
+                    &self,
+                    nonce: &[u8; NONCE_LEN],
+                    aad: &[u8],
+                    data: &mut [u8],
+                ) -> Result<[u8; TAG_LEN], TunError> {
+                    let tag = self
+                        .0
+                        .encrypt_in_place_detached(nonce.into(), aad, data)
+                        .map_err(|_| TunError::EncryptionFailed)?;
+                    Ok(tag.into())
+                }
+
+                fn open(
+                    &self,
+                    nonce: &[u8; NONCE_LEN],
+                    aad: &[u8],
+                    data: &mut [u8],
+                    tag: &[u8; TAG_LEN],
+                ) -> Result<(), TunError> {
+                    self.0
+                        .decrypt_in_place_detached(nonce.into(), aad, data, tag.into())
+                        .map_err(|_| TunError::DecryptionFailed)
+                }
+            }
+        };
+    }
+
+    aead_cipher!(
+        /// ChaCha20-Poly1305 (RFC 8439)
+        ChaCha20Poly1305,
+        chacha20poly1305::ChaCha20Poly1305
+    );
+
+    aead_cipher!(
+        /// AES-256 in Galois/Counter Mode
+        Aes256Gcm,
+        aes_gcm::Aes256Gcm
+    );
+}
+
+#[cfg(feature = "crypto")]
+pub use self::aead_ciphers::{Aes256Gcm, ChaCha20Poly1305};
+
+/// Sliding window of recently received counters used to reject replayed packets
+#[derive(Clone, Debug, Default)]
+pub struct ReplayWindow {
+    // highest counter accepted so far
+    highest: Option<u64>,
+
+    // bit per counter in the window, indexed by `counter % REPLAY_WINDOW_SIZE`
+    bits: [u64; WINDOW_WORDS],
+}
+
+impl ReplayWindow {
+    /// Returns true if `counter` has not been seen and is not too old
+    ///
+    /// # Arguments
+    /// * `counter` - Counter of a received packet
+    pub fn check(&self, counter: u64) -> bool {
+        match self.highest {
+            None => true,
+            Some(highest) if counter > highest => true,
+            Some(highest) if highest - counter >= REPLAY_WINDOW_SIZE => false,
+            Some(_) => !self.is_set(counter),
+        }
+    }
+
+    /// Marks `counter` as seen
+    ///
+    /// Should only be called once the packet has been authenticated.
+    ///
+    /// # Arguments
+    /// * `counter` - Counter of an authenticated packet
+    pub fn update(&mut self, counter: u64) {
+        match self.highest {
+            Some(highest) if counter <= highest => (),
+            Some(highest) if counter - highest < REPLAY_WINDOW_SIZE => {
+                // forget counters that have slid out of the window
+                for old in highest + 1..=counter {
+                    self.clear(old);
+                }
+                self.highest = Some(counter);
+            }
+            _ => {
+                self.bits = [0; WINDOW_WORDS];
+                self.highest = Some(counter);
+            }
+        }
+
+        let (word, bit) = Self::position(counter);
+        self.bits[word] |= 1 << bit;
+    }
+
+    fn is_set(&self, counter: u64) -> bool {
+        let (word, bit) = Self::position(counter);
+        self.bits[word] & (1 << bit) != 0
+    }
+
+    fn clear(&mut self, counter: u64) {
+        let (word, bit) = Self::position(counter);
+        self.bits[word] &= !(1 << bit);
+    }
+
+    fn position(counter: u64) -> (usize, u64) {
+        let idx = counter % REPLAY_WINDOW_SIZE;
+        ((idx / 64) as usize, idx % 64)
+    }
+}
+
+/// Side of the tunnel, used to keep nonces used by each end distinct
+///
+/// Both ends share the same keys, so they must use different roles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Seals packets with direction byte 0 in the nonce and opens the peer's packets with 1,
+    /// the peer must be a `Responder`
+    Initiator,
+
+    /// Seals packets with direction byte 1 in the nonce and opens the peer's packets with 0,
+    /// the peer must be an `Initiator`
+    Responder,
+}
+
+impl Role {
+    fn nonce(self, counter: u64) -> [u8; NONCE_LEN] {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[3] = match self {
+            Role::Initiator => 0,
+            Role::Responder => 1,
+        };
+        nonce[4..].copy_from_slice(&counter.to_be_bytes());
+        nonce
+    }
+
+    fn peer(self) -> Self {
+        match self {
+            Role::Initiator => Role::Responder,
+            Role::Responder => Role::Initiator,
+        }
+    }
+}
+
+/// A key along with its sending counter and receiving replay window
+struct Key {
+    id: u8,
+    cipher: Box<dyn Cipher>,
+    tx_counter: AtomicU64,
+    window: Mutex<ReplayWindow>,
+}
+
+/// Keys currently accepted by an [`EncryptedTun`]
+struct Keys {
+    // key used to encrypt outgoing packets
+    current: Arc<Key>,
+
+    // key retained after a rotation so packets in flight can still be decrypted
+    previous: Option<Arc<Key>>,
+}
+
+/// A tun device wrapper that encrypts packets read from, and decrypts packets written to,
+/// the wrapped device
+pub struct EncryptedTun<T> {
+    tun: T,
+    role: Role,
+    keys: RwLock<Keys>,
+}
+
+impl<T> std::fmt::Debug for EncryptedTun<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedTun")
+            .field("role", &self.role)
+            .field("key_id", &self.key_id())
+            .finish()
+    }
+}
+
+impl<T> EncryptedTun<T> {
+    /// Wraps `tun`, encrypting packets with `cipher`
+    ///
+    /// # Arguments
+    /// * `tun` - Device to wrap
+    /// * `role` - Side of the tunnel (the peer must use the other role)
+    /// * `key_id` - Identifier of the key, sent with every packet
+    /// * `cipher` - Cipher initialized with the key shared with the peer
+    pub fn new(tun: T, role: Role, key_id: u8, cipher: impl Cipher + 'static) -> Self {
+        Self {
+            tun,
+            role,
+            keys: RwLock::new(Keys {
+                current: Arc::new(Key::new(key_id, Box::new(cipher))),
+                previous: None,
+            }),
+        }
+    }
+
+    /// Switches to a new key for outgoing packets
+    ///
+    /// Packets encrypted with the previous key are still accepted until the next rotation.
+    /// Each key must be fresh: reusing a key with a new id restarts its counter and reuses
+    /// nonces.
+    ///
+    /// # Arguments
+    /// * `key_id` - Identifier of the new key (must differ from the current key id)
+    /// * `cipher` - Cipher initialized with the new key
+    ///
+    /// # Errors
+    /// * `InvalidConfig` if `key_id` is the id of the current key (packets in flight sealed
+    ///   with the current key could no longer be told apart)
+    pub fn rotate(&self, key_id: u8, cipher: impl Cipher + 'static) -> Result<(), TunError> {
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        if keys.current.id == key_id {
+            return Err(TunError::InvalidConfig(
+                "key id must differ from the current key id",
+            ));
+        }
+
+        let key = Arc::new(Key::new(key_id, Box::new(cipher)));
+        let previous = std::mem::replace(&mut keys.current, key);
+        keys.previous = Some(previous);
+        Ok(())
+    }
+
+    /// Returns the id of the key used for outgoing packets
+    pub fn key_id(&self) -> u8 {
+        self.keys
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .current
+            .id
+    }
+
+    /// Returns the number of packets encrypted with the current key
+    ///
+    /// Useful to decide when to rotate keys.
+    pub fn sealed(&self) -> u64 {
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        keys.current.tx_counter.load(Ordering::Relaxed)
+    }
+
+    /// Returns a reference to the wrapped device
+    pub fn get_ref(&self) -> &T {
+        &self.tun
+    }
+
+    /// Consumes the wrapper, returning the wrapped device
+    pub fn into_inner(self) -> T {
+        self.tun
+    }
+
+    /// Authenticates and decrypts an encrypted packet
+    ///
+    /// # Arguments
+    /// * `pkt` - Encrypted packet (as produced by `read_packet` on the peer)
+    ///
+    /// # Errors
+    /// * `InvalidPacket` if the packet is too short
+    /// * `UnknownKey` if the packet was encrypted with a key that isn't installed
+    /// * `ReplayedPacket` if the packet was already received (or is too old)
+    /// * `DecryptionFailed` if the packet has been tampered with
+    pub fn decrypt(&self, pkt: &[u8]) -> Result<Vec<u8>, TunError> {
+        if pkt.len() < OVERHEAD {
+            return Err(TunError::InvalidPacket("encrypted packet too short"));
+        }
+
+        let id = pkt[0];
+        let key = {
+            let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+            std::iter::once(&keys.current)
+                .chain(keys.previous.iter())
+                .find(|k| k.id == id)
+                .cloned()
+                .ok_or(TunError::UnknownKey { id })?
+        };
+
+        let mut counter = [0u8; 8];
+        counter.copy_from_slice(&pkt[1..HEADER_LEN]);
+        let counter = u64::from_be_bytes(counter);
+
+        let mut window = key.window.lock().unwrap_or_else(|e| e.into_inner());
+        if !window.check(counter) {
+            return Err(TunError::ReplayedPacket { counter });
+        }
+
+        let (hdr, body) = pkt.split_at(HEADER_LEN);
+        let (ciphertext, tag) = body.split_at(body.len() - TAG_LEN);
+        let mut tag_bytes = [0u8; TAG_LEN];
+        tag_bytes.copy_from_slice(tag);
+
+        let mut data = ciphertext.to_vec();
+        let nonce = self.role.peer().nonce(counter);
+        key.cipher.open(&nonce, hdr, &mut data, &tag_bytes)?;
+
+        window.update(counter);
+        Ok(data)
+    }
+}
+
+impl Key {
+    fn new(id: u8, cipher: Box<dyn Cipher>) -> Self {
+        Self {
+            id,
+            cipher,
+            tx_counter: AtomicU64::new(0),
+            window: Mutex::new(ReplayWindow::default()),
+        }
+    }
+}
+
+impl<T: DynTun> Tun for EncryptedTun<T> {
+    type PktInfo = PacketInfo;
+
+    fn up(&self) -> Result<(), TunError> {
+        self.tun.up()
+    }
+
+    fn down(&self) -> Result<(), TunError> {
+        self.tun.down()
+    }
+
+    /// Reads a packet from the wrapped device and encrypts it
+    ///
+    /// `buf` must be at least [`OVERHEAD`] bytes larger than the largest packet.
+    fn read_packet(&self, buf: &mut [u8]) -> Result<(usize, Self::PktInfo), TunError> {
+        if buf.len() <= OVERHEAD {
+            return Err(TunError::BufferTooSmall);
+        }
+
+        let end = buf.len() - TAG_LEN;
+        let (n, pi) = self.tun.read_packet(&mut buf[HEADER_LEN..end])?;
+
+        let key = Arc::clone(&self.keys.read().unwrap_or_else(|e| e.into_inner()).current);
+        let counter = key.tx_counter.fetch_add(1, Ordering::Relaxed);
+        buf[0] = key.id;
+        buf[1..HEADER_LEN].copy_from_slice(&counter.to_be_bytes());
+
+        let (hdr, body) = buf.split_at_mut(HEADER_LEN);
+        let tag = key
+            .cipher
+            .seal(&self.role.nonce(counter), hdr, &mut body[..n])?;
+        body[n..n + TAG_LEN].copy_from_slice(&tag);
+
+        Ok((OVERHEAD + n, pi))
+    }
+
+    /// Decrypts a packet and writes it to the wrapped device
+    ///
+    /// The packet's protocol is derived from the decrypted packet.
+    ///
+    /// # Errors
+    /// * `InvalidData` if the packet cannot be decrypted (see [`decrypt`](Self::decrypt))
+    /// * I/O if writing to the wrapped device fails
+    fn write_packet(&self, buf: &[u8], pi: Self::PktInfo) -> Result<usize, io::Error> {
+        let pkt = self.decrypt(buf).map_err(|error| {
+            tracing::debug!(?error, "dropping packet that failed decryption");
+            io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+        })?;
+
+        let pi = PacketInfo {
+            flags: pi.flags,
+            protocol: PacketInfo::for_packet(&pkt).protocol,
+        };
+        self.tun.write_packet(&pkt, pi)?;
+        Ok(buf.len())
+    }
+
+    fn blank_pktinfo(&self) -> Self::PktInfo {
+        self.tun.blank_pktinfo()
+    }
+
+    fn poll_readable(&self, timeout: Duration) -> Result<bool, TunError> {
+        self.tun.poll_readable(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::default();
+        for counter in [5, 3, 7, 4] {
+            assert!(window.check(counter));
+            window.update(counter);
+            assert!(!window.check(counter));
+        }
+        assert!(window.check(6));
+
+        // jumping ahead forgets everything that slid out of the window
+        window.update(7 + REPLAY_WINDOW_SIZE);
+        assert!(!window.check(7));
+        assert!(window.check(8));
+        assert!(!window.check(7 + REPLAY_WINDOW_SIZE));
+
+        window.update(10 * REPLAY_WINDOW_SIZE);
+        assert!(window.check(10 * REPLAY_WINDOW_SIZE - 1));
+        assert!(!window.check(9 * REPLAY_WINDOW_SIZE));
+    }
+
+    #[cfg(feature = "crypto")]
+    mod aead {
+        use super::super::*;
+        use crate::mock::MockTun;
+
+        type Encrypted = EncryptedTun<MockTun<(u16, u16)>>;
+
+        fn pair(key: [u8; 32]) -> (Encrypted, Encrypted) {
+            (
+                EncryptedTun::new(
+                    MockTun::default(),
+                    Role::Initiator,
+                    0,
+                    ChaCha20Poly1305::new(&key),
+                ),
+                EncryptedTun::new(
+                    MockTun::default(),
+                    Role::Responder,
+                    0,
+                    ChaCha20Poly1305::new(&key),
+                ),
+            )
+        }
+
+        fn seal(tun: &Encrypted, pkt: &[u8]) -> Vec<u8> {
+            tun.get_ref().push_packet(pkt);
+            let mut buf = [0u8; 256];
+            let (n, _) = Tun::read_packet(tun, &mut buf).unwrap();
+            buf[..n].to_vec()
+        }
+
+        #[test]
+        fn roundtrip_and_replay() {
+            let (a, b) = pair([7; 32]);
+            let sealed = seal(&a, &[0x45, 1, 2, 3]);
+            assert_eq!(sealed.len(), 4 + OVERHEAD);
+            assert_ne!(sealed[HEADER_LEN..HEADER_LEN + 4], [0x45, 1, 2, 3]);
+
+            Tun::write_packet(&b, &sealed, PacketInfo::default()).unwrap();
+            assert_eq!(
+                b.get_ref().written(),
+                vec![(vec![0x45, 1, 2, 3], (0, 0x0800))]
+            );
+
+            // replayed and tampered packets are rejected
+            assert!(matches!(
+                b.decrypt(&sealed),
+                Err(TunError::ReplayedPacket { counter: 0 })
+            ));
+            let mut tampered = seal(&a, &[0x45, 4]);
+            tampered[HEADER_LEN] ^= 1;
+            assert!(matches!(
+                b.decrypt(&tampered),
+                Err(TunError::DecryptionFailed)
+            ));
+
+            // packets reflected back to the sender don't decrypt (different nonce space)
+            let sealed = seal(&a, &[0x45, 5]);
+            assert!(a.decrypt(&sealed).is_err());
+            assert_eq!(b.decrypt(&sealed).unwrap(), [0x45, 5]);
+        }
+
+        #[test]
+        fn key_rotation() {
+            let (a, b) = pair([1; 32]);
+            let old = seal(&a, &[0x60, 1]);
+
+            // the key id must change
+            assert!(matches!(
+                a.rotate(0, Aes256Gcm::new(&[2; 32])),
+                Err(TunError::InvalidConfig(_))
+            ));
+            assert_eq!(a.key_id(), 0);
+
+            a.rotate(1, Aes256Gcm::new(&[2; 32])).unwrap();
+            let new = seal(&a, &[0x60, 2]);
+            assert!(matches!(
+                b.decrypt(&new),
+                Err(TunError::UnknownKey { id: 1 })
+            ));
+
+            b.rotate(1, Aes256Gcm::new(&[2; 32])).unwrap();
+            assert_eq!(b.key_id(), 1);
+            assert_eq!(b.decrypt(&new).unwrap(), [0x60, 2]);
+
+            // the previous key is still accepted for packets in flight
+            assert_eq!(b.decrypt(&old).unwrap(), [0x60, 1]);
+            assert_eq!(a.sealed(), 1);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Object-safe companion to the [`Tun`] trait
+//!
+//! [`Tun`] has an associated packet info type and requires `Sized`, so it cannot be used as
+//! a trait object. [`DynTun`] exposes the same operations using a uniform [`PacketInfo`]
+//! and is implemented for every [`Tun`] whose packet info converts to and from it, allowing
+//! backends to be selected at runtime (e.g., `Box<dyn DynTun>`).
+
+use crate::{Tun, TunError};
+use std::{io, time::Duration};
+
+/// Ethertype used for IPv4 packets
+const ETHERTYPE_IPV4: u16 = 0x0800;
+
+/// Ethertype used for IPv6 packets
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+
+/// Backend-independent packet information
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PacketInfo {
+    /// Backend-specific flags (e.g., `TUN_PKT_STRIP` on Linux)
+    pub flags: u16,
+
+    /// Ethertype of the packet (`0x0800` for IPv4, `0x86DD` for IPv6) or 0 if unknown
+    pub protocol: u16,
+}
+
+impl PacketInfo {
+    /// Returns packet info with the protocol derived from the packet's IP version
+    ///
+    /// # Arguments
+    /// * `pkt` - IPv4 or IPv6 packet
+    pub fn for_packet(pkt: &[u8]) -> Self {
+        let protocol = match pkt.first().map(|b| b >> 4) {
+            Some(4) => ETHERTYPE_IPV4,
+            Some(6) => ETHERTYPE_IPV6,
+            _ => 0,
+        };
+
+        Self { flags: 0, protocol }
+    }
+}
+
+/// Conversion between a backend's packet info type and [`PacketInfo`]
+pub trait PktInfoConvert {
+    /// Converts the backend's packet info into a [`PacketInfo`]
+    fn to_packet_info(&self) -> PacketInfo;
+
+    /// Converts a [`PacketInfo`] into the backend's packet info
+    fn from_packet_info(info: PacketInfo) -> Self;
+}
+
+impl PktInfoConvert for PacketInfo {
+    fn to_packet_info(&self) -> PacketInfo {
+        *self
+    }
+
+    fn from_packet_info(info: PacketInfo) -> Self {
+        info
+    }
+}
+
+/// Backends without packet info
+impl PktInfoConvert for () {
+    fn to_packet_info(&self) -> PacketInfo {
+        PacketInfo::default()
+    }
+
+    fn from_packet_info(_info: PacketInfo) -> Self {}
+}
+
+/// `(flags, ethertype)` packet info used on Linux
+impl PktInfoConvert for (u16, u16) {
+    fn to_packet_info(&self) -> PacketInfo {
+        PacketInfo {
+            flags: self.0,
+            protocol: self.1,
+        }
+    }
+
+    fn from_packet_info(info: PacketInfo) -> Self {
+        (info.flags, info.protocol)
+    }
+}
+
+/// Address family packet info used on FreeBSD
+impl PktInfoConvert for u32 {
+    fn to_packet_info(&self) -> PacketInfo {
+        let protocol = match *self as i32 {
+            libc::AF_INET => ETHERTYPE_IPV4,
+            libc::AF_INET6 => ETHERTYPE_IPV6,
+            _ => 0,
+        };
+
+        PacketInfo { flags: 0, protocol }
+    }
+
+    fn from_packet_info(info: PacketInfo) -> Self {
+        match info.protocol {
+            ETHERTYPE_IPV4 => libc::AF_INET as u32,
+            ETHERTYPE_IPV6 => libc::AF_INET6 as u32,
+            _ => 0,
+        }
+    }
+}
+
+/// Object-safe version of [`Tun`]
+///
+/// Implemented for every [`Tun`] whose packet info implements [`PktInfoConvert`]. The method
+/// names match [`Tun`], so when both traits are in scope calls must be qualified (e.g.,
+/// `DynTun::read_packet(&tun, &mut buf)`).
+pub trait DynTun {
+    /// Marks the device as up on the system
+    fn up(&self) -> Result<(), TunError>;
+
+    /// Marks the device as down on the system
+    fn down(&self) -> Result<(), TunError>;
+
+    /// Reads a packet from this tun device (see [`Tun::read_packet`])
+    ///
+    /// # Arguments
+    /// * `buf` - buffer to read data into
+    ///
+    /// # Errors
+    /// * I/O
+    fn read_packet(&self, buf: &mut [u8]) -> Result<(usize, PacketInfo), TunError>;
+
+    /// Writes a packet to the tun device (see [`Tun::write_packet`])
+    ///
+    /// If `pi` has no protocol set, it is derived from the packet's IP version.
+    ///
+    /// # Arguments
+    /// * `buf` - Buffer to write
+    /// * `pi` - Packet information
+    fn write_packet(&self, buf: &[u8], pi: PacketInfo) -> Result<usize, io::Error>;
+
+    /// Returns a blank/empty packet info struct
+    fn blank_pktinfo(&self) -> PacketInfo;
+
+    /// Waits until a packet is available to read (see [`Tun::poll_readable`])
+    ///
+    /// # Arguments
+    /// * `timeout` - Maximum amount of time to wait
+    ///
+    /// # Errors
+    /// * I/O if waiting on the device fails
+    fn poll_readable(&self, timeout: Duration) -> Result<bool, TunError>;
+}
+
+impl<T> DynTun for T
+where
+    T: Tun,
+    T::PktInfo: PktInfoConvert,
+{
+    fn up(&self) -> Result<(), TunError> {
+        Tun::up(self)
+    }
+
+    fn down(&self) -> Result<(), TunError> {
+        Tun::down(self)
+    }
+
+    fn read_packet(&self, buf: &mut [u8]) -> Result<(usize, PacketInfo), TunError> {
+        let (n, pi) = Tun::read_packet(self, buf)?;
+        Ok((n, pi.to_packet_info()))
+    }
+
+    fn write_packet(&self, buf: &[u8], mut pi: PacketInfo) -> Result<usize, io::Error> {
+        if pi.protocol == 0 {
+            pi.protocol = PacketInfo::for_packet(buf).protocol;
+        }
+
+        Tun::write_packet(self, buf, T::PktInfo::from_packet_info(pi))
+    }
+
+    fn blank_pktinfo(&self) -> PacketInfo {
+        Tun::blank_pktinfo(self).to_packet_info()
+    }
+
+    fn poll_readable(&self, timeout: Duration) -> Result<bool, TunError> {
+        Tun::poll_readable(self, timeout)
+    }
+}
+
+/// Allows boxed trait objects to be used wherever a [`Tun`] is expected
+impl<D> Tun for Box<D>
+where
+    D: DynTun + ?Sized,
+{
+    type PktInfo = PacketInfo;
+
+    fn up(&self) -> Result<(), TunError> {
+        self.as_ref().up()
+    }
+
+    fn down(&self) -> Result<(), TunError> {
+        self.as_ref().down()
+    }
+
+    fn read_packet(&self, buf: &mut [u8]) -> Result<(usize, Self::PktInfo), TunError> {
+        self.as_ref().read_packet(buf)
+    }
+
+    fn write_packet(&self, buf: &[u8], pi: Self::PktInfo) -> Result<usize, io::Error> {
+        self.as_ref().write_packet(buf, pi)
+    }
+
+    fn blank_pktinfo(&self) -> Self::PktInfo {
+        self.as_ref().blank_pktinfo()
+    }
+
+    fn poll_readable(&self, timeout: Duration) -> Result<bool, TunError> {
+        self.as_ref().poll_readable(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // only `DynTun` is imported so method calls on boxed devices aren't ambiguous
+    use super::{DynTun, PacketInfo, PktInfoConvert, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+    use crate::{mock::MockTun, replay::ReplayTun, responder::EchoResponder, TunConfig};
+
+    #[test]
+    fn heterogeneous_backends() {
+        let mock = MockTun::new();
+        mock.push_packet(&[0x45, 1][..]);
+
+        let linux_like = MockTun::<(u16, u16)>::default();
+        linux_like.push_packet_with_info(&[0x60, 2][..], (1, ETHERTYPE_IPV6));
+
+        let backends: Vec<Box<dyn DynTun>> = vec![
+            Box::new(mock),
+            Box::new(linux_like),
+            Box::new(ReplayTun::from_records(Vec::new())),
+        ];
+
+        let mut buf = [0u8; 16];
+        let (n, pi) = backends[0].read_packet(&mut buf).unwrap();
+        assert_eq!((&buf[..n], pi), (&[0x45, 1][..], PacketInfo::default()));
+
+        let (n, pi) = backends[1].read_packet(&mut buf).unwrap();
+        assert_eq!(&buf[..n], [0x60, 2]);
+        assert_eq!(
+            pi,
+            PacketInfo {
+                flags: 1,
+                protocol: ETHERTYPE_IPV6
+            }
+        );
+
+        for tun in &backends {
+            tun.up().unwrap();
+            assert_eq!(
+                tun.write_packet(&[0x45, 0], tun.blank_pktinfo()).unwrap(),
+                2
+            );
+        }
+    }
+
+    #[test]
+    fn protocol_derived_on_write() {
+        let mock = Box::new(MockTun::<(u16, u16)>::default());
+        let tun: &dyn DynTun = mock.as_ref();
+        tun.write_packet(&[0x45, 0], PacketInfo::default()).unwrap();
+        tun.write_packet(&[0x60, 0], PacketInfo::default()).unwrap();
+
+        let written: Vec<_> = mock.written().into_iter().map(|(_, pi)| pi).collect();
+        assert_eq!(written, vec![(0, ETHERTYPE_IPV4), (0, ETHERTYPE_IPV6)]);
+
+        let af = u32::from_packet_info(PacketInfo::for_packet(&[0x60]));
+        assert_eq!(af, libc::AF_INET6 as u32);
+        assert_eq!(af.to_packet_info().protocol, ETHERTYPE_IPV6);
+    }
+
+    #[test]
+    fn boxed_backend_is_tun() {
+        let mock = MockTun::new();
+        mock.push_packet(&b"passthrough"[..]);
+
+        let boxed: Box<dyn DynTun> = Box::new(mock);
+        let responder = EchoResponder::new(boxed, &TunConfig::default());
+
+        let mut buf = [0u8; 16];
+        let (n, _) = crate::Tun::read_packet(&responder, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"passthrough");
+    }
+}
@@ -0,0 +1,164 @@
+//! Passing tun device file descriptors between processes
+//!
+//! A privileged supervisor can create a device and hand its file descriptor to unprivileged
+//! workers over a Unix socket using `SCM_RIGHTS` ancillary data. The worker adopts the
+//! received descriptor with `OsTun::from_fd`.
+
+use std::{
+    io, mem,
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+    },
+    ptr,
+};
+
+/// Sends a file descriptor over a Unix socket
+///
+/// A single byte of regular data is sent along with the descriptor, as some platforms drop
+/// ancillary data attached to empty messages. The descriptor stays open in this process.
+///
+/// # Arguments
+/// * `socket` - Connected Unix socket
+/// * `fd` - File descriptor to send (e.g., `tun.as_raw_fd()`)
+///
+/// # Errors
+/// * I/O if the message fails to send
+pub fn send_fd(socket: &UnixStream, fd: RawFd) -> io::Result<()> {
+    let mut data = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as _,
+        iov_len: data.len(),
+    };
+
+    // u64 backing storage keeps the control buffer aligned for cmsghdr
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as _) } as usize;
+    let mut control = vec![0u64; space.div_ceil(mem::size_of::<u64>())];
+
+    // SAFETY: msghdr is a plain C struct, all-zero is a valid (empty) value
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as _;
+    msg.msg_controllen = space as _;
+
+    // SAFETY: the control buffer is large enough for one cmsghdr carrying a single fd
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as _) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+    }
+
+    loop {
+        match unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) } {
+            -1 => match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => continue,
+                e => return Err(e),
+            },
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Receives a file descriptor sent with [`send_fd`]
+///
+/// The received descriptor is owned by the caller and has close-on-exec set.
+///
+/// # Arguments
+/// * `socket` - Connected Unix socket
+///
+/// # Errors
+/// * I/O if the message fails to receive
+/// * I/O (`UnexpectedEof`) if the peer closed the socket
+/// * I/O (`InvalidData`) if the message didn't carry a file descriptor
+pub fn recv_fd(socket: &UnixStream) -> io::Result<RawFd> {
+    let mut data = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as _,
+        iov_len: data.len(),
+    };
+
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as _) } as usize;
+    let mut control = vec![0u64; space.div_ceil(mem::size_of::<u64>())];
+
+    // SAFETY: msghdr is a plain C struct, all-zero is a valid (empty) value
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as _;
+    msg.msg_controllen = space as _;
+
+    let n = loop {
+        match unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) } {
+            -1 => match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => continue,
+                e => return Err(e),
+            },
+            n => break n,
+        }
+    };
+
+    if n == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    // SAFETY: the kernel filled in msg_controllen bytes of the control buffer
+    let mut fd = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                fd = Some(ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        // descriptors that didn't fit were closed by the kernel
+        tracing::warn!("ancillary data truncated while receiving fd");
+    }
+
+    fd.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no file descriptor received"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs::File,
+        io::{Read, Write},
+        os::unix::io::FromRawFd,
+    };
+
+    #[test]
+    fn pass_fd_over_socket() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let (mut reader, writer) = UnixStream::pair().unwrap();
+
+        send_fd(&a, writer.as_raw_fd()).unwrap();
+        drop(writer);
+
+        // the received descriptor refers to the same socket as the sent one
+        let mut received = unsafe { File::from_raw_fd(recv_fd(&b).unwrap()) };
+        received.write_all(b"hello").unwrap();
+        drop(received);
+
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"hello");
+
+        // plain data without a descriptor is rejected
+        (&a).write_all(b"x").unwrap();
+        let err = recv_fd(&b).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        drop(a);
+        assert_eq!(
+            recv_fd(&b).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
@@ -0,0 +1,956 @@
+//! IP fragmentation and reassembly
+//!
+//! [`Reassembler`] collects IPv4 and IPv6 fragments read from a tun device and returns
+//! complete packets, while [`Fragmenter`] splits packets that exceed a path MTU before
+//! they are written to a device (or encapsulated).
+
+use crate::{checksum, TunError};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+/// Default amount of time to wait for all fragments of a packet (matches Linux)
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default maximum number of bytes buffered across all incomplete packets
+pub const DEFAULT_MEMORY_LIMIT: usize = 4 * 1024 * 1024;
+
+/// Largest packet that can be produced by reassembly
+const MAX_PACKET_LEN: usize = u16::MAX as usize;
+
+/// Minimum MTU every IPv4 link must support
+const IPV4_MIN_MTU: usize = 68;
+
+/// Minimum MTU every IPv6 link must support
+const IPV6_MIN_MTU: usize = 1280;
+
+const IPV6_HEADER_LEN: usize = 40;
+const IPV6_FRAG_HEADER_LEN: usize = 8;
+
+// IPv6 extension header numbers
+const EXT_HOP_BY_HOP: u8 = 0;
+const EXT_ROUTING: u8 = 43;
+const EXT_FRAGMENT: u8 = 44;
+const EXT_DEST_OPTS: u8 = 60;
+
+/// Returns true if `pkt` is an IPv4 or IPv6 fragment
+///
+/// # Arguments
+/// * `pkt` - IPv4 or IPv6 packet (without packet info)
+pub fn is_fragment(pkt: &[u8]) -> bool {
+    match pkt.first().map(|b| b >> 4) {
+        Some(4) if pkt.len() >= 20 => u16::from_be_bytes([pkt[6], pkt[7]]) & 0x3FFF != 0,
+        Some(6) => matches!(Ipv6Headers::parse(pkt), Ok(hdrs) if hdrs.fragment.is_some()),
+        _ => false,
+    }
+}
+
+/// Identifies the packet a fragment belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    V4 {
+        src: [u8; 4],
+        dst: [u8; 4],
+        proto: u8,
+        id: u16,
+    },
+    V6 {
+        src: [u8; 16],
+        dst: [u8; 16],
+        id: u32,
+    },
+}
+
+/// A single parsed fragment
+#[derive(Debug)]
+struct Fragment<'a> {
+    key: Key,
+
+    /// Header to use for the reassembled packet (only used from the first fragment)
+    header: &'a [u8],
+
+    /// IPv6 only: offset in `header` of the next header field pointing at the fragment
+    /// header, and the value it should be replaced with
+    next_header: Option<(usize, u8)>,
+
+    /// Offset of this fragment's data in the reassembled payload
+    offset: usize,
+
+    /// True if more fragments follow this one
+    more: bool,
+
+    /// Fragment data
+    data: &'a [u8],
+}
+
+impl Fragment<'_> {
+    /// Length of the header counted by the packet's length field (all of it for IPv4, the
+    /// extension headers for IPv6)
+    fn header_len(&self) -> usize {
+        match self.key {
+            Key::V4 { .. } => self.header.len(),
+            Key::V6 { .. } => self.header.len().saturating_sub(IPV6_HEADER_LEN),
+        }
+    }
+}
+
+/// A packet currently being reassembled
+#[derive(Debug)]
+struct Datagram {
+    /// Header of the first fragment (once received)
+    header: Option<Vec<u8>>,
+
+    /// See [`Fragment::next_header`]
+    next_header: Option<(usize, u8)>,
+
+    /// Reassembled payload
+    data: Vec<u8>,
+
+    /// Ranges of `data` that have been received
+    ranges: Vec<Range<usize>>,
+
+    /// Total payload length (known once the last fragment arrives)
+    total: Option<usize>,
+
+    /// Time the first fragment was received
+    created: Instant,
+}
+
+impl Datagram {
+    fn memory(&self) -> usize {
+        self.data.len() + self.header.as_ref().map(|h| h.len()).unwrap_or(0)
+    }
+
+    fn is_complete(&self) -> bool {
+        let received: usize = self.ranges.iter().map(|r| r.len()).sum();
+        self.header.is_some() && Some(received) == self.total
+    }
+
+    /// Adds a fragment to this datagram
+    ///
+    /// Returns false if the fragment is inconsistent with data already received (i.e.,
+    /// overlaps another fragment or conflicts with the total length)
+    fn insert(&mut self, frag: &Fragment) -> bool {
+        let range = frag.offset..frag.offset + frag.data.len();
+
+        for existing in &self.ranges {
+            if *existing == range && self.data[range.clone()] == *frag.data {
+                // exact duplicate (e.g., retransmission), nothing to do
+                return true;
+            }
+
+            if existing.start < range.end && range.start < existing.end {
+                return false;
+            }
+        }
+
+        if !frag.more {
+            if self.total.map(|t| t != range.end).unwrap_or(false) {
+                return false;
+            }
+            self.total = Some(range.end);
+        }
+
+        if self.total.map(|t| range.end > t).unwrap_or(false) {
+            return false;
+        }
+
+        if frag.offset == 0 {
+            self.header = Some(frag.header.to_vec());
+            self.next_header = frag.next_header;
+        }
+
+        if self.data.len() < range.end {
+            self.data.resize(range.end, 0);
+        }
+        self.data[range.clone()].copy_from_slice(frag.data);
+        self.ranges.push(range);
+        true
+    }
+
+    /// Value of the reassembled packet's length field (total length for IPv4, payload
+    /// length for IPv6)
+    fn len(&self) -> usize {
+        let header = self.header.as_deref().unwrap_or_default();
+        match header.first().map(|b| b >> 4) {
+            Some(4) => header.len() + self.data.len(),
+            _ => header.len().saturating_sub(IPV6_HEADER_LEN) + self.data.len(),
+        }
+    }
+
+    /// Builds the reassembled packet
+    fn build(self) -> Vec<u8> {
+        let mut pkt = self.header.unwrap_or_default();
+        let hdr_len = pkt.len();
+        pkt.extend_from_slice(&self.data);
+
+        match pkt[0] >> 4 {
+            4 => {
+                let total = (pkt.len() as u16).to_be_bytes();
+                pkt[2..4].copy_from_slice(&total);
+                pkt[6..8].copy_from_slice(&[0, 0]);
+                let csum = checksum::ipv4_header(&pkt[..hdr_len]);
+                pkt[10..12].copy_from_slice(&csum.to_be_bytes());
+            }
+            _ => {
+                if let Some((idx, next)) = self.next_header {
+                    pkt[idx] = next;
+                }
+                let payload = ((pkt.len() - IPV6_HEADER_LEN) as u16).to_be_bytes();
+                pkt[4..6].copy_from_slice(&payload);
+            }
+        }
+
+        pkt
+    }
+}
+
+/// Reassembles IPv4 and IPv6 fragments into complete packets
+///
+/// Fragments that overlap previously received data cause the entire packet to be
+/// discarded (see RFC 5722), incomplete packets are discarded after a timeout, and the
+/// total amount of buffered data is bounded by a memory limit.
+#[derive(Debug)]
+pub struct Reassembler {
+    // packets currently being reassembled
+    pending: HashMap<Key, Datagram>,
+
+    // amount of time to wait for all fragments of a packet
+    timeout: Duration,
+
+    // maximum number of bytes buffered across all pending packets
+    memory_limit: usize,
+
+    // number of bytes currently buffered
+    memory: usize,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reassembler {
+    /// Creates a new reassembler using the default timeout and memory limit
+    pub fn new() -> Self {
+        Self {
+            pending: HashMap::new(),
+            timeout: DEFAULT_TIMEOUT,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            memory: 0,
+        }
+    }
+
+    /// Sets the amount of time to wait for all fragments of a packet to arrive
+    ///
+    /// # Arguments
+    /// * `timeout` - Time after the first fragment is received to discard the packet
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum number of bytes buffered across all incomplete packets
+    ///
+    /// When the limit is exceeded, the oldest incomplete packets are discarded.
+    ///
+    /// # Arguments
+    /// * `limit` - Maximum number of bytes to buffer
+    pub fn memory_limit(mut self, limit: usize) -> Self {
+        self.memory_limit = limit;
+        self
+    }
+
+    /// Returns the number of packets currently being reassembled
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns the number of bytes currently buffered
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Processes a packet read from a tun device
+    ///
+    /// Packets that are not fragments are returned as-is. Fragments are buffered until
+    /// the packet is complete, at which point the reassembled packet is returned.
+    ///
+    /// # Arguments
+    /// * `pkt` - IPv4 or IPv6 packet (without packet info)
+    ///
+    /// # Errors
+    /// * `pkt` is not a well-formed IPv4 or IPv6 packet
+    pub fn push<'a>(&mut self, pkt: &'a [u8]) -> Result<Option<Cow<'a, [u8]>>, TunError> {
+        self.push_at(pkt, Instant::now())
+    }
+
+    /// Processes a packet as if it was received at `now`
+    ///
+    /// See [`Reassembler::push`]
+    pub fn push_at<'a>(
+        &mut self,
+        pkt: &'a [u8],
+        now: Instant,
+    ) -> Result<Option<Cow<'a, [u8]>>, TunError> {
+        self.expire(now);
+
+        let frag = match parse_fragment(pkt)? {
+            Some(frag) => frag,
+            None => return Ok(Some(Cow::Borrowed(pkt))),
+        };
+
+        if frag.header_len() + frag.offset + frag.data.len() > MAX_PACKET_LEN
+            || (frag.more && !frag.data.len().is_multiple_of(8))
+        {
+            tracing::debug!("discarding invalid fragment");
+            self.discard(&frag.key);
+            return Ok(None);
+        }
+
+        let datagram = self.pending.entry(frag.key.clone()).or_insert(Datagram {
+            header: None,
+            next_header: None,
+            data: Vec::new(),
+            ranges: Vec::new(),
+            total: None,
+            created: now,
+        });
+
+        let before = datagram.memory();
+        if !datagram.insert(&frag) {
+            tracing::debug!(key = ?frag.key, "discarding packet with overlapping fragments");
+            self.discard(&frag.key);
+            return Ok(None);
+        }
+        let after = datagram.memory();
+        self.memory = self.memory + after - before;
+
+        if datagram.is_complete() {
+            let datagram = self.pending.remove(&frag.key).expect("pending datagram");
+            self.memory -= datagram.memory();
+
+            // the first fragment's header may be longer than the one checked above
+            if datagram.len() > MAX_PACKET_LEN {
+                tracing::debug!(key = ?frag.key, "discarding oversized packet");
+                return Ok(None);
+            }
+            return Ok(Some(Cow::Owned(datagram.build())));
+        }
+
+        self.enforce_limit(&frag.key);
+        Ok(None)
+    }
+
+    /// Discards any packets that have not completed before their timeout
+    ///
+    /// # Arguments
+    /// * `now` - Current time
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let mut freed = 0;
+        self.pending.retain(|key, datagram| {
+            let expired = now.saturating_duration_since(datagram.created) >= timeout;
+            if expired {
+                tracing::debug!(?key, "fragment reassembly timed out");
+                freed += datagram.memory();
+            }
+            !expired
+        });
+        self.memory -= freed;
+    }
+
+    fn discard(&mut self, key: &Key) {
+        if let Some(datagram) = self.pending.remove(key) {
+            self.memory -= datagram.memory();
+        }
+    }
+
+    /// Evicts the oldest pending packets until we're under the memory limit
+    fn enforce_limit(&mut self, current: &Key) {
+        while self.memory > self.memory_limit {
+            let oldest = self
+                .pending
+                .iter()
+                .min_by_key(|(key, d)| (*key == current, d.created))
+                .map(|(key, _)| key.clone());
+
+            match oldest {
+                Some(key) => {
+                    tracing::debug!(?key, "fragment memory limit exceeded");
+                    self.discard(&key);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// Parses a fragment, returning `None` if the packet is not fragmented
+fn parse_fragment(pkt: &[u8]) -> Result<Option<Fragment<'_>>, TunError> {
+    match pkt.first().map(|b| b >> 4) {
+        Some(4) => parse_fragment_v4(pkt),
+        Some(6) => parse_fragment_v6(pkt),
+        Some(_) => Err(TunError::InvalidPacket("unknown ip version")),
+        None => Err(TunError::NotEnoughData),
+    }
+}
+
+fn parse_fragment_v4(pkt: &[u8]) -> Result<Option<Fragment<'_>>, TunError> {
+    if pkt.len() < 20 {
+        return Err(TunError::NotEnoughData);
+    }
+
+    let hdr_len = ((pkt[0] & 0x0F) as usize) * 4;
+    let total_len = u16::from_be_bytes([pkt[2], pkt[3]]) as usize;
+    if hdr_len < 20 || total_len < hdr_len {
+        return Err(TunError::InvalidPacket("invalid ipv4 header length"));
+    }
+    if pkt.len() < total_len {
+        return Err(TunError::NotEnoughData);
+    }
+
+    let flags = u16::from_be_bytes([pkt[6], pkt[7]]);
+    let more = flags & 0x2000 != 0;
+    let offset = ((flags & 0x1FFF) as usize) * 8;
+    if !more && offset == 0 {
+        return Ok(None);
+    }
+
+    let mut src = [0u8; 4];
+    let mut dst = [0u8; 4];
+    src.copy_from_slice(&pkt[12..16]);
+    dst.copy_from_slice(&pkt[16..20]);
+
+    Ok(Some(Fragment {
+        key: Key::V4 {
+            src,
+            dst,
+            proto: pkt[9],
+            id: u16::from_be_bytes([pkt[4], pkt[5]]),
+        },
+        header: &pkt[..hdr_len],
+        next_header: None,
+        offset,
+        more,
+        data: &pkt[hdr_len..total_len],
+    }))
+}
+
+fn parse_fragment_v6(pkt: &[u8]) -> Result<Option<Fragment<'_>>, TunError> {
+    let hdrs = Ipv6Headers::parse(pkt)?;
+    let frag = match hdrs.fragment {
+        Some(off) => off,
+        None => return Ok(None),
+    };
+
+    let total_len = IPV6_HEADER_LEN + u16::from_be_bytes([pkt[4], pkt[5]]) as usize;
+    let info = u16::from_be_bytes([pkt[frag + 2], pkt[frag + 3]]);
+    let more = info & 0x1 != 0;
+    let offset = ((info >> 3) as usize) * 8;
+
+    let mut src = [0u8; 16];
+    let mut dst = [0u8; 16];
+    src.copy_from_slice(&pkt[8..24]);
+    dst.copy_from_slice(&pkt[24..40]);
+
+    Ok(Some(Fragment {
+        key: Key::V6 {
+            src,
+            dst,
+            id: u32::from_be_bytes([pkt[frag + 4], pkt[frag + 5], pkt[frag + 6], pkt[frag + 7]]),
+        },
+        header: &pkt[..frag],
+        next_header: Some((hdrs.next_idx, pkt[frag])),
+        offset,
+        more,
+        data: &pkt[frag + IPV6_FRAG_HEADER_LEN..total_len],
+    }))
+}
+
+/// Location of the IPv6 extension headers relevant to fragmentation
+#[derive(Debug)]
+struct Ipv6Headers {
+    /// Length of the unfragmentable part (IPv6 header plus hop-by-hop / routing headers)
+    unfragmentable: usize,
+
+    /// Offset of the next header field that points past the unfragmentable part
+    next_idx: usize,
+
+    /// Offset of the fragment header (if present)
+    fragment: Option<usize>,
+}
+
+impl Ipv6Headers {
+    fn parse(pkt: &[u8]) -> Result<Self, TunError> {
+        if pkt.len() < IPV6_HEADER_LEN {
+            return Err(TunError::NotEnoughData);
+        }
+
+        let total_len = IPV6_HEADER_LEN + u16::from_be_bytes([pkt[4], pkt[5]]) as usize;
+        if pkt.len() < total_len {
+            return Err(TunError::NotEnoughData);
+        }
+
+        let mut hdrs = Self {
+            unfragmentable: IPV6_HEADER_LEN,
+            next_idx: 6,
+            fragment: None,
+        };
+
+        let mut next = pkt[6];
+        let mut next_idx = 6;
+        let mut off = IPV6_HEADER_LEN;
+        loop {
+            match next {
+                EXT_HOP_BY_HOP | EXT_ROUTING | EXT_DEST_OPTS => {
+                    if off + 2 > total_len {
+                        return Err(TunError::InvalidPacket("truncated ipv6 extension header"));
+                    }
+
+                    // destination options are only unfragmentable if followed by routing
+                    let len = (pkt[off + 1] as usize + 1) * 8;
+                    if next != EXT_DEST_OPTS || pkt[off] == EXT_ROUTING {
+                        hdrs.unfragmentable = off + len;
+                        hdrs.next_idx = off;
+                    }
+
+                    next = pkt[off];
+                    next_idx = off;
+                    off += len;
+                }
+                EXT_FRAGMENT => {
+                    if off + IPV6_FRAG_HEADER_LEN > total_len {
+                        return Err(TunError::InvalidPacket("truncated ipv6 fragment header"));
+                    }
+
+                    hdrs.fragment = Some(off);
+                    hdrs.unfragmentable = off;
+                    hdrs.next_idx = next_idx;
+                    return Ok(hdrs);
+                }
+                _ => return Ok(hdrs),
+            }
+
+            if off > total_len {
+                return Err(TunError::InvalidPacket("truncated ipv6 extension header"));
+            }
+        }
+    }
+}
+
+/// Splits packets into fragments no larger than a given MTU
+#[derive(Debug)]
+pub struct Fragmenter {
+    // maximum size of a produced packet
+    mtu: usize,
+
+    // identification for the next IPv6 packet we fragment
+    next_id: u32,
+}
+
+impl Fragmenter {
+    /// Creates a new fragmenter
+    ///
+    /// # Arguments
+    /// * `mtu` - Maximum size of each produced packet
+    pub fn new(mtu: usize) -> Self {
+        Self {
+            mtu,
+            next_id: rand_id(),
+        }
+    }
+
+    /// Returns the MTU used by this fragmenter
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Splits `pkt` into fragments that fit within the MTU
+    ///
+    /// Packets that already fit are returned unchanged (as a single element).
+    ///
+    /// # Arguments
+    /// * `pkt` - IPv4 or IPv6 packet (without packet info)
+    ///
+    /// # Errors
+    /// * `pkt` is not a well-formed IPv4 or IPv6 packet
+    /// * `pkt` is IPv4 with the don't fragment bit set and exceeds the MTU
+    /// * The MTU is below the minimum supported by the packet's IP version
+    /// * `pkt` is IPv6 with extension headers leaving no room for data under the MTU
+    pub fn fragment(&mut self, pkt: &[u8]) -> Result<Vec<Vec<u8>>, TunError> {
+        match pkt.first().map(|b| b >> 4) {
+            Some(4) => self.fragment_v4(pkt),
+            Some(6) => self.fragment_v6(pkt),
+            Some(_) => Err(TunError::InvalidPacket("unknown ip version")),
+            None => Err(TunError::NotEnoughData),
+        }
+    }
+
+    fn fragment_v4(&mut self, pkt: &[u8]) -> Result<Vec<Vec<u8>>, TunError> {
+        if pkt.len() < 20 {
+            return Err(TunError::NotEnoughData);
+        }
+
+        let hdr_len = ((pkt[0] & 0x0F) as usize) * 4;
+        let total_len = u16::from_be_bytes([pkt[2], pkt[3]]) as usize;
+        if hdr_len < 20 || total_len < hdr_len {
+            return Err(TunError::InvalidPacket("invalid ipv4 header length"));
+        }
+        if pkt.len() < total_len {
+            return Err(TunError::NotEnoughData);
+        }
+
+        if total_len <= self.mtu {
+            return Ok(vec![pkt[..total_len].to_vec()]);
+        }
+
+        if self.mtu < IPV4_MIN_MTU {
+            return Err(TunError::MtuTooSmall { mtu: self.mtu });
+        }
+
+        let flags = u16::from_be_bytes([pkt[6], pkt[7]]);
+        if flags & 0x4000 != 0 {
+            return Err(TunError::FragmentationNeeded { mtu: self.mtu });
+        }
+
+        // an already fragmented packet keeps its position in the original payload
+        let base = ((flags & 0x1FFF) as usize) * 8;
+        let last_more = flags & 0x2000 != 0;
+
+        let first_hdr = &pkt[..hdr_len];
+        let other_hdr = copied_options(first_hdr);
+        let payload = &pkt[hdr_len..total_len];
+
+        let mut frags = Vec::new();
+        let mut pos = 0;
+        while pos < payload.len() {
+            let hdr = match pos {
+                0 => first_hdr,
+                _ => &other_hdr[..],
+            };
+
+            let room = (self.mtu - hdr.len()) & !7;
+            let end = std::cmp::min(pos + room, payload.len());
+            let more = end < payload.len() || last_more;
+
+            let mut frag = Vec::with_capacity(hdr.len() + end - pos);
+            frag.extend_from_slice(hdr);
+            frag.extend_from_slice(&payload[pos..end]);
+
+            frag[0] = 0x40 | (hdr.len() / 4) as u8;
+            let len = (frag.len() as u16).to_be_bytes();
+            frag[2..4].copy_from_slice(&len);
+            let info = (((base + pos) / 8) as u16) | if more { 0x2000 } else { 0 };
+            frag[6..8].copy_from_slice(&info.to_be_bytes());
+            let csum = checksum::ipv4_header(&frag[..hdr.len()]);
+            frag[10..12].copy_from_slice(&csum.to_be_bytes());
+
+            frags.push(frag);
+            pos = end;
+        }
+
+        Ok(frags)
+    }
+
+    fn fragment_v6(&mut self, pkt: &[u8]) -> Result<Vec<Vec<u8>>, TunError> {
+        let hdrs = Ipv6Headers::parse(pkt)?;
+        let total_len = IPV6_HEADER_LEN + u16::from_be_bytes([pkt[4], pkt[5]]) as usize;
+        if total_len <= self.mtu {
+            return Ok(vec![pkt[..total_len].to_vec()]);
+        }
+
+        if self.mtu < IPV6_MIN_MTU {
+            return Err(TunError::MtuTooSmall { mtu: self.mtu });
+        }
+
+        if hdrs.fragment.is_some() {
+            return Err(TunError::InvalidPacket("ipv6 packet is already fragmented"));
+        }
+
+        let unfrag = &pkt[..hdrs.unfragmentable];
+        let next = pkt[hdrs.next_idx];
+        let payload = &pkt[hdrs.unfragmentable..total_len];
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        // each fragment repeats the unfragmentable part, leaving no room for data when the
+        // extension headers (nearly) fill the MTU
+        let room = self
+            .mtu
+            .checked_sub(unfrag.len() + IPV6_FRAG_HEADER_LEN)
+            .map(|room| room & !7)
+            .filter(|&room| room >= 8)
+            .ok_or(TunError::MtuTooSmall { mtu: self.mtu })?;
+
+        let mut frags = Vec::new();
+        let mut pos = 0;
+        while pos < payload.len() {
+            let end = std::cmp::min(pos + room, payload.len());
+            let more = end < payload.len();
+
+            let mut frag = Vec::with_capacity(unfrag.len() + IPV6_FRAG_HEADER_LEN + end - pos);
+            frag.extend_from_slice(unfrag);
+            frag[hdrs.next_idx] = EXT_FRAGMENT;
+
+            let info = (pos as u16) | if more { 1 } else { 0 };
+            frag.extend_from_slice(&[next, 0]);
+            frag.extend_from_slice(&info.to_be_bytes());
+            frag.extend_from_slice(&id.to_be_bytes());
+            frag.extend_from_slice(&payload[pos..end]);
+
+            let payload_len = ((frag.len() - IPV6_HEADER_LEN) as u16).to_be_bytes();
+            frag[4..6].copy_from_slice(&payload_len);
+
+            frags.push(frag);
+            pos = end;
+        }
+
+        Ok(frags)
+    }
+}
+
+/// Builds the IPv4 header used for non-initial fragments (only options with the copied
+/// flag set are included)
+fn copied_options(hdr: &[u8]) -> Vec<u8> {
+    let mut out = hdr[..20].to_vec();
+    let mut off = 20;
+    while off < hdr.len() {
+        let kind = hdr[off];
+        let len = match kind {
+            // end of option list
+            0 => break,
+            // no-op
+            1 => 1,
+            _ if off + 1 < hdr.len() => std::cmp::max(hdr[off + 1] as usize, 2),
+            _ => break,
+        };
+
+        let end = std::cmp::min(off + len, hdr.len());
+        if kind & 0x80 != 0 {
+            out.extend_from_slice(&hdr[off..end]);
+        }
+        off = end;
+    }
+
+    // pad options to a multiple of 4 bytes
+    while !out.len().is_multiple_of(4) {
+        out.push(0);
+    }
+
+    out
+}
+
+/// Produces a (non-cryptographic) random starting identification
+fn rand_id() -> u32 {
+    use std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hasher},
+    };
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default(),
+    );
+    hasher.finish() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn udp4_packet(len: usize) -> Vec<u8> {
+        let total = 28 + len;
+        let mut pkt = vec![0u8; total];
+        pkt[0] = 0x45;
+        pkt[2..4].copy_from_slice(&(total as u16).to_be_bytes());
+        pkt[4..6].copy_from_slice(&0x4242u16.to_be_bytes());
+        pkt[8] = 64;
+        pkt[9] = checksum::PROTO_UDP;
+        pkt[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        pkt[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        pkt[20..22].copy_from_slice(&1234u16.to_be_bytes());
+        pkt[22..24].copy_from_slice(&5678u16.to_be_bytes());
+        pkt[24..26].copy_from_slice(&((8 + len) as u16).to_be_bytes());
+        for (i, b) in pkt[28..].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        checksum::fill(&mut pkt).unwrap();
+        pkt
+    }
+
+    fn udp6_packet(len: usize) -> Vec<u8> {
+        let mut pkt = vec![0u8; 48 + len];
+        pkt[0] = 0x60;
+        pkt[4..6].copy_from_slice(&((8 + len) as u16).to_be_bytes());
+        pkt[6] = checksum::PROTO_UDP;
+        pkt[7] = 64;
+        pkt[8] = 0xfd;
+        pkt[23] = 1;
+        pkt[24] = 0xfd;
+        pkt[39] = 2;
+        pkt[40..42].copy_from_slice(&1234u16.to_be_bytes());
+        pkt[42..44].copy_from_slice(&5678u16.to_be_bytes());
+        pkt[44..46].copy_from_slice(&((8 + len) as u16).to_be_bytes());
+        for (i, b) in pkt[48..].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        checksum::fill(&mut pkt).unwrap();
+        pkt
+    }
+
+    #[test]
+    fn passthrough_unfragmented() {
+        let pkt = udp4_packet(100);
+        let mut reasm = Reassembler::new();
+        let out = reasm.push(&pkt).unwrap().unwrap();
+        assert!(matches!(out, Cow::Borrowed(_)));
+        assert!(!is_fragment(&pkt));
+    }
+
+    #[test]
+    fn ipv4_roundtrip_out_of_order() {
+        let pkt = udp4_packet(3000);
+        let mut frags = Fragmenter::new(1000).fragment(&pkt).unwrap();
+        assert_eq!(frags.len(), 4);
+        assert!(frags.iter().all(|f| f.len() <= 1000 && is_fragment(f)));
+        assert!(frags.iter().all(|f| checksum::compute(&f[..20]) == 0));
+
+        frags.reverse();
+        let mut reasm = Reassembler::new();
+        let last = frags.pop().unwrap();
+        for frag in &frags {
+            assert!(reasm.push(frag).unwrap().is_none());
+        }
+        let out = reasm.push(&last).unwrap().unwrap();
+        assert_eq!(out[..], pkt[..]);
+        assert_eq!(reasm.pending(), 0);
+        assert_eq!(reasm.memory(), 0);
+    }
+
+    #[test]
+    fn ipv6_roundtrip() {
+        let pkt = udp6_packet(4000);
+        let frags = Fragmenter::new(1280).fragment(&pkt).unwrap();
+        assert_eq!(frags.len(), 4);
+        assert!(frags.iter().all(|f| f.len() <= 1280 && is_fragment(f)));
+
+        let mut reasm = Reassembler::new();
+        let mut out = None;
+        for frag in &frags {
+            out = reasm.push(frag).unwrap().map(|p| p.into_owned());
+        }
+        assert_eq!(out.unwrap(), pkt);
+    }
+
+    #[test]
+    fn ipv6_large_extension_headers() {
+        /// UDP packet with a hop-by-hop options header of `hbh_len` bytes (PadN padded)
+        fn with_hop_by_hop(hbh_len: usize) -> Vec<u8> {
+            let udp = udp6_packet(2000);
+            let mut pkt = udp[..40].to_vec();
+            pkt[6] = EXT_HOP_BY_HOP;
+            pkt.extend_from_slice(&[checksum::PROTO_UDP, (hbh_len / 8 - 1) as u8, 1]);
+            pkt.push((hbh_len - 4) as u8);
+            pkt.resize(40 + hbh_len, 0);
+            pkt.extend_from_slice(&udp[40..]);
+            let payload = ((pkt.len() - 40) as u16).to_be_bytes();
+            pkt[4..6].copy_from_slice(&payload);
+            pkt
+        }
+
+        // room for a single 8 byte block of data per fragment
+        let frags = Fragmenter::new(1280)
+            .fragment(&with_hop_by_hop(200))
+            .unwrap();
+        assert!(frags.iter().all(|f| f.len() <= 1280 && is_fragment(f)));
+
+        // headers leave less than 8 bytes under the mtu
+        for hbh_len in [1232, 1240, 2040] {
+            assert!(matches!(
+                Fragmenter::new(1280).fragment(&with_hop_by_hop(hbh_len)),
+                Err(TunError::MtuTooSmall { mtu: 1280 })
+            ));
+        }
+    }
+
+    #[test]
+    fn oversized_reassembly_discarded() {
+        // last fragment ending exactly at 65535 bytes of payload, plus the header
+        let mut frag = udp4_packet(0);
+        frag.truncate(27);
+        frag[2..4].copy_from_slice(&27u16.to_be_bytes());
+        frag[6..8].copy_from_slice(&8191u16.to_be_bytes());
+        frag[10..12].copy_from_slice(&[0, 0]);
+        let csum = checksum::ipv4_header(&frag[..20]);
+        frag[10..12].copy_from_slice(&csum.to_be_bytes());
+
+        let mut reasm = Reassembler::new();
+        assert!(reasm.push(&frag).unwrap().is_none());
+        assert_eq!(reasm.pending(), 0);
+    }
+
+    #[test]
+    fn dont_fragment() {
+        let mut pkt = udp4_packet(2000);
+        pkt[6] |= 0x40;
+        let res = Fragmenter::new(1500).fragment(&pkt);
+        assert!(matches!(
+            res,
+            Err(TunError::FragmentationNeeded { mtu: 1500 })
+        ));
+    }
+
+    #[test]
+    fn overlapping_fragments_discarded() {
+        let pkt = udp4_packet(3000);
+        let frags = Fragmenter::new(1000).fragment(&pkt).unwrap();
+
+        // craft a fragment overlapping the second one
+        let mut evil = frags[1].clone();
+        evil[6..8].copy_from_slice(&((0x2000 | 100) as u16).to_be_bytes());
+
+        let mut reasm = Reassembler::new();
+        assert!(reasm.push(&frags[0]).unwrap().is_none());
+        assert!(reasm.push(&frags[1]).unwrap().is_none());
+
+        // exact duplicates are tolerated
+        assert!(reasm.push(&frags[1]).unwrap().is_none());
+        assert_eq!(reasm.pending(), 1);
+
+        assert!(reasm.push(&evil).unwrap().is_none());
+        assert_eq!(reasm.pending(), 0);
+        assert_eq!(reasm.memory(), 0);
+    }
+
+    #[test]
+    fn timeout_and_memory_limit() {
+        let start = Instant::now();
+        let a = Fragmenter::new(1000).fragment(&udp4_packet(3000)).unwrap();
+        let mut b = udp4_packet(3000);
+        b[4] = 0x99;
+        let b = Fragmenter::new(1000).fragment(&b).unwrap();
+
+        let mut reasm = Reassembler::new().timeout(Duration::from_secs(5));
+        reasm.push_at(&a[0], start).unwrap();
+        reasm.expire(start + Duration::from_secs(6));
+        assert_eq!(reasm.pending(), 0);
+        assert_eq!(reasm.memory(), 0);
+
+        let mut reasm = Reassembler::new().memory_limit(2500);
+        reasm.push_at(&a[0], start).unwrap();
+        reasm.push_at(&a[1], start).unwrap();
+        reasm
+            .push_at(&b[0], start + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(reasm.pending(), 1);
+        assert!(reasm.memory() <= 2500);
+    }
+}
@@ -6,8 +6,9 @@ use std::{
     io::{self, Read, Write},
     mem::{self, MaybeUninit},
     net::IpAddr,
-    os::unix::io::RawFd,
+    os::unix::io::{AsRawFd, RawFd},
     ptr,
+    time::Duration,
 };
 
 const TUN_DEVICE_PATH: &[u8; 9] = b"/dev/tun\0";
@@ -73,6 +74,12 @@ struct IfFlagsReq {
     pad: [u8; 12],
 }
 
+impl std::fmt::Display for OsTun {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 impl Read for OsTun {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         // SAFETY: buf is guarenteed to be a valid u8 pointer and we don't exceed it's length
@@ -104,6 +111,12 @@ impl Write for OsTun {
     }
 }
 
+impl AsRawFd for OsTun {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
 impl OsTun {
     /// Creates a new TUN device on the OS
     ///
@@ -112,7 +125,14 @@ impl OsTun {
     ///
     /// # Arguments
     /// * `cfg` - tun configuration options
+    ///
+    /// # Errors
+    /// * `InvalidConfigs` if the config fails [`TunConfig::validate`]
+    /// * `DeviceOpenFailed` / `DeviceCreateFailed` if `/dev/tun` can't be opened or its mode
+    ///   can't be set
     pub fn create(cfg: TunConfig) -> Result<Self, TunError> {
+        cfg.validate()?;
+
         // 1. create a new tun device by opening the special device `/dev/tun`
         let tun_dev_path = CStr::from_bytes_with_nul(TUN_DEVICE_PATH.as_ref())
             .map_err(|_| TunError::InvalidCString)?;
@@ -121,16 +141,30 @@ impl OsTun {
         // has exactly one null byte at the end of the string
         let fd = unsafe { libc::open(tun_dev_path.as_ptr(), libc::O_RDWR) };
         if fd == -1 {
-            return Err(TunError::IO(io::Error::last_os_error()));
+            return Err(TunError::DeviceOpenFailed {
+                name: cfg.name.clone().unwrap_or_default(),
+                op: "open /dev/tun",
+                source: io::Error::last_os_error(),
+            });
         }
 
-        // 2. set the device to broadcast mode (vs. point to point) w/ multicast
-        let flags: i32 = libc::IFF_BROADCAST | libc::IFF_MULTICAST;
+        // 2. set the device to broadcast mode (vs. point to point) w/ multicast, unless a
+        // peer address is configured
+        let flags: i32 = match cfg.peer {
+            Some(_) => libc::IFF_POINTOPOINT | libc::IFF_MULTICAST,
+            None => libc::IFF_BROADCAST | libc::IFF_MULTICAST,
+        };
 
         // SAFETY: ioctl has been verified using truss to be correct
         if unsafe { libc::ioctl(fd, TUNSIFMODE, &flags as *const i32) } == -1 {
+            let source = io::Error::last_os_error();
             tracing::error!("failed to set interface to broadcast mode");
-            return Err(TunError::Generic(Box::new(nix::errno::Errno::last())));
+            unsafe { libc::close(fd) };
+            return Err(TunError::DeviceCreateFailed {
+                name: cfg.name.clone().unwrap_or_default(),
+                op: "TUNSIFMODE",
+                source,
+            });
         }
 
         // 3. get the device name
@@ -141,7 +175,9 @@ impl OsTun {
 
         // SAFTEY: fd is guarenteed to be valid & statbuf is zerod
         if unsafe { libc::fstat(fd, statbuf.as_mut_ptr()) } == -1 {
-            return Err(TunError::IO(io::Error::last_os_error()));
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(TunError::IO(err));
         }
 
         // SAFETY: fstat() error code has been checked and is guarenteed
@@ -161,7 +197,9 @@ impl OsTun {
             )
         } == ptr::null_mut()
         {
-            return Err(TunError::IO(io::Error::last_os_error()));
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(TunError::IO(err));
         }
 
         // 4. create socket file descriptor used to configure interface
@@ -169,7 +207,9 @@ impl OsTun {
         // SAFETY: socket call uses standard parameters and return value is checked
         let sock_fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
         if sock_fd == -1 {
-            return Err(TunError::DeviceNotFound);
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(TunError::IO(err));
         }
 
         // 4. create OsTun instance
@@ -186,6 +226,24 @@ impl OsTun {
         Ok(tun)
     }
 
+    /// Returns the name of this device as assigned by the kernel (e.g., `tun0`)
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.name.len());
+
+        // device names assigned by devname_r() are ascii
+        std::str::from_utf8(&self.name[..len]).unwrap_or_default()
+    }
+
+    /// Returns the interface index of this device (0 if the interface no longer exists)
+    pub fn index(&self) -> u32 {
+        // SAFETY: name is guaranteed to be null-terminated
+        unsafe { libc::if_nametoindex(self.name.as_ptr() as *const libc::c_char) }
+    }
+
     /// Applies the tunnel config settings to this TUN device
     ///
     /// # Arguments
@@ -208,7 +266,12 @@ impl OsTun {
                         x => return Err(TunError::Ipv4InvalidCidr { cidr: x }),
                     };
 
-                    let broadcast = !mask | ip;
+                    // destination address in point-to-point mode
+                    let broadcast = match cfg.peer {
+                        Some(IpAddr::V4(peer)) => u32::from(peer),
+                        Some(peer) => return Err(TunError::InvalidPeer { peer }),
+                        None => !mask | ip,
+                    };
 
                     let req = IfAliasReq {
                         ifra_name: self.name.clone(),
@@ -280,7 +343,7 @@ impl OsTun {
 
         // SAFETY: ioctl has been verified using truss to be correct
         if unsafe { libc::ioctl(self.sock_fd, SIOCGIFFLAGS, &mut req as *mut _) } == -1 {
-            return Err(TunError::Generic(Box::new(nix::errno::Errno::last())));
+            return Err(TunError::IO(io::Error::last_os_error()));
         }
 
         Ok(req)
@@ -298,7 +361,7 @@ impl Tun for OsTun {
 
         // SAFETY: ioctl has been verified using truss to be correct
         if unsafe { libc::ioctl(self.sock_fd, SIOCSIFFLAGS, &req as *const _) } == -1 {
-            return Err(TunError::Generic(Box::new(nix::errno::Errno::last())));
+            return Err(TunError::IO(io::Error::last_os_error()));
         }
 
         Ok(())
@@ -310,7 +373,7 @@ impl Tun for OsTun {
 
         // SAFETY: ioctl has been verified using truss to be correct
         if unsafe { libc::ioctl(self.sock_fd, SIOCSIFFLAGS, &req as *const _) } == -1 {
-            return Err(TunError::Generic(Box::new(nix::errno::Errno::last())));
+            return Err(TunError::IO(io::Error::last_os_error()));
         }
 
         Ok(())
@@ -398,6 +461,10 @@ impl Tun for OsTun {
     fn blank_pktinfo(&self) -> Self::PktInfo {
         0
     }
+
+    fn poll_readable(&self, timeout: Duration) -> Result<bool, TunError> {
+        Ok(crate::poll_fd(self.fd, Some(timeout))?)
+    }
 }
 
 impl Drop for OsTun {
@@ -1,23 +1,49 @@
 //! Platform-agnostic TUN library
 
-use std::{
-    io::{self, Read, Write},
-    net::IpAddr,
-    sync::Arc,
-};
+use std::{io, net::IpAddr, sync::Arc, time::Duration};
 
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "linux")]
-pub use self::linux::OsTun;
+pub use self::linux::{
+    NameConflict, OsTun, Preflight, RouteAllGuard, RoutingRule, TunInfo, TunLink, TunOffloads,
+    RT_TABLE_MAIN,
+};
 
 #[cfg(target_os = "freebsd")]
 mod freebsd;
 #[cfg(target_os = "freebsd")]
 pub use self::freebsd::OsTun;
 
-//#[cfg(feature = "channel")]
-//mod channel;
+pub mod capture;
+pub mod checksum;
+mod cidr;
+pub mod codec;
+#[cfg(feature = "serde")]
+mod config_serde;
+pub mod crypto;
+mod dynamic;
+pub mod fdpass;
+pub mod frag;
+pub mod mock;
+
+#[cfg(feature = "smoltcp")]
+pub mod phy;
+
+pub mod replay;
+pub mod responder;
+
+#[cfg(all(target_os = "linux", feature = "seccomp"))]
+pub mod seccomp;
+
+pub mod tunnel;
+
+#[cfg(feature = "channel")]
+mod channel;
+#[cfg(feature = "channel")]
+pub use self::channel::ChannelTun;
+pub use self::cidr::IpCidr;
+pub use self::dynamic::{DynTun, PacketInfo, PktInfoConvert};
 
 #[derive(Clone, Debug)]
 pub struct TunDevice(Arc<OsTun>);
@@ -30,6 +56,12 @@ impl std::ops::Deref for TunDevice {
     }
 }
 
+impl std::fmt::Display for TunDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self.0.as_ref(), f)
+    }
+}
+
 impl TunDevice {
     pub fn create(cfg: TunConfig) -> Result<Self, TunError> {
         Ok(Self(Arc::new(OsTun::create(cfg)?)))
@@ -53,29 +85,225 @@ pub enum TunError {
     #[error("device name contains non-unicode (utf-8) characters")]
     DeviceNameNotUnicode,
 
-    #[error("failed to open tun device")]
-    DeviceOpenFailed,
+    #[error("device name is invalid: {reason}")]
+    DeviceNameInvalid { reason: &'static str },
+
+    #[error(
+        "failed to open tun device {name:?} ({op}): {source}{}",
+        hint_suffix(source)
+    )]
+    DeviceOpenFailed {
+        name: String,
+        op: &'static str,
+        source: io::Error,
+    },
+
+    #[error(
+        "failed to create tun device {name:?} ({op}): {source}{}",
+        hint_suffix(source)
+    )]
+    DeviceCreateFailed {
+        name: String,
+        op: &'static str,
+        source: io::Error,
+    },
+
+    #[error("failed to drop privileges ({op}): {source}")]
+    PrivilegeDropFailed { op: &'static str, source: io::Error },
 
-    #[error("failed to create tun device")]
-    DeviceCreateFailed,
+    #[error("failed to install seccomp filter ({op}): {source}")]
+    SeccompFailed { op: &'static str, source: io::Error },
 
-    #[error("failed to find device")]
-    DeviceNotFound,
+    #[error("failed to find device {name:?}")]
+    DeviceNotFound { name: String },
+
+    #[error("file descriptor does not refer to a tun device")]
+    NotTunDevice,
+
+    #[error("peer address {peer} requires a local address of the same family")]
+    InvalidPeer { peer: IpAddr },
+
+    #[error("duplicate address detection failed for {addr}")]
+    DadFailed { addr: IpAddr },
+
+    #[error("timed out after {timeout:?}")]
+    Timeout { timeout: Duration },
 
     #[error("cidr must be between 0 and 32, got {cidr}")]
     Ipv4InvalidCidr { cidr: u8 },
 
+    #[error("invalid address {value:?}: {reason}")]
+    InvalidAddress { value: String, reason: &'static str },
+
+    #[error("address {addr} is used more than once")]
+    DuplicateAddress { addr: IpAddr },
+
+    #[error("mtu must be between {min} and {max}, got {mtu}")]
+    MtuOutOfRange { mtu: u32, min: u32, max: u32 },
+
+    #[error("invalid config: {0}")]
+    InvalidConfig(&'static str),
+
+    #[error("invalid config: {}", join_errors(.0))]
+    InvalidConfigs(Vec<TunError>),
+
+    #[error("invalid value for `{field}`: {source}")]
+    InvalidConfigField {
+        field: String,
+        source: Box<TunError>,
+    },
+
     #[error("buffer too small")]
     BufferTooSmall,
 
     #[error("read didn't produce enough data")]
     NotEnoughData,
 
+    #[error("invalid packet: {0}")]
+    InvalidPacket(&'static str),
+
+    #[error("packet exceeds mtu ({mtu}) and has the don't fragment bit set")]
+    FragmentationNeeded { mtu: usize },
+
+    #[error("mtu too small to fragment packet, got {mtu}")]
+    MtuTooSmall { mtu: usize },
+
+    #[error("invalid capture file: {0}")]
+    InvalidCapture(&'static str),
+
+    #[error("failed to encrypt packet")]
+    EncryptionFailed,
+
+    #[error("failed to decrypt packet (corrupted or forged)")]
+    DecryptionFailed,
+
+    #[error("packet was already received (counter {counter})")]
+    ReplayedPacket { counter: u64 },
+
+    #[error("packet encrypted with unknown key (id {id})")]
+    UnknownKey { id: u8 },
+
     #[error("{0}")]
     IO(#[from] io::Error),
 
     #[error("tunnel error: {0}")]
-    Generic(Box<dyn std::error::Error>),
+    Generic(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Hint shown after permission errors
+pub(crate) const CAP_NET_ADMIN_HINT: &str = "run as root or grant the CAP_NET_ADMIN capability \
+     (e.g., `sudo setcap cap_net_admin+ep <binary>`)";
+
+/// Formats the hint for an OS error as ` (hint: ...)`, or nothing if there is none
+fn hint_suffix(source: &io::Error) -> String {
+    match os_error_hint(source.raw_os_error()) {
+        Some(hint) => format!(" (hint: {hint})"),
+        None => String::new(),
+    }
+}
+
+/// Returns an actionable hint for common errors when creating or configuring devices
+fn os_error_hint(errno: Option<i32>) -> Option<&'static str> {
+    match errno? {
+        libc::EPERM | libc::EACCES => Some(CAP_NET_ADMIN_HINT),
+        libc::ENOENT | libc::ENODEV => {
+            Some("the tun driver isn't available, load it with `modprobe tun`")
+        }
+        libc::EBUSY => Some("a device with this name already exists and is in use"),
+        _ => None,
+    }
+}
+
+/// Returns true if `fd` becomes readable before `timeout` expires (or waits forever if
+/// `None`)
+pub(crate) fn poll_fd(fd: std::os::unix::io::RawFd, timeout: Option<Duration>) -> io::Result<bool> {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    let timeout = match timeout {
+        Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as i32,
+        None => -1,
+    };
+
+    // SAFETY: pfd is a valid pollfd and we only pass a single entry
+    match unsafe { libc::poll(&mut pfd as *mut _, 1, timeout) } {
+        -1 => match io::Error::last_os_error() {
+            error if error.kind() == io::ErrorKind::Interrupted => Ok(false),
+            error => Err(error),
+        },
+        0 => Ok(false),
+        // hangups and errors are reported by the following read
+        _ => Ok(true),
+    }
+}
+
+/// Formats a list of errors as `first; second; ...`
+fn join_errors(errors: &[TunError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl TunError {
+    /// Wraps this error with the name of the config field that caused it
+    pub(crate) fn in_field(self, field: impl Into<String>) -> TunError {
+        TunError::InvalidConfigField {
+            field: field.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Returns the OS error code that caused this error, if any
+    ///
+    /// Includes errors reported by the kernel over netlink (e.g., when assigning addresses).
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            TunError::IO(source)
+            | TunError::DeviceOpenFailed { source, .. }
+            | TunError::DeviceCreateFailed { source, .. }
+            | TunError::PrivilegeDropFailed { source, .. }
+            | TunError::SeccompFailed { source, .. } => source.raw_os_error(),
+            TunError::InvalidConfigField { source, .. } => source.raw_os_error(),
+            #[cfg(target_os = "linux")]
+            TunError::Generic(error) => match error.downcast_ref::<neli::err::NlError>()? {
+                // the kernel reports negative errno values
+                neli::err::NlError::Nlmsgerr(e) => Some(e.error.abs()),
+                neli::err::NlError::Wrapped(neli::err::WrappedError::IOError(e)) => {
+                    e.raw_os_error()
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns true if this error was caused by missing privileges
+    ///
+    /// Creating devices and changing their configuration requires root or the
+    /// `CAP_NET_ADMIN` capability, see [`TunError::hint`].
+    pub fn is_permission_denied(&self) -> bool {
+        matches!(self.raw_os_error(), Some(libc::EPERM | libc::EACCES))
+            || matches!(self, TunError::IO(e) if e.kind() == io::ErrorKind::PermissionDenied)
+    }
+
+    /// Returns an actionable hint on how to fix this error, if there is one
+    ///
+    /// e.g., how to grant `CAP_NET_ADMIN` when permission was denied.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            // missing CAP_SETUID / CAP_SETGID, not CAP_NET_ADMIN
+            TunError::PrivilegeDropFailed { .. } | TunError::SeccompFailed { .. } => None,
+            _ if self.is_permission_denied() => Some(CAP_NET_ADMIN_HINT),
+            TunError::DeviceOpenFailed { source, .. }
+            | TunError::DeviceCreateFailed { source, .. } => os_error_hint(source.raw_os_error()),
+            _ => None,
+        }
+    }
 }
 
 pub trait Tun: Sized {
@@ -115,6 +343,21 @@ pub trait Tun: Sized {
     /// Useful for methods where you have to call `write_packet` but packet info hasn't been
     /// provided
     fn blank_pktinfo(&self) -> Self::PktInfo;
+
+    /// Waits until a packet is available to read
+    ///
+    /// Returns false if `timeout` expired first. Devices that can't wait report a packet as
+    /// available right away, so `read_packet` may still block.
+    ///
+    /// # Arguments
+    /// * `timeout` - Maximum amount of time to wait
+    ///
+    /// # Errors
+    /// * I/O if waiting on the device fails
+    fn poll_readable(&self, timeout: Duration) -> Result<bool, TunError> {
+        let _ = timeout;
+        Ok(true)
+    }
 }
 
 impl<T> Tun for Arc<T>
@@ -142,19 +385,62 @@ where
     fn blank_pktinfo(&self) -> Self::PktInfo {
         self.as_ref().blank_pktinfo()
     }
+
+    fn poll_readable(&self, timeout: Duration) -> Result<bool, TunError> {
+        self.as_ref().poll_readable(timeout)
+    }
 }
 
 /// Configuration for a new TUN device
-#[derive(Debug, Default)]
+///
+/// With the `serde` feature, configs can be read from (and written to) config files.
+/// Addresses and routes are CIDR strings (e.g., `"10.0.0.1/24"`) and IPv6 lifetimes are
+/// whole seconds. Invalid values are reported with the name of the offending field.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(
+        try_from = "config_serde::TunConfigRepr",
+        into = "config_serde::TunConfigRepr"
+    )
+)]
 pub struct TunConfig {
     /// IP address and subnet mask to assign TUN device
     pub(crate) ip: Option<(IpAddr, u8)>,
 
+    /// Remote peer address for point-to-point links
+    pub(crate) peer: Option<IpAddr>,
+
     /// Name to assign to this TUN interface
     pub(crate) name: Option<String>,
 
     /// Enables (or disables) additional packet info on read
     pub(crate) packet_info: bool,
+
+    /// Options applied when assigning an IPv6 address
+    pub(crate) ipv6: Ipv6AddrOptions,
+
+    /// How the kernel generates IPv6 link-local addresses
+    pub(crate) addr_gen_mode: Option<AddrGenMode>,
+
+    /// Value of the `disable_ipv6` sysctl
+    pub(crate) disable_ipv6: Option<bool>,
+
+    /// Value of the `accept_ra` sysctl
+    pub(crate) accept_ra: Option<bool>,
+
+    /// Value of the `rp_filter` sysctl
+    pub(crate) rp_filter: Option<RpFilter>,
+
+    /// MTU of the device
+    pub(crate) mtu: Option<u32>,
+
+    /// Routes (destination and CIDR) to add through the device
+    pub(crate) routes: Vec<(IpAddr, u8)>,
+
+    /// Brings the device up once configured
+    pub(crate) up: bool,
 }
 
 impl TunConfig {
@@ -172,13 +458,34 @@ impl TunConfig {
         self
     }
 
+    /// Sets the address of the remote end of a point-to-point link
+    ///
+    /// The address set with [`TunConfig::ip`] becomes the local address and the CIDR
+    /// applies to the peer, like `ip addr add 10.0.0.1 peer 10.0.0.2`. Requires an address
+    /// of the same family to be set with [`TunConfig::ip`].
+    ///
+    /// # Supported OSes:
+    /// * Linux
+    /// * FreeBSD (IPv4 only)
+    ///
+    /// # Arguments
+    /// * `peer` - Address of the remote end of the tunnel
+    pub fn peer(mut self, peer: impl Into<IpAddr>) -> Self {
+        self.peer = Some(peer.into());
+        self
+    }
+
     /// Sets the name of this interface
     ///
+    /// If not set, the OS picks a name. On Linux the name may be a template containing `%d`
+    /// (e.g., `vpn%d`), which the kernel replaces with the first free number. Use
+    /// `OsTun::name` to get the assigned name.
+    ///
     /// # Supported OSes:
     /// * Linux
     ///
     /// # Arguments
-    /// * `name` - Unique name to assign to this interface
+    /// * `name` - Unique name (or name template) to assign to this interface
     pub fn name(mut self, name: impl Into<String>) -> Self {
         self.name = Some(name.into());
         self
@@ -200,4 +507,498 @@ impl TunConfig {
         self.packet_info = enabled;
         self
     }
+
+    /// Sets the MTU of the device
+    ///
+    /// # Supported OSes:
+    /// * Linux
+    ///
+    /// # Arguments
+    /// * `mtu` - Maximum transmission unit in bytes
+    pub fn mtu(mut self, mtu: u32) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// Adds a route through the device to the main routing table
+    ///
+    /// Routes are only added to devices that are up, see [`TunConfig::up`]. May be called
+    /// multiple times to add several routes.
+    ///
+    /// # Supported OSes:
+    /// * Linux
+    ///
+    /// # Arguments
+    /// * `dst` - Destination network address
+    /// * `cidr` - CIDR of the destination network
+    pub fn route(mut self, dst: impl Into<IpAddr>, cidr: u8) -> Self {
+        self.routes.push((dst.into(), cidr));
+        self
+    }
+
+    /// Brings the device up once the other settings have been applied
+    ///
+    /// # Supported OSes:
+    /// * Linux
+    ///
+    /// # Arguments
+    /// * `up` - True to bring the device up
+    pub fn up(mut self, up: bool) -> Self {
+        self.up = up;
+        self
+    }
+
+    /// Sets options used when assigning an IPv6 address with [`TunConfig::ip`]
+    ///
+    /// Ignored for IPv4 addresses.
+    ///
+    /// # Supported OSes:
+    /// * Linux
+    ///
+    /// # Arguments
+    /// * `opts` - IPv6 address options
+    pub fn ipv6_options(mut self, opts: Ipv6AddrOptions) -> Self {
+        self.ipv6 = opts;
+        self
+    }
+
+    /// Sets how the kernel generates IPv6 link-local addresses for this interface
+    ///
+    /// [`AddrGenMode::None`] prevents the link-local address and the router solicitations
+    /// that would otherwise be read from the device once it comes up.
+    ///
+    /// # Supported OSes:
+    /// * Linux
+    ///
+    /// # Arguments
+    /// * `mode` - Address generation mode
+    pub fn addr_gen_mode(mut self, mode: AddrGenMode) -> Self {
+        self.addr_gen_mode = Some(mode);
+        self
+    }
+
+    /// Disables (or enables) IPv6 on this interface (`net.ipv6.conf.<name>.disable_ipv6`)
+    ///
+    /// # Supported OSes:
+    /// * Linux
+    ///
+    /// # Arguments
+    /// * `disabled` - True to disable IPv6
+    pub fn disable_ipv6(mut self, disabled: bool) -> Self {
+        self.disable_ipv6 = Some(disabled);
+        self
+    }
+
+    /// Enables (or disables) accepting router advertisements on this interface
+    /// (`net.ipv6.conf.<name>.accept_ra`)
+    ///
+    /// Router solicitations are only sent when router advertisements are accepted.
+    ///
+    /// # Supported OSes:
+    /// * Linux
+    ///
+    /// # Arguments
+    /// * `enabled` - True to accept router advertisements
+    pub fn accept_ra(mut self, enabled: bool) -> Self {
+        self.accept_ra = Some(enabled);
+        self
+    }
+
+    /// Sets the reverse path filtering mode of this interface
+    /// (`net.ipv4.conf.<name>.rp_filter`)
+    ///
+    /// # Supported OSes:
+    /// * Linux
+    ///
+    /// # Arguments
+    /// * `mode` - Reverse path filtering mode
+    pub fn rp_filter(mut self, mode: RpFilter) -> Self {
+        self.rp_filter = Some(mode);
+        self
+    }
+
+    /// Checks this config for problems before creating a device
+    ///
+    /// Called by `OsTun::create`. Checks:
+    /// * the name fits in `IFNAMSIZ`, contains no `/`, `:`, whitespace or nul bytes and has at
+    ///   most one `%d` template
+    /// * address and route prefix lengths are valid for their address family
+    /// * the peer has a local address of the same family and differs from it
+    /// * routes are listed once and the device is brought up to add them
+    /// * the MTU is in range, and large enough for IPv6 when an IPv6 address is assigned
+    /// * IPv6 options aren't combined with `disable_ipv6`
+    ///
+    /// # Errors
+    /// * `InvalidConfigs` listing every problem found, each an `InvalidConfigField` naming
+    ///   the offending field
+    pub fn validate(&self) -> Result<(), TunError> {
+        let mut errors = Vec::new();
+        let mut check = |field: &str, result: Result<(), TunError>| {
+            if let Err(e) = result {
+                errors.push(e.in_field(field));
+            }
+        };
+
+        if let Some(name) = &self.name {
+            check("name", validate_name(name));
+        }
+
+        if let Some((ip, prefix)) = self.ip {
+            check("ip", IpCidr::new(ip, prefix).map(drop));
+        }
+
+        match (self.ip, self.peer) {
+            (Some((ip, _)), Some(peer)) if ip == peer => {
+                check("peer", Err(TunError::DuplicateAddress { addr: peer }))
+            }
+            (Some((ip, _)), Some(peer)) if ip.is_ipv4() != peer.is_ipv4() => {
+                check("peer", Err(TunError::InvalidPeer { peer }))
+            }
+            (None, Some(peer)) => check("peer", Err(TunError::InvalidPeer { peer })),
+            _ => (),
+        }
+
+        for (i, &(dst, prefix)) in self.routes.iter().enumerate() {
+            let field = format!("routes[{i}]");
+            check(&field, IpCidr::new(dst, prefix).map(drop));
+            if self.routes[..i].contains(&(dst, prefix)) {
+                check(&field, Err(TunError::DuplicateAddress { addr: dst }));
+            }
+        }
+        if !self.routes.is_empty() && !self.up {
+            check(
+                "routes",
+                Err(TunError::InvalidConfig(
+                    "routes require the device to be brought up (`up`)",
+                )),
+            );
+        }
+
+        let ipv6 = matches!(self.ip, Some((IpAddr::V6(_), _)));
+        if let Some(mtu) = self.mtu {
+            // below 1280 the kernel disables IPv6 on the interface
+            let min = if ipv6 { 1280 } else { 68 };
+            if !(min..=65535).contains(&mtu) {
+                check(
+                    "mtu",
+                    Err(TunError::MtuOutOfRange {
+                        mtu,
+                        min,
+                        max: 65535,
+                    }),
+                );
+            }
+        }
+
+        if self.disable_ipv6 == Some(true) {
+            let conflict = if ipv6 {
+                Some("an IPv6 address can't be assigned with IPv6 disabled")
+            } else if self.routes.iter().any(|(dst, _)| dst.is_ipv6()) {
+                Some("IPv6 routes can't be added with IPv6 disabled")
+            } else if self.accept_ra == Some(true) || self.addr_gen_mode.is_some() {
+                Some("IPv6 autoconfiguration options have no effect with IPv6 disabled")
+            } else {
+                None
+            };
+
+            if let Some(conflict) = conflict {
+                check("disable_ipv6", Err(TunError::InvalidConfig(conflict)));
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(TunError::InvalidConfigs(errors)),
+        }
+    }
+}
+
+/// Checks a device name (or template) the way the kernel does in `dev_valid_name`
+fn validate_name(name: &str) -> Result<(), TunError> {
+    let invalid = |reason| Err(TunError::DeviceNameInvalid { reason });
+
+    if let Some(pos) = name.find('\0') {
+        return Err(TunError::DeviceNameContainsNuls { pos });
+    }
+
+    // IFNAMSIZ includes the trailing nul byte
+    let max = libc::IFNAMSIZ - 1;
+    if name.len() > max {
+        return Err(TunError::DeviceNameTooLong {
+            len: name.len(),
+            max,
+        });
+    }
+
+    if name == "." || name == ".." {
+        return invalid("`.` and `..` are reserved");
+    }
+    if name.contains(['/', ':']) || name.chars().any(char::is_whitespace) {
+        return invalid("must not contain `/`, `:` or whitespace");
+    }
+
+    // templates have a single `%d`, the kernel rejects any other `%`
+    if let Some((_, rest)) = name.split_once('%') {
+        if !rest.starts_with('d') || rest[1..].contains('%') {
+            return invalid("templates must contain a single `%d` and no other `%`");
+        }
+    }
+
+    Ok(())
+}
+
+/// How the kernel generates IPv6 link-local (and SLAAC) addresses for an interface
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum AddrGenMode {
+    /// Derive the interface identifier from the hardware address
+    Eui64 = 0,
+
+    /// Don't generate addresses (no link-local address or router solicitations)
+    None = 1,
+
+    /// Stable, privacy preserving identifiers (RFC 7217)
+    StablePrivacy = 2,
+
+    /// Random identifiers
+    Random = 3,
+}
+
+/// Reverse path filtering mode of an interface (see `rp_filter` in `ip-sysctl.txt`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum RpFilter {
+    /// No source validation
+    Off = 0,
+
+    /// Drop packets whose source isn't routed back through the receiving interface
+    Strict = 1,
+
+    /// Drop packets whose source isn't reachable through any interface
+    Loose = 2,
+}
+
+/// Options for assigning IPv6 addresses
+///
+/// By default addresses are permanent and go through duplicate address detection (DAD),
+/// leaving them tentative (unusable as a source address) for a short time after the
+/// device comes up. Use `OsTun::wait_address_ready` to wait for DAD to finish.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Ipv6AddrOptions {
+    /// Skip duplicate address detection (`IFA_F_NODAD`)
+    pub(crate) nodad: bool,
+
+    /// Don't create a prefix route for the address (`IFA_F_NOPREFIXROUTE`)
+    pub(crate) noprefixroute: bool,
+
+    /// Create temporary (privacy) addresses from this address (`IFA_F_MANAGETEMPADDR`)
+    pub(crate) managetempaddr: bool,
+
+    /// Valid and preferred lifetimes, permanent if not set
+    pub(crate) lifetimes: Option<(Duration, Duration)>,
+}
+
+impl Ipv6AddrOptions {
+    /// Disables (or enables) duplicate address detection
+    ///
+    /// # Arguments
+    /// * `enabled` - True to skip duplicate address detection
+    pub fn nodad(mut self, enabled: bool) -> Self {
+        self.nodad = enabled;
+        self
+    }
+
+    /// Disables (or enables) creating a prefix route for the address
+    ///
+    /// # Arguments
+    /// * `enabled` - True to skip the prefix route
+    pub fn noprefixroute(mut self, enabled: bool) -> Self {
+        self.noprefixroute = enabled;
+        self
+    }
+
+    /// Enables (or disables) managing temporary addresses based on this address
+    ///
+    /// # Arguments
+    /// * `enabled` - True to create temporary addresses
+    pub fn managetempaddr(mut self, enabled: bool) -> Self {
+        self.managetempaddr = enabled;
+        self
+    }
+
+    /// Sets the valid and preferred lifetimes of the address
+    ///
+    /// Lifetimes are rounded down to whole seconds. Once the preferred lifetime expires the
+    /// address is deprecated, once the valid lifetime expires it is removed.
+    ///
+    /// # Arguments
+    /// * `valid` - Time until the address is removed
+    /// * `preferred` - Time until the address is deprecated (at most `valid`)
+    pub fn lifetimes(mut self, valid: Duration, preferred: Duration) -> Self {
+        self.lifetimes = Some((valid, preferred.min(valid)));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    /// Returns the `(field, error)` pairs of a failed validation
+    fn problems(cfg: TunConfig) -> Vec<(String, TunError)> {
+        match cfg.validate() {
+            Err(TunError::InvalidConfigs(errors)) => errors
+                .into_iter()
+                .map(|e| match e {
+                    TunError::InvalidConfigField { field, source } => (field, *source),
+                    e => panic!("error without field: {e}"),
+                })
+                .collect(),
+            other => panic!("expected InvalidConfigs, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn error_details_and_hints() {
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<TunError>();
+
+        let err = TunError::DeviceCreateFailed {
+            name: "tun0".into(),
+            op: "TUNSETIFF",
+            source: io::Error::from_raw_os_error(libc::EPERM),
+        };
+        assert!(err.is_permission_denied());
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+        assert_eq!(err.hint(), Some(CAP_NET_ADMIN_HINT));
+        assert!(err
+            .to_string()
+            .starts_with("failed to create tun device \"tun0\" (TUNSETIFF): "));
+        assert!(err.to_string().contains("CAP_NET_ADMIN"), "{err}");
+
+        let err = TunError::DeviceOpenFailed {
+            name: String::new(),
+            op: "open /dev/net/tun",
+            source: io::Error::from_raw_os_error(libc::ENOENT),
+        };
+        assert!(!err.is_permission_denied());
+        assert!(err.hint().unwrap().contains("modprobe tun"));
+
+        // other errors don't guess at a cause
+        let err = TunError::IO(io::Error::from_raw_os_error(libc::ENOENT));
+        assert_eq!(err.hint(), None);
+        let err = TunError::IO(io::ErrorKind::PermissionDenied.into());
+        assert!(err.is_permission_denied());
+    }
+
+    #[test]
+    fn validate_config() {
+        let valid = TunConfig::default()
+            .name("vpn%d")
+            .ip([10, 0, 0, 1], 32)
+            .peer([10, 0, 0, 2])
+            .mtu(1420)
+            .route([10, 1, 0, 0], 16)
+            .route(Ipv6Addr::LOCALHOST, 128)
+            .up(true);
+        valid.validate().unwrap();
+        TunConfig::default().validate().unwrap();
+        TunConfig::default().name("").validate().unwrap();
+
+        for (name, reason) in [
+            ("a/b", "must not contain `/`, `:` or whitespace"),
+            ("eth0:1", "must not contain `/`, `:` or whitespace"),
+            ("tun 0", "must not contain `/`, `:` or whitespace"),
+            ("..", "`.` and `..` are reserved"),
+            (
+                "vpn%s",
+                "templates must contain a single `%d` and no other `%`",
+            ),
+            (
+                "vpn%d%d",
+                "templates must contain a single `%d` and no other `%`",
+            ),
+        ] {
+            let errors = problems(TunConfig::default().name(name));
+            assert!(
+                matches!(&errors[..], [(f, TunError::DeviceNameInvalid { reason: r })] if f == "name" && *r == reason),
+                "{name}: {errors:?}"
+            );
+        }
+        assert!(matches!(
+            &problems(TunConfig::default().name("sixteen-chars-xx"))[..],
+            [(_, TunError::DeviceNameTooLong { len: 16, max: 15 })]
+        ));
+        assert!(matches!(
+            &problems(TunConfig::default().name("tun\0"))[..],
+            [(_, TunError::DeviceNameContainsNuls { pos: 3 })]
+        ));
+
+        // every problem is reported at once, naming the field
+        let errors = problems(
+            TunConfig::default()
+                .ip(Ipv6Addr::LOCALHOST, 129)
+                .peer([10, 0, 0, 2])
+                .mtu(1000)
+                .route([10, 1, 0, 0], 33)
+                .route([10, 2, 0, 0], 16)
+                .route([10, 2, 0, 0], 16)
+                .disable_ipv6(true),
+        );
+        let fields: Vec<_> = errors.iter().map(|(f, _)| f.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "ip",
+                "peer",
+                "routes[0]",
+                "routes[2]",
+                "routes",
+                "mtu",
+                "disable_ipv6"
+            ]
+        );
+        assert!(matches!(errors[0].1, TunError::InvalidAddress { .. }));
+        assert!(matches!(errors[1].1, TunError::InvalidPeer { .. }));
+        assert!(matches!(errors[2].1, TunError::InvalidAddress { .. }));
+        assert!(matches!(errors[3].1, TunError::DuplicateAddress { .. }));
+        assert!(matches!(errors[4].1, TunError::InvalidConfig(_)));
+        assert!(matches!(
+            errors[5].1,
+            TunError::MtuOutOfRange {
+                mtu: 1000,
+                min: 1280,
+                ..
+            }
+        ));
+        assert!(matches!(errors[6].1, TunError::InvalidConfig(_)));
+
+        let errors = problems(
+            TunConfig::default()
+                .ip([10, 0, 0, 1], 24)
+                .peer([10, 0, 0, 1]),
+        );
+        assert!(matches!(errors[0].1, TunError::DuplicateAddress { .. }));
+
+        let err = TunConfig::default()
+            .peer([10, 0, 0, 2])
+            .mtu(40)
+            .validate()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: invalid value for `peer`: peer address 10.0.0.2 requires a local \
+             address of the same family; invalid value for `mtu`: mtu must be between 68 and \
+             65535, got 40"
+        );
+    }
 }
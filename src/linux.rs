@@ -1,27 +1,90 @@
-use crate::{Tun, TunConfig, TunError};
+use crate::{AddrGenMode, Ipv6AddrOptions, Tun, TunConfig, TunError};
 use neli::{
     consts::{
-        nl::{NlmF, NlmFFlags},
-        rtnl::{Arphrd, Ifa, IfaF, IfaFFlags, Iff, IffFlags, RtAddrFamily, RtScope, Rtm},
+        nl::{NlTypeWrapper, NlmF, NlmFFlags},
+        rtnl::{
+            Arphrd, Ifa, IfaFFlags, Iff, IffFlags, Ifla, RtAddrFamily, RtScope, RtTable, Rta, Rtm,
+            RtmF, RtmFFlags, Rtn, Rtprot,
+        },
         socket::NlFamily,
     },
     err::NlError,
     nl::{NlPayload, Nlmsghdr},
-    rtnl::{self, Ifaddrmsg, Rtattr},
+    rtnl::{self, Ifaddrmsg, Rtattr, Rtmsg},
     socket::NlSocketHandle,
     types::RtBuffer,
+    Nl,
 };
 
 use std::{
+    cmp,
     ffi::CString,
+    fmt,
+    fmt::Debug,
     io::{self, Read, Write},
-    net::IpAddr,
-    os::{raw::c_short, unix::io::RawFd},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::{
+        raw::c_short,
+        unix::io::{AsRawFd, RawFd},
+    },
+    sync::{Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
 };
 
 const TUNSETIFF: u64 = 0x4004_54ca;
+const TUNGETIFF: u64 = 0x8004_54d2;
+const TUNGETSNDBUF: u64 = 0x8004_54d3;
+const TUNGETVNETHDRSZ: u64 = 0x8004_54d7;
+const SIOCETHTOOL: u64 = 0x8946;
+const SIOCGIFMTU: u64 = 0x8921;
+
+// address flags that don't fit in `ifa_flags` and must be sent as IFA_FLAGS (linux/if_addr.h)
+const IFA_F_MANAGETEMPADDR: u32 = 0x100;
+const IFA_F_NOPREFIXROUTE: u32 = 0x200;
+
+// lifetime used for permanent addresses in IFA_CACHEINFO
+const INFINITY_LIFE_TIME: u32 = u32::MAX;
+
+// interval between checks while waiting for duplicate address detection
+const DAD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// attribute types nested in IFLA_AF_SPEC to set the address generation mode
+const AF_SPEC_INET6: u16 = libc::AF_INET6 as u16;
+const IFLA_INET6_ADDR_GEN_MODE: u16 = 8;
+
+// routing rule attributes (linux/fib_rules.h)
+const FRA_DST: u16 = 1;
+const FRA_SRC: u16 = 2;
+const FRA_PRIORITY: u16 = 6;
+const FRA_FWMARK: u16 = 10;
+const FRA_SUPPRESS_PREFIXLEN: u16 = 14;
+const FRA_TABLE: u16 = 15;
+const FRA_FWMASK: u16 = 16;
+const FRA_UID_RANGE: u16 = 20;
+
+// routing rule flags / actions (linux/fib_rules.h)
+const FIB_RULE_INVERT: u32 = 0x2;
+const FR_ACT_TO_TBL: u8 = 1;
+
+/// Main routing table (`ip route show table main`)
+pub const RT_TABLE_MAIN: u32 = 254;
+
+// link info attribute containing the link kind (linux/if_link.h)
+const IFLA_INFO_KIND: u16 = 1;
+
+// ethtool commands used to query offloads (linux/ethtool.h)
+const ETHTOOL_GTXCSUM: u32 = 0x16;
+const ETHTOOL_GSG: u32 = 0x18;
+const ETHTOOL_GTSO: u32 = 0x1e;
+const ETHTOOL_GGSO: u32 = 0x23;
 const CLONE_DEVICE_PATH: &[u8] = b"/dev/net/tun\0";
 
+// capabilities (linux/capability.h)
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+const CAP_SETGID: u32 = 6;
+const CAP_NET_ADMIN: u32 = 12;
+
 //const RTNLGRP_LINK: libc::c_uint = 1;
 //const RTNLGRP_IPV4_IFADDR: libc::c_uint = 5;
 //const RTNLGRP_IPV6_IFADDR: libc::c_uint = 9;
@@ -39,13 +102,346 @@ struct IfReq {
     _pad: [u8; 64],
 }
 
+#[repr(C)]
+struct IfReqData {
+    name: [u8; libc::IFNAMSIZ],
+    data: *mut libc::c_void,
+    _pad: [u8; 16],
+}
+
+#[repr(C)]
+struct IfReqMtu {
+    name: [u8; libc::IFNAMSIZ],
+    mtu: libc::c_int,
+    _pad: [u8; 20],
+}
+
+#[repr(C)]
+struct EthtoolValue {
+    cmd: u32,
+    data: u32,
+}
+
+impl IfReq {
+    /// Returns the (null-terminated) interface name stored in this request
+    ///
+    /// # Errors
+    /// * `DeviceNameNotUnicode` if the name is not valid utf-8
+    fn name(&self) -> Result<CString, TunError> {
+        let len = self
+            .name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.name.len());
+
+        std::str::from_utf8(&self.name[..len]).map_err(|_| TunError::DeviceNameNotUnicode)?;
+
+        // cannot fail, the name was truncated at the first null byte
+        CString::new(&self.name[..len]).map_err(|_| TunError::InvalidCString)
+    }
+}
+
+/// A TUN or TAP interface present on the system
+///
+/// Describes the interface only; use [`OsTun::create`] with the same name (or
+/// [`OsTun::from_fd`]) to attach to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TunLink {
+    /// Name of the interface (e.g., `tun0`)
+    pub name: String,
+
+    /// Interface index
+    pub index: u32,
+
+    /// True for TAP (layer-2) interfaces
+    pub tap: bool,
+
+    /// True if the interface is administratively up
+    pub up: bool,
+}
+
+impl TunLink {
+    /// Lists every TUN and TAP interface on the system
+    ///
+    /// Interfaces are found with a netlink `RTM_GETLINK` dump, keeping links whose kind
+    /// is `tun`. No privileges are required.
+    ///
+    /// # Errors
+    /// * I/O if the netlink socket fails to open
+    /// * If the netlink messages fail to send or parse
+    pub fn list() -> Result<Vec<Self>, TunError> {
+        let mut socket = NlSocketHandle::connect(NlFamily::Route, None, &[])?;
+        let msg = rtnl::Ifinfomsg::new(
+            RtAddrFamily::Unspecified,
+            Arphrd::Netrom,
+            0,
+            IffFlags::new(&[]),
+            IffFlags::new(&[]),
+            RtBuffer::new(),
+        );
+
+        let hdr = {
+            let len = None;
+            let nl_type = Rtm::Getlink;
+            let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Dump]);
+            let seq = None;
+            let pid = None;
+            let payload = msg;
+            Nlmsghdr::new(len, nl_type, flags, seq, pid, NlPayload::Payload(payload))
+        };
+
+        socket.send(hdr)?;
+
+        let mut links = Vec::new();
+        for msg in socket.iter::<rtnl::Ifinfomsg>(false) {
+            let msg = msg?;
+            let link = match msg.nl_payload {
+                NlPayload::Payload(link) => link,
+                _ => continue,
+            };
+
+            let is_tun = link
+                .rtattrs
+                .iter()
+                .find(|attr| attr.rta_type == Ifla::Linkinfo)
+                .map(|attr| link_kind(attr.rta_payload.as_ref()) == Some(&b"tun"[..]))
+                .unwrap_or(false);
+
+            let name = link
+                .rtattrs
+                .iter()
+                .find(|attr| attr.rta_type == Ifla::Ifname)
+                .map(|attr| {
+                    let name = attr.rta_payload.as_ref();
+                    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                    String::from_utf8_lossy(&name[..len]).into_owned()
+                });
+
+            if let (true, Some(name)) = (is_tun, name) {
+                links.push(Self {
+                    name,
+                    index: link.ifi_index as u32,
+                    tap: u16::from(link.ifi_type) == libc::ARPHRD_ETHER,
+                    up: link.ifi_flags.contains(&Iff::Up),
+                });
+            }
+        }
+
+        Ok(links)
+    }
+
+    /// Finds the TUN or TAP interface named `name`
+    ///
+    /// Returns `None` if no such interface exists (or it isn't a TUN/TAP interface).
+    ///
+    /// # Arguments
+    /// * `name` - Name of the interface (e.g., `tun0`)
+    ///
+    /// # Errors
+    /// * See [`TunLink::list`]
+    pub fn find_by_name(name: &str) -> Result<Option<Self>, TunError> {
+        Ok(Self::list()?.into_iter().find(|link| link.name == name))
+    }
+
+    /// Finds the TUN or TAP interface with index `index`
+    ///
+    /// Returns `None` if no such interface exists (or it isn't a TUN/TAP interface).
+    ///
+    /// # Arguments
+    /// * `index` - Interface index
+    ///
+    /// # Errors
+    /// * See [`TunLink::list`]
+    pub fn find_by_index(index: u32) -> Result<Option<Self>, TunError> {
+        Ok(Self::list()?.into_iter().find(|link| link.index == index))
+    }
+}
+
+impl fmt::Display for TunLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// Returns the kind (e.g., `tun`) from the nested attributes of an `IFLA_LINKINFO` attribute
+///
+/// # Arguments
+/// * `linkinfo` - Payload of the `IFLA_LINKINFO` attribute
+fn link_kind(mut linkinfo: &[u8]) -> Option<&[u8]> {
+    // each nested attribute: | len (2) | type (2) | payload | padding to 4 bytes |
+    while linkinfo.len() >= 4 {
+        let len = u16::from_ne_bytes([linkinfo[0], linkinfo[1]]) as usize;
+        let ty = u16::from_ne_bytes([linkinfo[2], linkinfo[3]]);
+        if len < 4 || len > linkinfo.len() {
+            return None;
+        }
+
+        if ty == IFLA_INFO_KIND {
+            let kind = &linkinfo[4..len];
+            let end = kind.iter().position(|&b| b == 0).unwrap_or(kind.len());
+            return Some(&kind[..end]);
+        }
+
+        linkinfo = &linkinfo[cmp::min((len + 3) & !3, linkinfo.len())..];
+    }
+
+    None
+}
+
+/// Kernel-side configuration of a TUN/TAP device (see [`OsTun::info`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TunInfo {
+    /// Raw `IFF_*` device flags (e.g., `libc::IFF_TUN | libc::IFF_NO_PI`)
+    pub flags: i32,
+
+    /// User allowed to attach to the device, if restricted
+    pub owner: Option<u32>,
+
+    /// Group allowed to attach to the device, if restricted
+    pub group: Option<u32>,
+
+    /// Size of the virtio-net header prepended to packets when `IFF_VNET_HDR` is set
+    pub vnet_hdr_size: usize,
+
+    /// Socket send buffer size in bytes (`i32::MAX` if unlimited)
+    pub sndbuf: i32,
+
+    /// Offloads currently enabled on the interface
+    pub offloads: TunOffloads,
+}
+
+impl TunInfo {
+    /// Returns true if this is a TAP (layer-2) device
+    pub fn is_tap(&self) -> bool {
+        self.flags & libc::IFF_TAP != 0
+    }
+
+    /// Returns true if packets are prepended with packet information (`IFF_NO_PI` unset)
+    pub fn packet_info(&self) -> bool {
+        self.flags & libc::IFF_NO_PI == 0
+    }
+
+    /// Returns true if packets are prepended with a virtio-net header
+    pub fn vnet_hdr(&self) -> bool {
+        self.flags & libc::IFF_VNET_HDR != 0
+    }
+
+    /// Returns true if the device supports multiple queues
+    pub fn multi_queue(&self) -> bool {
+        self.flags & libc::IFF_MULTI_QUEUE != 0
+    }
+
+    /// Returns true if the device outlives the process that created it
+    pub fn persistent(&self) -> bool {
+        self.flags & libc::IFF_PERSIST != 0
+    }
+}
+
+/// Offloads enabled on a TUN/TAP device
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TunOffloads {
+    /// Transmit checksum offload
+    pub tx_checksum: bool,
+
+    /// Scatter-gather
+    pub scatter_gather: bool,
+
+    /// TCP segmentation offload
+    pub tso: bool,
+
+    /// Generic segmentation offload
+    pub gso: bool,
+}
+
+/// Whether this process is able to create a TUN device (see [`OsTun::preflight`])
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Preflight {
+    /// Process has `CAP_NET_ADMIN` in its effective capability set
+    pub cap_net_admin: bool,
+
+    /// `/dev/net/tun` exists
+    pub clone_device_exists: bool,
+
+    /// `/dev/net/tun` can be opened for reading and writing
+    pub clone_device_accessible: bool,
+
+    /// An interface with the requested name already exists
+    pub name_exists: bool,
+
+    /// Problem with the requested device name, if any
+    pub name_conflict: Option<NameConflict>,
+}
+
+/// Why a requested device name can't be used (see [`Preflight`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameConflict {
+    /// An interface with this name exists but isn't a TUN device (e.g., a TAP device)
+    NotTun,
+
+    /// A persistent TUN device with this name is restricted to another user or group
+    OwnedByOther {
+        owner: Option<u32>,
+        group: Option<u32>,
+    },
+}
+
+impl Preflight {
+    /// Returns true if no problems were found
+    pub fn is_ok(&self) -> bool {
+        self.problems().is_empty()
+    }
+
+    /// Describes each problem found, with a hint on how to fix it
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.clone_device_exists {
+            problems.push(
+                "/dev/net/tun doesn't exist, load the tun driver with `modprobe tun`".to_owned(),
+            );
+        } else if !self.clone_device_accessible {
+            problems.push("/dev/net/tun can't be opened for reading and writing".to_owned());
+        }
+
+        match self.name_conflict {
+            Some(NameConflict::NotTun) => {
+                problems.push("an interface that isn't a tun device has this name".to_owned())
+            }
+            Some(NameConflict::OwnedByOther { owner, group }) => problems.push(format!(
+                "a persistent tun device with this name is restricted to user {} / group {}",
+                owner.map_or("(any)".to_owned(), |id| id.to_string()),
+                group.map_or("(any)".to_owned(), |id| id.to_string()),
+            )),
+            None => (),
+        }
+
+        // persistent devices owned by this user can be attached to without it
+        let attach = self.name_exists && self.name_conflict.is_none();
+        if !self.cap_net_admin && !attach {
+            problems.push(format!(
+                "missing CAP_NET_ADMIN, {}",
+                crate::CAP_NET_ADMIN_HINT
+            ));
+        }
+        problems
+    }
+}
+
+impl fmt::Display for Preflight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.problems().as_slice() {
+            [] => f.write_str("ok"),
+            problems => f.write_str(&problems.join("; ")),
+        }
+    }
+}
+
 /// A generic layer-3 tunnel using the OS's networking primitives
 #[derive(Debug)]
 pub struct OsTun {
     // opened file descriptor used to read/write to this device
     fd: RawFd,
 
-    // null-terminated device name string
+    // null-terminated device name string (guaranteed to be valid utf-8)
     name: CString,
 
     // index of inteface
@@ -53,6 +449,32 @@ pub struct OsTun {
 
     // set to true if packet info has been requested
     packet_info: bool,
+
+    // changes applied by `configure`, reverted when dropped
+    applied: Mutex<Vec<Change>>,
+}
+
+/// A change applied to the system by [`OsTun::configure`]
+#[derive(Debug)]
+enum Change {
+    /// Sysctl at `path` was changed from `previous`
+    Sysctl { path: String, previous: String },
+
+    /// Address was added
+    Address {
+        ip: IpAddr,
+        mask: u8,
+        peer: Option<IpAddr>,
+    },
+
+    /// Route was added to `table`
+    Route { dst: IpAddr, prefix: u8, table: u32 },
+
+    /// MTU was changed from `previous`
+    Mtu { previous: u32 },
+
+    /// Link was brought up
+    Up,
 }
 
 impl Read for OsTun {
@@ -81,6 +503,28 @@ impl Write for OsTun {
     }
 }
 
+impl AsRawFd for OsTun {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for OsTun {
+    fn drop(&mut self) {
+        let _ = self.rollback();
+
+        if unsafe { libc::close(self.fd) } == -1 {
+            tracing::error!("failed to close device fd");
+        }
+    }
+}
+
+impl fmt::Display for OsTun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 impl Tun for OsTun {
     // (number of bytes read, address family (if packet info))
     type PktInfo = (u16, u16);
@@ -210,19 +654,26 @@ impl Tun for OsTun {
     fn blank_pktinfo(&self) -> Self::PktInfo {
         (0, 0)
     }
+
+    fn poll_readable(&self, timeout: Duration) -> Result<bool, TunError> {
+        Ok(crate::poll_fd(self.fd, Some(timeout))?)
+    }
 }
 
 impl OsTun {
     /// Creates a new TUN device
     ///
-    /// If a TUN device named `name` does not already exist, one will be created.
+    /// If a TUN device named `name` does not already exist, one will be created. If no name
+    /// is configured, the kernel picks one (e.g., `tun0`). The name may also be a template
+    /// such as `vpn%d`, where the kernel replaces `%d` with the first free number. The
+    /// assigned name is available from [`OsTun::name`].
     ///
     /// Creating a new TUN device requires root privileges or `CAP_NET_ADMIN` to be set on
     /// the binary. To avoid requiring root privileges, a TUN device can be created using
     /// the `iproute2` package.
     ///
     /// To create a TUN device via `iproute` named `tun0` owned by user `fred`:
-    /// ```
+    /// ```text
     /// sudo ip tuntap add dev tun0 mode tun user fred
     /// ```
     ///
@@ -235,36 +686,34 @@ impl OsTun {
     /// * `cfg` - Tunnel device configuration
     ///
     /// # Errors
-    /// * `name` contains interior null bytes (aka not a c string)
-    /// * `name` is too long (longer than `libc::IFNAMSIZ`)
+    /// * `InvalidConfigs` if the config fails [`TunConfig::validate`]
+    /// * the assigned name contains non-unicode (utf-8) characters
     /// * not run as root user or with CAP_NET_ADMIN capability set
     /// * TUN device fails to create for other reasons
     pub fn create(cfg: TunConfig) -> Result<Self, TunError> {
+        cfg.validate()?;
         let mut cfg = cfg;
 
-        // ensure the device name is present (required on linux)
-        let name = match cfg.name.take() {
-            Some(name) => name,
-            None => return Err(TunError::DeviceNameRequired),
-        };
+        // an empty name lets the kernel pick one (same as the `tun%d` template)
+        let name = cfg.name.take().unwrap_or_default();
 
-        // sanity check length of device name and check for interior nulls
+        // validate() rejected interior nulls and names longer than IFNAMSIZ
         let name =
             CString::new(name.as_str()).map_err(|error| TunError::DeviceNameContainsNuls {
                 pos: error.nul_position(),
             })?;
-
         let name_bytes = name.as_bytes();
-        if name_bytes.len() > (libc::IFNAMSIZ - 1) {
-            return Err(TunError::DeviceNameTooLong {
-                len: name_bytes.len(),
-                max: libc::IFNAMSIZ,
-            });
-        }
+        let requested = || String::from_utf8_lossy(name_bytes).into_owned();
 
         // open clone device
         let fd: RawFd = match unsafe { libc::open(CLONE_DEVICE_PATH.as_ptr() as _, libc::O_RDWR) } {
-            -1 => return Err(TunError::DeviceOpenFailed),
+            -1 => {
+                return Err(TunError::DeviceOpenFailed {
+                    name: requested(),
+                    op: "open /dev/net/tun",
+                    source: io::Error::last_os_error(),
+                })
+            }
             x if x < -1 => unreachable!("unexcepted return value from open(): {}", x),
             fd => fd,
         };
@@ -285,17 +734,26 @@ impl OsTun {
         req.name[..name_bytes.len()].copy_from_slice(name_bytes);
 
         // create TUN device
-        if unsafe { libc::ioctl(fd, TUNSETIFF as _, &req) } < 0 {
-            return Err(TunError::DeviceCreateFailed);
+        if unsafe { libc::ioctl(fd, TUNSETIFF as _, &mut req) } < 0 {
+            let source = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(TunError::DeviceCreateFailed {
+                name: requested(),
+                op: "TUNSETIFF",
+                source,
+            });
         }
 
-        // fetch interface index
-        let index = match unsafe { libc::if_nametoindex(name.as_ptr()) } {
-            0 => return Err(TunError::DeviceNotFound),
-            x if x >= (i32::MAX as u32) => {
-                unreachable!("if_nametoindex returned negative value")
+        // the kernel writes back the assigned name (templates and unnamed devices)
+        let assigned = req
+            .name()
+            .and_then(|name| interface_index(&name).map(|index| (name, index)));
+        let (name, index) = match assigned {
+            Ok(assigned) => assigned,
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                return Err(e);
             }
-            idx => idx as i32,
         };
 
         let mut tun = Self {
@@ -303,111 +761,1338 @@ impl OsTun {
             name,
             index,
             packet_info: cfg.packet_info,
+            applied: Mutex::new(Vec::new()),
         };
         tun.configure(cfg)?;
         Ok(tun)
     }
 
-    /// Applies the tunnel config settings to this TUN device
-    ///
-    /// # Arguments
-    /// * `cfg` - Tunnel Configuration Options
-    pub fn configure(&mut self, cfg: TunConfig) -> Result<(), TunError> {
-        if let Some((ip, mask)) = cfg.ip {
-            self.assign_ip(ip, mask)?;
-        }
+    /// Returns the name of this device as assigned by the kernel
+    pub fn name(&self) -> &str {
+        // validated when the device was created / adopted
+        self.name.to_str().unwrap_or_default()
+    }
 
-        Ok(())
+    /// Returns the interface index of this device
+    pub fn index(&self) -> u32 {
+        self.index as u32
     }
 
-    /// Opens a netlink socket and binds the request multicast groups
+    /// Adopts an already open TUN device file descriptor
+    ///
+    /// Intended for processes that inherit the descriptor from a privileged parent or
+    /// receive it over a Unix socket (see [`crate::fdpass`]). The device name is queried with
+    /// `TUNGETIFF` and whether packet info is enabled is read from sysfs (falling back to the
+    /// `TUNGETIFF` flags, which can't always distinguish it). No privileges are required.
+    ///
+    /// The returned device takes ownership of `fd`.
     ///
     /// # Arguments
-    /// * `groups` - List of multicast groups to bind/listen
+    /// * `fd` - Open file descriptor attached to a TUN device
     ///
     /// # Errors
-    /// * I/O if the netlink socket fails to open
-    fn open_netlink_socket(&self, groups: &[u32]) -> Result<NlSocketHandle, TunError> {
-        // create netlink socket
-        let handle = NlSocketHandle::connect(
-            NlFamily::Route,
-            None,
-            groups,
-            //&[RTNLGRP_LINK, RTNLGRP_IPV4_IFADDR, RTNLGRP_IPV6_IFADDR],
-        )?;
+    /// * I/O if `fd` is not attached to a device (`TUNGETIFF` fails)
+    /// * `NotTunDevice` if `fd` is attached to a TAP device
+    /// * `DeviceNotFound` if the interface no longer exists
+    pub fn from_fd(fd: RawFd) -> Result<Self, TunError> {
+        let mut req = IfReq {
+            name: [0u8; libc::IFNAMSIZ],
+            flags: 0,
+            _pad: [0u8; 64],
+        };
 
-        Ok(handle)
+        if unsafe { libc::ioctl(fd, TUNGETIFF as _, &mut req) } < 0 {
+            return Err(TunError::IO(io::Error::last_os_error()));
+        }
+
+        let flags = req.flags as libc::c_int;
+        if flags & (libc::IFF_TUN | libc::IFF_TAP) != libc::IFF_TUN {
+            return Err(TunError::NotTunDevice);
+        }
+
+        let name = req.name()?;
+        let index = interface_index(&name)?;
+
+        // TUNGETIFF reports IFF_NOFILTER using the same bit as IFF_NO_PI, so prefer the
+        // device flags exposed in sysfs
+        let flags = sysfs_tun_flags(&name).unwrap_or(flags);
+
+        tracing::debug!(?name, index, flags, "adopted tun device from fd");
+        Ok(Self {
+            fd,
+            name,
+            index,
+            packet_info: flags & libc::IFF_NO_PI == 0,
+            applied: Mutex::new(Vec::new()),
+        })
     }
 
-    /// Assign an IP address to the tunnel
+    /// Queries the kernel-side configuration of this device
     ///
-    /// # Arguments
-    /// * `ip` - IP Address to assign (e.g., `192.168.70.100`)
-    /// * `mask` - CIDR / subnet mask (e.g., `24`)
+    /// Useful to verify a device that was attached to rather than created (e.g., using
+    /// [`OsTun::from_fd`] or an existing persistent device). No privileges are required.
     ///
     /// # Errors
-    /// * I/O if the netlink socket fails to open
-    /// * If the ip address is invalid
-    /// * If the subnet mask is inappropriate for the ip address
-    ///     * i.e., >32 for IPv4 or >128 for IPv6
-    /// * If the netlink message fails to send properly
-    fn assign_ip(&self, ip: IpAddr, mask: u8) -> Result<(), TunError> {
-        tracing::debug!("assigning ip {}/{} to tun device", ip, mask);
-        let mut socket = self.open_netlink_socket(&[])?;
-
-        // set ip on device
-        let msg = Ifaddrmsg {
-            ifa_family: match ip {
-                IpAddr::V4(_) => RtAddrFamily::Inet,
-                IpAddr::V6(_) => RtAddrFamily::Inet6,
-            },
-            ifa_prefixlen: mask,
-            ifa_flags: IfaFFlags::new(&[IfaF::Permanent]),
-            ifa_scope: RtScope::Universe.into(),
-            ifa_index: self.index,
-            rtattrs: {
-                let mut attrs = RtBuffer::new();
-                attrs.push(match ip {
-                    IpAddr::V4(ip) => Rtattr::new(None, Ifa::Address, &ip.octets()[..])?,
-                    IpAddr::V6(ip) => Rtattr::new(None, Ifa::Address, &ip.octets()[..])?,
-                });
-                attrs.push(match ip {
-                    IpAddr::V4(ip) => Rtattr::new(None, Ifa::Local, &ip.octets()[..])?,
-                    IpAddr::V6(ip) => Rtattr::new(None, Ifa::Local, &ip.octets()[..])?,
-                });
-                attrs
-            },
+    /// * I/O if any of the ioctls fail (e.g., the device was removed)
+    pub fn info(&self) -> Result<TunInfo, TunError> {
+        let mut req = IfReq {
+            name: [0u8; libc::IFNAMSIZ],
+            flags: 0,
+            _pad: [0u8; 64],
         };
 
-        let hdr = {
-            let len = None;
-            let nl_type = Rtm::Newaddr;
-            let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Create, NlmF::Excl]);
-            let seq = None;
-            let pid = None;
-            let payload = msg;
-            Nlmsghdr::new(len, nl_type, flags, seq, pid, NlPayload::Payload(payload))
-        };
+        if unsafe { libc::ioctl(self.fd, TUNGETIFF as _, &mut req) } < 0 {
+            return Err(TunError::IO(io::Error::last_os_error()));
+        }
 
-        socket.send(hdr)?;
+        let mut vnet_hdr_size: libc::c_int = 0;
+        if unsafe { libc::ioctl(self.fd, TUNGETVNETHDRSZ as _, &mut vnet_hdr_size) } < 0 {
+            return Err(TunError::IO(io::Error::last_os_error()));
+        }
 
-        Ok(())
-    }
-}
+        let mut sndbuf: libc::c_int = 0;
+        if unsafe { libc::ioctl(self.fd, TUNGETSNDBUF as _, &mut sndbuf) } < 0 {
+            return Err(TunError::IO(io::Error::last_os_error()));
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // see `from_fd` for why sysfs is preferred over the TUNGETIFF flags
+        let flags = sysfs_tun_flags(&self.name).unwrap_or(req.flags as libc::c_int);
 
-    #[test]
-    #[cfg_attr(not(feature = "root-tests"), ignore)]
-    fn root_create_tun_device() {
-        let _dev = OsTun::create(TunConfig::default().name("linux0"))
-            .expect("failed to create linux tun device");
+        Ok(TunInfo {
+            flags,
+            owner: sysfs_id(&self.name, "owner"),
+            group: sysfs_id(&self.name, "group"),
+            vnet_hdr_size: vnet_hdr_size as usize,
+            sndbuf,
+            offloads: self.offloads()?,
+        })
     }
 
-    #[test]
-    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    /// Queries the offloads enabled on this interface using ethtool ioctls
+    ///
+    /// # Errors
+    /// * I/O if the socket fails to open or an ioctl fails
+    fn offloads(&self) -> Result<TunOffloads, TunError> {
+        let sock = match unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) } {
+            -1 => return Err(TunError::IO(io::Error::last_os_error())),
+            sock => sock,
+        };
+
+        let query = |cmd: u32| -> io::Result<bool> {
+            let mut value = EthtoolValue { cmd, data: 0 };
+            let mut req = IfReqData {
+                name: [0u8; libc::IFNAMSIZ],
+                data: &mut value as *mut EthtoolValue as _,
+                _pad: [0u8; 16],
+            };
+
+            let name = self.name.as_bytes();
+            req.name[..name.len()].copy_from_slice(name);
+
+            match unsafe { libc::ioctl(sock, SIOCETHTOOL as _, &mut req) } {
+                -1 => Err(io::Error::last_os_error()),
+                _ => Ok(value.data != 0),
+            }
+        };
+
+        let offloads = (|| {
+            Ok::<_, io::Error>(TunOffloads {
+                tx_checksum: query(ETHTOOL_GTXCSUM)?,
+                scatter_gather: query(ETHTOOL_GSG)?,
+                tso: query(ETHTOOL_GTSO)?,
+                gso: query(ETHTOOL_GGSO)?,
+            })
+        })();
+
+        unsafe { libc::close(sock) };
+        Ok(offloads?)
+    }
+
+    /// Applies the tunnel config settings to this TUN device
+    ///
+    /// Configuration is transactional: if any step fails, the changes already applied by
+    /// this call are reverted before the error is returned. Changes are also reverted when
+    /// the device is dropped (see [`OsTun::rollback`] and [`OsTun::commit`]).
+    ///
+    /// # Arguments
+    /// * `cfg` - Tunnel Configuration Options
+    ///
+    /// # Errors
+    /// * `InvalidPeer` if a peer is set without a local address of the same family
+    /// * I/O if a sysctl can't be changed
+    /// * If the kernel rejects an address, route or link change
+    pub fn configure(&mut self, cfg: TunConfig) -> Result<(), TunError> {
+        let mut changes = Vec::new();
+        match self.apply(cfg, &mut changes) {
+            Ok(()) => {
+                self.lock_applied().append(&mut changes);
+                Ok(())
+            }
+            Err(error) => {
+                tracing::debug!(%error, "configuration failed, reverting {} changes", changes.len());
+                let _ = self.revert(changes);
+                Err(error)
+            }
+        }
+    }
+
+    /// Applies `cfg`, recording each change in `changes` once it succeeded
+    fn apply(&self, cfg: TunConfig, changes: &mut Vec<Change>) -> Result<(), TunError> {
+        // applied before addresses, disabling IPv6 removes any IPv6 addresses
+        if let Some(disabled) = cfg.disable_ipv6 {
+            changes.push(self.set_sysctl("ipv6", "disable_ipv6", disabled as u8)?);
+        }
+        if let Some(enabled) = cfg.accept_ra {
+            changes.push(self.set_sysctl("ipv6", "accept_ra", enabled as u8)?);
+        }
+        if let Some(mode) = cfg.rp_filter {
+            changes.push(self.set_sysctl("ipv4", "rp_filter", mode as u8)?);
+        }
+        if let Some(mode) = cfg.addr_gen_mode {
+            let path = format!("/proc/sys/net/ipv6/conf/{}/addr_gen_mode", self.name());
+            let previous = std::fs::read_to_string(&path)?.trim().to_owned();
+            self.set_addr_gen_mode(mode)?;
+            changes.push(Change::Sysctl { path, previous });
+        }
+
+        if let Some(mtu) = cfg.mtu {
+            let previous = self.mtu()?;
+            self.set_mtu(mtu)?;
+            changes.push(Change::Mtu { previous });
+        }
+
+        match (cfg.ip, cfg.peer) {
+            (Some((ip, mask)), peer) => {
+                self.assign_ip(ip, mask, peer, &cfg.ipv6)?;
+                changes.push(Change::Address { ip, mask, peer });
+            }
+            (None, Some(peer)) => return Err(TunError::InvalidPeer { peer }),
+            (None, None) => (),
+        }
+
+        if cfg.up {
+            // a device that was already up (e.g., persistent) stays up on rollback
+            let was_up = TunLink::find_by_name(self.name())?.is_some_and(|link| link.up);
+            self.set_link(&[Iff::Up], &[Iff::Up], RtBuffer::new())?;
+            if !was_up {
+                changes.push(Change::Up);
+            }
+        }
+
+        // routes through a device require it to be up
+        for (dst, prefix) in cfg.routes {
+            self.add_route(dst, prefix, RT_TABLE_MAIN)?;
+            changes.push(Change::Route {
+                dst,
+                prefix,
+                table: RT_TABLE_MAIN,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Reverts every change applied by [`OsTun::configure`]
+    ///
+    /// Reverting continues after a failure, the first error is returned.
+    ///
+    /// # Errors
+    /// * If a change fails to revert
+    pub fn rollback(&self) -> Result<(), TunError> {
+        let changes = std::mem::take(&mut *self.lock_applied());
+        self.revert(changes)
+    }
+
+    /// Keeps the changes applied by [`OsTun::configure`] when this device is dropped
+    ///
+    /// E.g., for persistent devices or when the device is handed off to another process.
+    pub fn commit(&self) {
+        self.lock_applied().clear();
+    }
+
+    /// Checks whether this process is able to create a device with `cfg`
+    ///
+    /// Reports on `CAP_NET_ADMIN`, access to `/dev/net/tun` and whether the requested name
+    /// is taken by an interface this process can't attach to. Use it to report a clear error
+    /// before calling [`OsTun::create`].
+    ///
+    /// # Arguments
+    /// * `cfg` - Configuration that will be passed to [`OsTun::create`]
+    pub fn preflight(cfg: &TunConfig) -> Preflight {
+        let cap_net_admin = effective_caps().is_ok_and(|caps| caps & (1 << CAP_NET_ADMIN) != 0);
+
+        let path = CLONE_DEVICE_PATH.as_ptr() as *const libc::c_char;
+        // SAFETY: path is null-terminated
+        let accessible = unsafe {
+            libc::faccessat(
+                libc::AT_FDCWD,
+                path,
+                libc::R_OK | libc::W_OK,
+                libc::AT_EACCESS,
+            )
+        } == 0;
+
+        // templates always pick a free name
+        let name = cfg
+            .name
+            .as_deref()
+            .filter(|name| !name.is_empty() && !name.contains('%'))
+            .and_then(|name| CString::new(name).ok())
+            .filter(|name| interface_index(name).is_ok());
+
+        let name_conflict = name.as_ref().and_then(|name| {
+            match sysfs_tun_flags(name) {
+                Some(flags) if flags & libc::IFF_TUN != 0 => (),
+                _ => return Some(NameConflict::NotTun),
+            }
+
+            // same check as the kernel's tun_not_capable()
+            let owner = sysfs_id(name, "owner");
+            let group = sysfs_id(name, "group");
+            let other_owner = owner.is_some_and(|uid| uid != unsafe { libc::geteuid() });
+            let other_group = group.is_some_and(|gid| !in_group(gid));
+            match (other_owner || other_group) && !cap_net_admin {
+                true => Some(NameConflict::OwnedByOther { owner, group }),
+                false => None,
+            }
+        });
+
+        Preflight {
+            cap_net_admin,
+            clone_device_exists: std::path::Path::new("/dev/net/tun").exists(),
+            clone_device_accessible: accessible,
+            name_exists: name.is_some(),
+            name_conflict,
+        }
+    }
+
+    /// Switches the process to `uid` / `gid` and clears all capabilities
+    ///
+    /// Call once the device is created and configured. Changes applied by
+    /// [`OsTun::configure`] are committed first, as they can't be reverted without
+    /// `CAP_NET_ADMIN`. The device can still be read from and written to.
+    ///
+    /// The user and groups change for the whole process, but capabilities are only cleared
+    /// for the calling thread. Call this before spawning other threads.
+    ///
+    /// # Arguments
+    /// * `uid` - User to switch to (e.g., `65534` for `nobody`)
+    /// * `gid` - Group to switch to, supplementary groups are cleared
+    ///
+    /// # Errors
+    /// * `PrivilegeDropFailed` if changing groups, user or capabilities fails (e.g.,
+    ///   missing `CAP_SETUID` / `CAP_SETGID` to switch to another user, or supplementary
+    ///   groups are set without `CAP_SETGID` to clear them)
+    pub fn drop_privileges(&self, uid: u32, gid: u32) -> Result<(), TunError> {
+        let check = |op, res: libc::c_int| match res {
+            -1 => Err(TunError::PrivilegeDropFailed {
+                op,
+                source: io::Error::last_os_error(),
+            }),
+            _ => Ok(()),
+        };
+
+        self.commit();
+        tracing::debug!("dropping privileges to uid {} / gid {}", uid, gid);
+
+        // capabilities must not survive the user change
+        check("prctl(PR_SET_KEEPCAPS)", unsafe {
+            libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0)
+        })?;
+        // EINVAL on kernels without ambient capabilities (< 4.3), nothing to clear
+        let _ = unsafe {
+            libc::prctl(
+                libc::PR_CAP_AMBIENT,
+                libc::PR_CAP_AMBIENT_CLEAR_ALL,
+                0,
+                0,
+                0,
+            )
+        };
+
+        // supplementary groups can only be changed with CAP_SETGID, keeping them would
+        // leave the process with more access than `gid` grants
+        if effective_caps().is_ok_and(|caps| caps & (1 << CAP_SETGID) != 0) {
+            check("setgroups", unsafe { libc::setgroups(0, std::ptr::null()) })?;
+        } else if unsafe { libc::getgroups(0, std::ptr::null_mut()) } > 0 {
+            return Err(TunError::PrivilegeDropFailed {
+                op: "setgroups",
+                source: io::Error::from_raw_os_error(libc::EPERM),
+            });
+        }
+        check("setresgid", unsafe { libc::setresgid(gid, gid, gid) })?;
+        check("setresuid", unsafe { libc::setresuid(uid, uid, uid) })?;
+
+        let header = CapHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let data = [CapData::default(); 2];
+        // SAFETY: header and data match the layout expected by version 3 of capset
+        check("capset", unsafe {
+            libc::syscall(libc::SYS_capset, &header, data.as_ptr()) as libc::c_int
+        })?;
+
+        Ok(())
+    }
+
+    /// Reverts `changes` in reverse order
+    ///
+    /// # Errors
+    /// * The first error encountered (later changes are still reverted)
+    fn revert(&self, changes: Vec<Change>) -> Result<(), TunError> {
+        let mut result = Ok(());
+        for change in changes.into_iter().rev() {
+            tracing::debug!(?change, "reverting change");
+            let res = match &change {
+                Change::Sysctl { path, previous } => {
+                    std::fs::write(path, previous).map_err(TunError::from)
+                }
+                Change::Address { ip, mask, peer } => self.remove_ip(*ip, *mask, *peer),
+                Change::Route { dst, prefix, table } => self.delete_route(*dst, *prefix, *table),
+                Change::Mtu { previous } => self.set_mtu(*previous),
+                Change::Up => self.set_link(&[], &[Iff::Up], RtBuffer::new()),
+            };
+
+            if let Err(error) = res {
+                tracing::warn!(?change, %error, "failed to revert change");
+                result = result.and(Err(error));
+            }
+        }
+
+        result
+    }
+
+    fn lock_applied(&self) -> MutexGuard<'_, Vec<Change>> {
+        self.applied.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Assigns a point-to-point address with a distinct remote peer to the tunnel
+    ///
+    /// Equivalent to `ip addr add <local> peer <peer>/<mask> dev <name>`. The prefix applies
+    /// to the peer address, so a route to the peer (or its network) is installed.
+    ///
+    /// # Arguments
+    /// * `local` - Local address of this end of the tunnel (e.g., `10.0.0.1`)
+    /// * `peer` - Address of the remote end of the tunnel (e.g., `10.0.0.2`)
+    /// * `mask` - CIDR / subnet mask of the peer (e.g., `32`)
+    ///
+    /// # Errors
+    /// * `InvalidPeer` if the addresses are not of the same family
+    /// * See [`OsTun::configure`]
+    pub fn add_peer_address(
+        &self,
+        local: impl Into<IpAddr>,
+        peer: impl Into<IpAddr>,
+        mask: u8,
+    ) -> Result<(), TunError> {
+        self.assign_ip(
+            local.into(),
+            mask,
+            Some(peer.into()),
+            &Ipv6AddrOptions::default(),
+        )
+    }
+
+    /// Assigns an IPv6 address to the tunnel using the provided options
+    ///
+    /// # Arguments
+    /// * `ip` - IPv6 address to assign (e.g., `fd00::1`)
+    /// * `mask` - CIDR / subnet mask (e.g., `64`)
+    /// * `opts` - Address flags and lifetimes
+    ///
+    /// # Errors
+    /// * See [`OsTun::configure`]
+    pub fn add_ipv6_address(
+        &self,
+        ip: Ipv6Addr,
+        mask: u8,
+        opts: &Ipv6AddrOptions,
+    ) -> Result<(), TunError> {
+        self.assign_ip(ip.into(), mask, None, opts)
+    }
+
+    /// Waits until duplicate address detection for `ip` has finished
+    ///
+    /// IPv6 addresses are tentative (and can't be used as a source address) until DAD
+    /// completes, which only starts once the device is up. Returns immediately for
+    /// addresses that aren't tentative (e.g., IPv4 or `nodad` addresses).
+    ///
+    /// # Arguments
+    /// * `ip` - Address assigned to this device
+    /// * `timeout` - Maximum time to wait
+    ///
+    /// # Errors
+    /// * `DadFailed` if another host on the link uses the address
+    /// * `Timeout` if the address is still tentative (or not assigned) after `timeout`
+    /// * If the netlink messages fail to send or parse
+    pub fn wait_address_ready(&self, ip: IpAddr, timeout: Duration) -> Result<(), TunError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.address_flags(ip)? {
+                Some(flags) if flags & libc::IFA_F_DADFAILED != 0 => {
+                    return Err(TunError::DadFailed { addr: ip })
+                }
+                Some(flags) if flags & libc::IFA_F_TENTATIVE == 0 => return Ok(()),
+                _ if Instant::now() >= deadline => return Err(TunError::Timeout { timeout }),
+                _ => thread::sleep(DAD_POLL_INTERVAL),
+            }
+        }
+    }
+
+    /// Returns the `IFA_F_*` flags of the address `ip` on this device, if assigned
+    ///
+    /// # Errors
+    /// * If the netlink messages fail to send or parse
+    fn address_flags(&self, ip: IpAddr) -> Result<Option<u32>, TunError> {
+        let mut socket = self.open_netlink_socket(&[])?;
+        let msg = Ifaddrmsg {
+            ifa_family: match ip {
+                IpAddr::V4(_) => RtAddrFamily::Inet,
+                IpAddr::V6(_) => RtAddrFamily::Inet6,
+            },
+            ifa_prefixlen: 0,
+            ifa_flags: IfaFFlags::empty(),
+            ifa_scope: 0,
+            ifa_index: self.index,
+            rtattrs: RtBuffer::new(),
+        };
+
+        let hdr = {
+            let len = None;
+            let nl_type = Rtm::Getaddr;
+            let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Dump]);
+            let seq = None;
+            let pid = None;
+            let payload = msg;
+            Nlmsghdr::new(len, nl_type, flags, seq, pid, NlPayload::Payload(payload))
+        };
+
+        socket.send(hdr)?;
+
+        let octets = addr_octets(ip);
+
+        let mut found = None;
+        for msg in socket.iter::<Ifaddrmsg>(false) {
+            let addr = match msg?.nl_payload {
+                NlPayload::Payload(addr) if addr.ifa_index == self.index => addr,
+                _ => continue,
+            };
+
+            let matches = addr.rtattrs.iter().any(|attr| {
+                (attr.rta_type == Ifa::Local || attr.rta_type == Ifa::Address)
+                    && attr.rta_payload.as_ref() == octets.as_slice()
+            });
+
+            if matches && found.is_none() {
+                // IFA_FLAGS carries the full set of flags, ifa_flags only the lower 8 bits
+                let flags = addr
+                    .rtattrs
+                    .iter()
+                    .find(|attr| attr.rta_type == Ifa::Flags)
+                    .and_then(|attr| attr.rta_payload.as_ref().try_into().ok())
+                    .map(u32::from_ne_bytes)
+                    .unwrap_or_else(|| u8::try_from(&addr.ifa_flags).unwrap_or(0) as u32);
+                found = Some(flags);
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Sets how the kernel generates IPv6 link-local addresses for this interface
+    ///
+    /// Only affects addresses generated after the change (i.e., the next time the device
+    /// comes up).
+    ///
+    /// # Arguments
+    /// * `mode` - Address generation mode
+    ///
+    /// # Errors
+    /// * I/O if the netlink socket fails to open
+    /// * If the netlink message fails to send properly
+    pub fn set_addr_gen_mode(&self, mode: AddrGenMode) -> Result<(), TunError> {
+        tracing::debug!(?mode, "setting addr_gen_mode on tun device");
+
+        // | AF_INET6 (len 12) | IFLA_INET6_ADDR_GEN_MODE (len 5) | mode | padding (3) |
+        let mut af_spec = [0u8; 12];
+        af_spec[0..2].copy_from_slice(&12u16.to_ne_bytes());
+        af_spec[2..4].copy_from_slice(&AF_SPEC_INET6.to_ne_bytes());
+        af_spec[4..6].copy_from_slice(&5u16.to_ne_bytes());
+        af_spec[6..8].copy_from_slice(&IFLA_INET6_ADDR_GEN_MODE.to_ne_bytes());
+        af_spec[8] = mode as u8;
+
+        let mut attrs = RtBuffer::new();
+        attrs.push(Rtattr::new(None, Ifla::AfSpec, &af_spec[..])?);
+        self.set_link(&[], &[], attrs)
+    }
+
+    /// Returns the MTU of this device
+    ///
+    /// # Errors
+    /// * I/O if the ioctl fails
+    pub fn mtu(&self) -> Result<u32, TunError> {
+        let sock = match unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) } {
+            -1 => return Err(TunError::IO(io::Error::last_os_error())),
+            sock => sock,
+        };
+
+        let mut req = IfReqMtu {
+            name: [0u8; libc::IFNAMSIZ],
+            mtu: 0,
+            _pad: [0u8; 20],
+        };
+        let name = self.name.as_bytes();
+        req.name[..name.len()].copy_from_slice(name);
+
+        let res = unsafe { libc::ioctl(sock, SIOCGIFMTU as _, &mut req) };
+        let error = io::Error::last_os_error();
+        unsafe { libc::close(sock) };
+
+        match res {
+            -1 => Err(TunError::IO(error)),
+            _ => Ok(req.mtu as u32),
+        }
+    }
+
+    /// Sets the MTU of this device
+    ///
+    /// # Arguments
+    /// * `mtu` - Maximum transmission unit in bytes
+    ///
+    /// # Errors
+    /// * I/O if the netlink socket fails to open
+    /// * If the kernel rejects the MTU (e.g., below 68 with IPv4 addresses assigned)
+    pub fn set_mtu(&self, mtu: u32) -> Result<(), TunError> {
+        tracing::debug!(mtu, "setting mtu on tun device");
+        let mut attrs = RtBuffer::new();
+        attrs.push(Rtattr::new(None, Ifla::Mtu, mtu)?);
+        self.set_link(&[], &[], attrs)
+    }
+
+    /// Sets a per-interface sysctl (`/proc/sys/net/<family>/conf/<name>/<key>`)
+    ///
+    /// # Arguments
+    /// * `family` - Protocol family (`ipv4` or `ipv6`)
+    /// * `key` - Name of the sysctl (e.g., `accept_ra`)
+    /// * `value` - Value to write
+    ///
+    /// # Returns
+    /// The previous value of the sysctl
+    ///
+    /// # Errors
+    /// * I/O if the sysctl doesn't exist or can't be read / written
+    fn set_sysctl(&self, family: &str, key: &str, value: u8) -> Result<Change, TunError> {
+        let path = format!("/proc/sys/net/{}/conf/{}/{}", family, self.name(), key);
+        let previous = std::fs::read_to_string(&path)?.trim().to_owned();
+        tracing::debug!(%path, value, %previous, "setting sysctl");
+        std::fs::write(&path, value.to_string())?;
+        Ok(Change::Sysctl { path, previous })
+    }
+
+    /// Adds a route through this device to a routing table
+    ///
+    /// Equivalent to `ip route add <dst>/<prefix> dev <name> table <table>`.
+    ///
+    /// # Arguments
+    /// * `dst` - Destination network (e.g., `0.0.0.0` for a default route)
+    /// * `prefix` - CIDR of the destination network
+    /// * `table` - Routing table (e.g., [`RT_TABLE_MAIN`])
+    ///
+    /// # Errors
+    /// * I/O if the netlink socket fails to open
+    /// * If the kernel rejects the route (e.g., it already exists)
+    pub fn add_route(&self, dst: IpAddr, prefix: u8, table: u32) -> Result<(), TunError> {
+        tracing::debug!(
+            "adding route {}/{} via {} (table {})",
+            dst,
+            prefix,
+            self,
+            table
+        );
+        netlink_request(
+            Rtm::Newroute,
+            &[NlmF::Create, NlmF::Excl],
+            route_msg(dst, prefix, table, self.index)?,
+        )
+    }
+
+    /// Removes a route through this device from a routing table
+    ///
+    /// # Arguments
+    /// * `dst` - Destination network
+    /// * `prefix` - CIDR of the destination network
+    /// * `table` - Routing table
+    ///
+    /// # Errors
+    /// * I/O if the netlink socket fails to open
+    /// * If the kernel rejects the request (e.g., the route doesn't exist)
+    pub fn delete_route(&self, dst: IpAddr, prefix: u8, table: u32) -> Result<(), TunError> {
+        tracing::debug!(
+            "deleting route {}/{} via {} (table {})",
+            dst,
+            prefix,
+            self,
+            table
+        );
+        netlink_request(
+            Rtm::Delroute,
+            &[],
+            route_msg(dst, prefix, table, self.index)?,
+        )
+    }
+
+    /// Routes all traffic through this device except traffic to the `except` networks
+    ///
+    /// Adds IPv4 and IPv6 default routes through this device to `table`, then a rule per
+    /// excluded network (`to <net> lookup main`) at `priority` and a catch-all rule
+    /// (`lookup <table>`) at `priority + 1`. The excluded networks usually include the
+    /// tunnel endpoint, so encapsulated traffic doesn't loop through the device. IPv6 must
+    /// be enabled on the device.
+    ///
+    /// Everything is removed again when the returned guard is dropped. If any step fails,
+    /// the steps already applied are reverted.
+    ///
+    /// # Arguments
+    /// * `table` - Dedicated routing table for the tunnel (e.g., `51820`)
+    /// * `priority` - Priority of the rules (lower values are evaluated first)
+    /// * `except` - Networks (address and CIDR) to keep routing through the main table
+    ///
+    /// # Errors
+    /// * If a route or rule fails to add
+    pub fn route_all_except(
+        &self,
+        table: u32,
+        priority: u32,
+        except: &[(IpAddr, u8)],
+    ) -> Result<RouteAllGuard, TunError> {
+        let mut guard = RouteAllGuard {
+            index: self.index,
+            routes: Vec::new(),
+            rules: Vec::new(),
+        };
+
+        for dst in [
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        ] {
+            self.add_route(dst, 0, table)?;
+            guard.routes.push((dst, 0, table));
+        }
+
+        let excluded = except.iter().map(|&(net, prefix)| {
+            RoutingRule::new(RT_TABLE_MAIN)
+                .to(net, prefix)
+                .priority(priority)
+        });
+        let catch_all = [false, true].into_iter().map(|ipv6| {
+            RoutingRule::new(table)
+                .ipv6(ipv6)
+                .priority(priority.saturating_add(1))
+        });
+
+        for rule in excluded.chain(catch_all) {
+            rule.add()?;
+            guard.rules.push(rule);
+        }
+
+        Ok(guard)
+    }
+
+    /// Opens a netlink socket and binds the request multicast groups
+    ///
+    /// # Arguments
+    /// * `groups` - List of multicast groups to bind/listen
+    ///
+    /// # Errors
+    /// * I/O if the netlink socket fails to open
+    fn open_netlink_socket(&self, groups: &[u32]) -> Result<NlSocketHandle, TunError> {
+        // create netlink socket
+        let handle = NlSocketHandle::connect(
+            NlFamily::Route,
+            None,
+            groups,
+            //&[RTNLGRP_LINK, RTNLGRP_IPV4_IFADDR, RTNLGRP_IPV6_IFADDR],
+        )?;
+
+        Ok(handle)
+    }
+
+    /// Assign an IP address to the tunnel
+    ///
+    /// # Arguments
+    /// * `ip` - IP Address to assign (e.g., `192.168.70.100`)
+    /// * `mask` - CIDR / subnet mask (e.g., `24`)
+    /// * `peer` - Remote address for point-to-point links (defaults to `ip`)
+    /// * `opts` - Flags and lifetimes (IPv6 only)
+    ///
+    /// # Errors
+    /// * I/O if the netlink socket fails to open
+    /// * `InvalidPeer` if `peer` is not of the same family as `ip`
+    /// * If the ip address is invalid
+    /// * If the subnet mask is inappropriate for the ip address
+    ///     * i.e., >32 for IPv4 or >128 for IPv6
+    /// * If the netlink message fails to send properly
+    fn assign_ip(
+        &self,
+        ip: IpAddr,
+        mask: u8,
+        peer: Option<IpAddr>,
+        opts: &Ipv6AddrOptions,
+    ) -> Result<(), TunError> {
+        tracing::debug!(?peer, "assigning ip {}/{} to tun device", ip, mask);
+        let msg = self.addr_msg(ip, mask, peer, opts)?;
+        netlink_request(Rtm::Newaddr, &[NlmF::Create, NlmF::Excl], msg)
+    }
+
+    /// Removes an IP address from the tunnel
+    ///
+    /// # Arguments
+    /// * `ip` - IP Address to remove
+    /// * `mask` - CIDR / subnet mask it was assigned with
+    /// * `peer` - Remote address it was assigned with (point-to-point links)
+    ///
+    /// # Errors
+    /// * I/O if the netlink socket fails to open
+    /// * If the address isn't assigned to the tunnel
+    fn remove_ip(&self, ip: IpAddr, mask: u8, peer: Option<IpAddr>) -> Result<(), TunError> {
+        tracing::debug!(?peer, "removing ip {}/{} from tun device", ip, mask);
+        let msg = self.addr_msg(ip, mask, peer, &Ipv6AddrOptions::default())?;
+        netlink_request(Rtm::Deladdr, &[], msg)
+    }
+
+    /// Builds the netlink message to add / remove an address (see [`OsTun::assign_ip`])
+    fn addr_msg(
+        &self,
+        ip: IpAddr,
+        mask: u8,
+        peer: Option<IpAddr>,
+        opts: &Ipv6AddrOptions,
+    ) -> Result<Ifaddrmsg, TunError> {
+        // IFA_ADDRESS is the remote end on point-to-point links, IFA_LOCAL the local end
+        let address = peer.unwrap_or(ip);
+        if address.is_ipv4() != ip.is_ipv4() {
+            return Err(TunError::InvalidPeer { peer: address });
+        }
+
+        let opts = match ip {
+            IpAddr::V4(_) => Ipv6AddrOptions::default(),
+            IpAddr::V6(_) => *opts,
+        };
+
+        let mut flags = match opts.lifetimes {
+            Some(_) => 0,
+            None => libc::IFA_F_PERMANENT,
+        };
+        if opts.nodad {
+            flags |= libc::IFA_F_NODAD;
+        }
+        if opts.noprefixroute {
+            flags |= IFA_F_NOPREFIXROUTE;
+        }
+        if opts.managetempaddr {
+            flags |= IFA_F_MANAGETEMPADDR;
+        }
+
+        Ok(Ifaddrmsg {
+            ifa_family: match ip {
+                IpAddr::V4(_) => RtAddrFamily::Inet,
+                IpAddr::V6(_) => RtAddrFamily::Inet6,
+            },
+            ifa_prefixlen: mask,
+            ifa_flags: IfaFFlags::from(flags as u8),
+            ifa_scope: RtScope::Universe.into(),
+            ifa_index: self.index,
+            rtattrs: {
+                let mut attrs = RtBuffer::new();
+                attrs.push(Rtattr::new(None, Ifa::Address, &addr_octets(address)[..])?);
+                attrs.push(Rtattr::new(None, Ifa::Local, &addr_octets(ip)[..])?);
+                attrs.push(Rtattr::new(None, Ifa::Flags, flags)?);
+                if let Some((valid, preferred)) = opts.lifetimes {
+                    // struct ifa_cacheinfo { prefered, valid, cstamp, tstamp }
+                    let lifetime =
+                        |d: Duration| u32::try_from(d.as_secs()).unwrap_or(INFINITY_LIFE_TIME);
+                    let mut cacheinfo = [0u8; 16];
+                    cacheinfo[0..4].copy_from_slice(&lifetime(preferred).to_ne_bytes());
+                    cacheinfo[4..8].copy_from_slice(&lifetime(valid).to_ne_bytes());
+                    attrs.push(Rtattr::new(None, Ifa::Cacheinfo, &cacheinfo[..])?);
+                }
+                attrs
+            },
+        })
+    }
+
+    /// Sends a `RTM_SETLINK` request for this device
+    ///
+    /// # Arguments
+    /// * `flags` - Link flags to set
+    /// * `change` - Link flags to change (flags not in `change` are left as is)
+    /// * `attrs` - Link attributes to set
+    ///
+    /// # Errors
+    /// * I/O if the netlink socket fails to open
+    /// * If the kernel rejects the request
+    fn set_link(
+        &self,
+        flags: &[Iff],
+        change: &[Iff],
+        attrs: RtBuffer<Ifla, neli::types::Buffer>,
+    ) -> Result<(), TunError> {
+        let msg = rtnl::Ifinfomsg::new(
+            RtAddrFamily::Unspecified,
+            Arphrd::Netrom,
+            self.index,
+            IffFlags::new(flags),
+            IffFlags::new(change),
+            attrs,
+        );
+
+        netlink_request(Rtm::Setlink, &[], msg)
+    }
+}
+
+/// A policy routing rule (`ip rule`)
+///
+/// Rules select the routing table used for a packet, e.g., to steer traffic into a table
+/// whose default route points at a tun device. A rule without selectors matches all
+/// traffic of its address family (IPv4 unless the rule matches an IPv6 network or
+/// [`RoutingRule::ipv6`] is set).
+///
+/// ```no_run
+/// # use tun_rs::RoutingRule;
+/// // ip rule add not fwmark 0xca6c table 51820 priority 100
+/// let rule = RoutingRule::new(51820)
+///     .fwmark(0xca6c, u32::MAX)
+///     .invert(true)
+///     .priority(100);
+/// rule.add()?;
+/// # Ok::<(), tun_rs::TunError>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoutingRule {
+    ipv6: bool,
+    table: u32,
+    priority: Option<u32>,
+    fwmark: Option<(u32, u32)>,
+    from: Option<(IpAddr, u8)>,
+    to: Option<(IpAddr, u8)>,
+    uid_range: Option<(u32, u32)>,
+    suppress_prefixlength: Option<u32>,
+    invert: bool,
+}
+
+impl RoutingRule {
+    /// Creates a rule that looks up routes in `table`
+    ///
+    /// # Arguments
+    /// * `table` - Routing table to use for matching packets
+    pub fn new(table: u32) -> Self {
+        Self {
+            ipv6: false,
+            table,
+            priority: None,
+            fwmark: None,
+            from: None,
+            to: None,
+            uid_range: None,
+            suppress_prefixlength: None,
+            invert: false,
+        }
+    }
+
+    /// Applies the rule to IPv6 (or IPv4) traffic
+    ///
+    /// # Arguments
+    /// * `ipv6` - True for IPv6, false for IPv4
+    pub fn ipv6(mut self, ipv6: bool) -> Self {
+        self.ipv6 = ipv6;
+        self
+    }
+
+    /// Sets the priority of the rule (kernel assigned if not set)
+    ///
+    /// # Arguments
+    /// * `priority` - Rules with lower values are evaluated first
+    pub fn priority(mut self, priority: u32) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Matches packets with a firewall mark (e.g., set with `SO_MARK`)
+    ///
+    /// # Arguments
+    /// * `mark` - Mark to match
+    /// * `mask` - Bits of the mark to compare (`u32::MAX` to compare all)
+    pub fn fwmark(mut self, mark: u32, mask: u32) -> Self {
+        self.fwmark = Some((mark, mask));
+        self
+    }
+
+    /// Matches packets from a source network
+    ///
+    /// # Arguments
+    /// * `net` - Source network address (sets the address family of the rule)
+    /// * `prefix` - CIDR of the source network
+    pub fn from(mut self, net: IpAddr, prefix: u8) -> Self {
+        self.ipv6 = net.is_ipv6();
+        self.from = Some((net, prefix));
+        self
+    }
+
+    /// Matches packets to a destination network
+    ///
+    /// # Arguments
+    /// * `net` - Destination network address (sets the address family of the rule)
+    /// * `prefix` - CIDR of the destination network
+    pub fn to(mut self, net: IpAddr, prefix: u8) -> Self {
+        self.ipv6 = net.is_ipv6();
+        self.to = Some((net, prefix));
+        self
+    }
+
+    /// Matches packets sent by sockets owned by a range of users
+    ///
+    /// # Arguments
+    /// * `start` - First uid in the range
+    /// * `end` - Last uid in the range (inclusive)
+    pub fn uid_range(mut self, start: u32, end: u32) -> Self {
+        self.uid_range = Some((start, end));
+        self
+    }
+
+    /// Ignores lookup results with a prefix length of `len` or less
+    ///
+    /// E.g., a length of 0 ignores the default route of the table, so only more specific
+    /// routes are used.
+    ///
+    /// # Arguments
+    /// * `len` - Maximum prefix length to ignore
+    pub fn suppress_prefixlength(mut self, len: u32) -> Self {
+        self.suppress_prefixlength = Some(len);
+        self
+    }
+
+    /// Inverts the selectors (`ip rule add not ...`)
+    ///
+    /// # Arguments
+    /// * `invert` - True to match packets not matching the selectors
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.invert = invert;
+        self
+    }
+
+    /// Adds this rule to the system (`RTM_NEWRULE`)
+    ///
+    /// # Errors
+    /// * I/O if the netlink socket fails to open
+    /// * If the kernel rejects the rule (e.g., an address family mismatch)
+    pub fn add(&self) -> Result<(), TunError> {
+        tracing::debug!(rule = ?self, "adding routing rule");
+        netlink_request(Rtm::Newrule, &[NlmF::Create], self.to_msg()?)
+    }
+
+    /// Removes this rule from the system (`RTM_DELRULE`)
+    ///
+    /// The first rule matching all set fields is removed.
+    ///
+    /// # Errors
+    /// * I/O if the netlink socket fails to open
+    /// * If the kernel rejects the request (e.g., no such rule)
+    pub fn delete(&self) -> Result<(), TunError> {
+        tracing::debug!(rule = ?self, "deleting routing rule");
+        netlink_request(Rtm::Delrule, &[], self.to_msg()?)
+    }
+
+    /// Builds the netlink message for this rule
+    ///
+    /// `struct fib_rule_hdr` has the same layout as `struct rtmsg`, with the action stored
+    /// in place of the route type.
+    fn to_msg(&self) -> Result<Rtmsg, TunError> {
+        let mut attrs = RtBuffer::new();
+        let u32_attr = |ty: u16, value: u32| Rtattr::new(None, Rta::from(ty), value);
+
+        if let Some(priority) = self.priority {
+            attrs.push(u32_attr(FRA_PRIORITY, priority)?);
+        }
+        if let Some((mark, mask)) = self.fwmark {
+            attrs.push(u32_attr(FRA_FWMARK, mark)?);
+            attrs.push(u32_attr(FRA_FWMASK, mask)?);
+        }
+        if let Some((net, _)) = self.from {
+            attrs.push(Rtattr::new(
+                None,
+                Rta::from(FRA_SRC),
+                &addr_octets(net)[..],
+            )?);
+        }
+        if let Some((net, _)) = self.to {
+            attrs.push(Rtattr::new(
+                None,
+                Rta::from(FRA_DST),
+                &addr_octets(net)[..],
+            )?);
+        }
+        if let Some((start, end)) = self.uid_range {
+            let mut range = [0u8; 8];
+            range[..4].copy_from_slice(&start.to_ne_bytes());
+            range[4..].copy_from_slice(&end.to_ne_bytes());
+            attrs.push(Rtattr::new(None, Rta::from(FRA_UID_RANGE), &range[..])?);
+        }
+        if let Some(len) = self.suppress_prefixlength {
+            attrs.push(u32_attr(FRA_SUPPRESS_PREFIXLEN, len)?);
+        }
+        attrs.push(u32_attr(FRA_TABLE, self.table)?);
+
+        let mut flags = Vec::new();
+        if self.invert {
+            flags.push(RtmF::from(FIB_RULE_INVERT));
+        }
+
+        Ok(Rtmsg {
+            rtm_family: match self.ipv6 {
+                true => RtAddrFamily::Inet6,
+                false => RtAddrFamily::Inet,
+            },
+            rtm_dst_len: self.to.map(|(_, prefix)| prefix).unwrap_or(0),
+            rtm_src_len: self.from.map(|(_, prefix)| prefix).unwrap_or(0),
+            rtm_tos: 0,
+            // tables above 255 only fit in FRA_TABLE
+            rtm_table: RtTable::from(u8::try_from(self.table).unwrap_or(0)),
+            rtm_protocol: Rtprot::Unspec,
+            rtm_scope: RtScope::Universe,
+            rtm_type: Rtn::from(FR_ACT_TO_TBL),
+            rtm_flags: RtmFFlags::new(&flags),
+            rtattrs: attrs,
+        })
+    }
+}
+
+/// Routes and rules added by [`OsTun::route_all_except`]
+///
+/// Removes the rules and routes when dropped (failures are logged). Use
+/// [`RouteAllGuard::remove`] to handle failures instead.
+#[derive(Debug)]
+pub struct RouteAllGuard {
+    // index of the device the routes point at
+    index: i32,
+
+    // (destination, prefix, table) of each route added
+    routes: Vec<(IpAddr, u8, u32)>,
+
+    // rules added, in order
+    rules: Vec<RoutingRule>,
+}
+
+impl RouteAllGuard {
+    /// Returns the rules added by the helper
+    pub fn rules(&self) -> &[RoutingRule] {
+        &self.rules
+    }
+
+    /// Removes the rules and routes
+    ///
+    /// Removal continues after a failure, the first error is returned.
+    ///
+    /// # Errors
+    /// * If a rule or route fails to be removed
+    pub fn remove(mut self) -> Result<(), TunError> {
+        self.cleanup()
+    }
+
+    fn cleanup(&mut self) -> Result<(), TunError> {
+        let mut result = Ok(());
+
+        // rules first, so traffic never hits a table without a default route
+        for rule in self.rules.drain(..).rev() {
+            if let Err(error) = rule.delete() {
+                tracing::warn!(?rule, %error, "failed to remove routing rule");
+                result = result.and(Err(error));
+            }
+        }
+
+        for (dst, prefix, table) in self.routes.drain(..).rev() {
+            let res = route_msg(dst, prefix, table, self.index)
+                .and_then(|msg| netlink_request(Rtm::Delroute, &[], msg));
+            if let Err(error) = res {
+                tracing::warn!(%dst, prefix, table, %error, "failed to remove route");
+                result = result.and(Err(error));
+            }
+        }
+
+        result
+    }
+}
+
+impl Drop for RouteAllGuard {
+    fn drop(&mut self) {
+        let _ = self.cleanup();
+    }
+}
+
+/// Sends a single rtnetlink request and waits for the kernel to acknowledge it
+///
+/// # Arguments
+/// * `nl_type` - Message type (e.g., `Rtm::Newroute`)
+/// * `flags` - Flags in addition to `NLM_F_REQUEST` and `NLM_F_ACK`
+/// * `payload` - Message payload
+///
+/// # Errors
+/// * I/O if the netlink socket fails to open
+/// * If the kernel rejects the request
+fn netlink_request<P: Nl + Debug>(
+    nl_type: Rtm,
+    flags: &[NlmF],
+    payload: P,
+) -> Result<(), TunError> {
+    let mut socket = NlSocketHandle::connect(NlFamily::Route, None, &[])?;
+
+    let mut nl_flags = vec![NlmF::Request, NlmF::Ack];
+    nl_flags.extend_from_slice(flags);
+
+    let hdr = {
+        let len = None;
+        let flags = NlmFFlags::new(&nl_flags);
+        let seq = None;
+        let pid = None;
+        Nlmsghdr::new(len, nl_type, flags, seq, pid, NlPayload::Payload(payload))
+    };
+
+    socket.send(hdr)?;
+
+    // errors reported by the kernel are returned from recv
+    socket.recv::<NlTypeWrapper, P>()?;
+    Ok(())
+}
+
+/// Builds the netlink message for a unicast route through interface `index`
+fn route_msg(dst: IpAddr, prefix: u8, table: u32, index: i32) -> Result<Rtmsg, TunError> {
+    let mut attrs = RtBuffer::new();
+    if prefix > 0 {
+        attrs.push(Rtattr::new(None, Rta::Dst, &addr_octets(dst)[..])?);
+    }
+    attrs.push(Rtattr::new(None, Rta::Oif, index)?);
+    attrs.push(Rtattr::new(None, Rta::Table, table)?);
+
+    Ok(Rtmsg {
+        rtm_family: match dst {
+            IpAddr::V4(_) => RtAddrFamily::Inet,
+            IpAddr::V6(_) => RtAddrFamily::Inet6,
+        },
+        rtm_dst_len: prefix,
+        rtm_src_len: 0,
+        rtm_tos: 0,
+        rtm_table: RtTable::Unspec,
+        rtm_protocol: Rtprot::Boot,
+        rtm_scope: RtScope::Universe,
+        rtm_type: Rtn::Unicast,
+        rtm_flags: RtmFFlags::empty(),
+        rtattrs: attrs,
+    })
+}
+
+/// Returns the octets of an IPv4 or IPv6 address
+fn addr_octets(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+/// Returns the index of the interface named `name`
+///
+/// # Errors
+/// * `DeviceNotFound` if no interface with this name exists
+fn interface_index(name: &CString) -> Result<i32, TunError> {
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(TunError::DeviceNotFound {
+            name: name.to_string_lossy().into_owned(),
+        }),
+        x if x >= (i32::MAX as u32) => {
+            unreachable!("if_nametoindex returned negative value")
+        }
+        idx => Ok(idx as i32),
+    }
+}
+
+/// Reads the device flags of the TUN interface named `name` from sysfs
+///
+/// Returns `None` if sysfs isn't available (e.g., not mounted in a sandbox).
+fn sysfs_tun_flags(name: &CString) -> Option<libc::c_int> {
+    let path = format!("/sys/class/net/{}/tun_flags", name.to_string_lossy());
+    let flags = std::fs::read_to_string(path).ok()?;
+    libc::c_int::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok()
+}
+
+/// Reads the owning user or group (`attr`) of the TUN interface named `name` from sysfs
+///
+/// Returns `None` if the device isn't restricted (or sysfs isn't available).
+fn sysfs_id(name: &CString, attr: &str) -> Option<u32> {
+    let path = format!("/sys/class/net/{}/{}", name.to_string_lossy(), attr);
+    let id: i64 = std::fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    u32::try_from(id).ok()
+}
+
+/// Capability header passed to `capget` / `capset`
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+/// One half of a 64-bit capability set (version 3 uses two)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Returns the effective capability set of the calling thread
+///
+/// # Errors
+/// * I/O if `capget` fails
+fn effective_caps() -> io::Result<u64> {
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+
+    // SAFETY: header and data match the layout expected by version 3 of capget
+    match unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(u64::from(data[1].effective) << 32 | u64::from(data[0].effective)),
+    }
+}
+
+/// Returns true if `gid` is the effective or a supplementary group of this process
+fn in_group(gid: u32) -> bool {
+    if unsafe { libc::getegid() } == gid {
+        return true;
+    }
+
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups = vec![0; count.max(0) as usize];
+    match unsafe { libc::getgroups(count, groups.as_mut_ptr()) } {
+        -1 => false,
+        n => groups[..n as usize].contains(&gid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_create_tun_device() {
+        let _dev = OsTun::create(TunConfig::default().name("linux0"))
+            .expect("failed to create linux tun device");
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
     fn root_create_tun_device_with_ip() {
         let _dev = OsTun::create(
             TunConfig::default()
@@ -416,4 +2101,425 @@ mod tests {
         )
         .expect("failed to create linux tun device");
     }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_create_tun_device_with_peer() {
+        let dev = OsTun::create(
+            TunConfig::default()
+                .name("linux5")
+                .ip([10, 70, 0, 1], 32)
+                .peer([10, 70, 0, 2]),
+        )
+        .expect("failed to create linux tun device");
+
+        dev.add_peer_address([10, 70, 1, 1], [10, 70, 1, 2], 32)
+            .unwrap();
+        assert!(matches!(
+            dev.add_peer_address([10, 70, 2, 1], "fd00::2".parse::<IpAddr>().unwrap(), 128),
+            Err(TunError::InvalidPeer { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_ipv6_address_options() {
+        let opts = Ipv6AddrOptions::default()
+            .noprefixroute(true)
+            .lifetimes(Duration::from_secs(600), Duration::from_secs(300));
+        let dev = OsTun::create(
+            TunConfig::default()
+                .name("linux6")
+                .ip("fd00:70::1".parse::<IpAddr>().unwrap(), 64)
+                .ipv6_options(opts),
+        )
+        .expect("failed to create linux tun device");
+
+        let nodad = Ipv6AddrOptions::default().nodad(true);
+        dev.add_ipv6_address("fd00:71::1".parse().unwrap(), 64, &nodad)
+            .unwrap();
+        dev.up().unwrap();
+
+        let timeout = Duration::from_secs(5);
+        let flags = dev.address_flags("fd00:70::1".parse().unwrap()).unwrap();
+        assert_eq!(flags.unwrap() & libc::IFA_F_PERMANENT, 0);
+        assert_ne!(flags.unwrap() & IFA_F_NOPREFIXROUTE, 0);
+        dev.wait_address_ready("fd00:70::1".parse().unwrap(), timeout)
+            .unwrap();
+        dev.wait_address_ready("fd00:71::1".parse().unwrap(), timeout)
+            .unwrap();
+
+        assert!(matches!(
+            dev.wait_address_ready("fd00:72::1".parse().unwrap(), Duration::ZERO),
+            Err(TunError::Timeout { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_ipv6_autoconf_and_sysctls() {
+        use crate::RpFilter;
+
+        let dev = OsTun::create(
+            TunConfig::default()
+                .name("linux7")
+                .addr_gen_mode(AddrGenMode::None)
+                .accept_ra(false)
+                .rp_filter(RpFilter::Loose),
+        )
+        .expect("failed to create linux tun device");
+
+        let sysctl = |path: &str| std::fs::read_to_string(path).unwrap().trim().to_owned();
+        assert_eq!(sysctl("/proc/sys/net/ipv6/conf/linux7/accept_ra"), "0");
+        assert_eq!(sysctl("/proc/sys/net/ipv4/conf/linux7/rp_filter"), "2");
+        assert_eq!(sysctl("/proc/sys/net/ipv6/conf/linux7/addr_gen_mode"), "1");
+
+        // no link-local address is generated once the device is up
+        dev.up().unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let addrs = std::fs::read_to_string("/proc/net/if_inet6").unwrap();
+        assert!(!addrs
+            .lines()
+            .any(|line| line.starts_with("fe80") && line.ends_with("linux7")));
+    }
+
+    #[test]
+    fn routing_rule_message() {
+        let msg = RoutingRule::new(51820)
+            .from("fd00::".parse().unwrap(), 8)
+            .fwmark(0xca6c, u32::MAX)
+            .invert(true)
+            .to_msg()
+            .unwrap();
+
+        assert_eq!(msg.rtm_family, RtAddrFamily::Inet6);
+        assert_eq!((msg.rtm_src_len, msg.rtm_dst_len), (8, 0));
+        assert_eq!(msg.rtm_table, RtTable::Unspec);
+        assert!(msg.rtm_flags.contains(&RtmF::from(FIB_RULE_INVERT)));
+
+        let table = msg
+            .rtattrs
+            .iter()
+            .find(|attr| attr.rta_type == Rta::from(FRA_TABLE))
+            .unwrap();
+        assert_eq!(table.rta_payload.as_ref(), 51820u32.to_ne_bytes());
+
+        // small table ids are also set in the header
+        let msg = RoutingRule::new(RT_TABLE_MAIN).to_msg().unwrap();
+        assert_eq!(msg.rtm_table, RtTable::Main);
+        assert_eq!(msg.rtm_family, RtAddrFamily::Inet);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_route_all_except() {
+        let dev = OsTun::create(TunConfig::default().name("linux8"))
+            .expect("failed to create linux tun device");
+        dev.up().unwrap();
+
+        let rules = || {
+            let out = std::process::Command::new("ip")
+                .args(["-4", "rule", "show"])
+                .output()
+                .expect("failed to run ip rule");
+            String::from_utf8_lossy(&out.stdout).into_owned()
+        };
+
+        let endpoint = ("198.51.100.7".parse().unwrap(), 32);
+        let guard = dev.route_all_except(7070, 7070, &[endpoint]).unwrap();
+        assert_eq!(guard.rules().len(), 3);
+
+        let shown = rules();
+        assert!(
+            shown.contains("7070:\tfrom all to 198.51.100.7 lookup main"),
+            "{}",
+            shown
+        );
+        assert!(shown.contains("7071:\tfrom all lookup 7070"), "{}", shown);
+
+        // removed again once dropped
+        drop(guard);
+        assert!(!rules().contains("lookup 7070"));
+
+        // deleting a rule that doesn't exist fails
+        assert!(RoutingRule::new(7070).priority(7071).delete().is_err());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_configure_rolls_back() {
+        let mut dev = OsTun::create(TunConfig::default().name("linux9"))
+            .expect("failed to create linux tun device");
+        let ip: IpAddr = [10, 90, 0, 1].into();
+        let original_mtu = dev.mtu().unwrap();
+        let is_up = || TunLink::find_by_name("linux9").unwrap().unwrap().up;
+
+        // the invalid route fails after the address, mtu and link state were changed
+        let cfg = TunConfig::default()
+            .ip(ip, 24)
+            .mtu(1280)
+            .accept_ra(false)
+            .up(true)
+            .route([10, 91, 0, 0], 16)
+            .route([10, 92, 0, 0], 33);
+        assert!(dev.configure(cfg.clone()).is_err());
+        assert_eq!(dev.address_flags(ip).unwrap(), None);
+        assert_eq!(dev.mtu().unwrap(), original_mtu);
+        assert!(!is_up());
+
+        // applied changes are kept until rolled back
+        let cfg = TunConfig {
+            routes: vec![],
+            ..cfg
+        }
+        .route([10, 91, 0, 0], 16);
+        dev.configure(cfg).unwrap();
+        assert!(dev.address_flags(ip).unwrap().is_some());
+        assert_eq!(dev.mtu().unwrap(), 1280);
+        assert!(is_up());
+
+        dev.rollback().unwrap();
+        assert_eq!(dev.address_flags(ip).unwrap(), None);
+        assert_eq!(dev.mtu().unwrap(), original_mtu);
+        assert!(!is_up());
+
+        // nothing left to revert
+        dev.rollback().unwrap();
+
+        // a device that was already up is left up
+        dev.configure(TunConfig::default().up(true)).unwrap();
+        dev.commit();
+        dev.configure(TunConfig::default().up(true).mtu(1280))
+            .unwrap();
+        dev.rollback().unwrap();
+        assert_eq!(dev.mtu().unwrap(), original_mtu);
+        assert!(is_up());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_adopt_passed_fd() {
+        use crate::fdpass::{recv_fd, send_fd};
+        use std::os::unix::net::UnixStream;
+
+        let dev = OsTun::create(TunConfig::default().name("linux2").packet_info(true))
+            .expect("failed to create linux tun device");
+
+        let (supervisor, worker) = UnixStream::pair().unwrap();
+        send_fd(&supervisor, dev.as_raw_fd()).unwrap();
+        let adopted = OsTun::from_fd(recv_fd(&worker).unwrap()).unwrap();
+
+        assert_eq!(adopted.name(), "linux2");
+        assert_eq!(adopted.index, dev.index);
+        assert!(adopted.packet_info);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_query_device_info() {
+        let dev = OsTun::create(TunConfig::default().name("linux3"))
+            .expect("failed to create linux tun device");
+
+        let info = dev.info().unwrap();
+        assert!(!info.is_tap());
+        assert!(!info.packet_info());
+        assert!(!info.vnet_hdr() && !info.multi_queue() && !info.persistent());
+        assert_eq!((info.owner, info.group), (None, None));
+        assert_eq!(info.vnet_hdr_size, 10);
+        assert!(info.sndbuf > 0);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_kernel_assigned_names() {
+        let unnamed = OsTun::create(TunConfig::default()).expect("failed to create unnamed tun");
+        assert!(unnamed.name().starts_with("tun"), "{}", unnamed.name());
+
+        let first = OsTun::create(TunConfig::default().name("lnxtmpl%d")).unwrap();
+        let second = OsTun::create(TunConfig::default().name("lnxtmpl%d")).unwrap();
+        assert!(first.name().starts_with("lnxtmpl"));
+        assert_ne!(first.name(), second.name());
+        assert_eq!(interface_index(&second.name).unwrap(), second.index);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_preflight() {
+        let preflight = OsTun::preflight(&TunConfig::default().name("linux10"));
+        assert!(preflight.cap_net_admin);
+        assert!(preflight.clone_device_exists && preflight.clone_device_accessible);
+        assert!(!preflight.name_exists);
+        assert!(preflight.is_ok(), "{preflight}");
+
+        let preflight = OsTun::preflight(&TunConfig::default().name("lo"));
+        assert_eq!(preflight.name_conflict, Some(NameConflict::NotTun));
+        assert!(!preflight.is_ok());
+
+        // existing tun devices can be attached to
+        let _dev = OsTun::create(TunConfig::default().name("linux10")).unwrap();
+        let preflight = OsTun::preflight(&TunConfig::default().name("linux10"));
+        assert!(preflight.name_exists);
+        assert_eq!(preflight.name_conflict, None);
+
+        let preflight = Preflight {
+            cap_net_admin: false,
+            clone_device_exists: true,
+            clone_device_accessible: true,
+            name_exists: false,
+            name_conflict: None,
+        };
+        assert!(
+            preflight.to_string().contains("CAP_NET_ADMIN"),
+            "{preflight}"
+        );
+        assert!(Preflight {
+            name_exists: true,
+            ..preflight
+        }
+        .is_ok());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_drop_privileges() {
+        let mut dev =
+            OsTun::create(TunConfig::default().name("linux11").mtu(1400).up(true)).unwrap();
+
+        // privileges are per process, check them in a child
+        match unsafe { libc::fork() } {
+            0 => {
+                let ok = dev.drop_privileges(65534, 65534).is_ok()
+                    && unsafe { (libc::getuid(), libc::geteuid(), libc::getegid()) }
+                        == (65534, 65534, 65534)
+                    && effective_caps().ok() == Some(0)
+                    && dev.set_mtu(1300).is_err_and(|e| e.is_permission_denied())
+                    // regaining root isn't possible
+                    && unsafe { libc::setuid(0) } == -1
+                    // the device itself is still usable
+                    && dev.write(&[0x45; 20]).is_ok();
+                unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+            }
+            -1 => panic!("fork failed: {}", io::Error::last_os_error()),
+            pid => {
+                let mut status = 0;
+                assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+                assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+            }
+        }
+
+        // the parent keeps its privileges and configuration
+        assert_eq!(dev.mtu().unwrap(), 1400);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_drop_privileges_clears_groups() {
+        let dev = OsTun::create(TunConfig::default().name("linux13")).unwrap();
+
+        /// Sets the effective capabilities of the calling thread to `effective`
+        fn set_effective(effective: u64) -> bool {
+            let mut header = CapHeader {
+                version: LINUX_CAPABILITY_VERSION_3,
+                pid: 0,
+            };
+            let mut data = [CapData::default(); 2];
+            unsafe {
+                libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr());
+            }
+            data[0].effective = effective as u32;
+            data[1].effective = (effective >> 32) as u32;
+            unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) == 0 }
+        }
+
+        let groups = [1234];
+        match unsafe { libc::fork() } {
+            0 => {
+                let all = effective_caps().unwrap();
+
+                // without CAP_SETGID the supplementary groups can't be cleared
+                let ok = unsafe { libc::setgroups(1, groups.as_ptr()) } == 0
+                    && set_effective(all & !(1 << CAP_SETGID))
+                    && matches!(
+                        dev.drop_privileges(65534, 65534),
+                        Err(TunError::PrivilegeDropFailed { op: "setgroups", .. })
+                    )
+                    && set_effective(all)
+                    // a non-root user with CAP_SETGID clears them
+                    && unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) } == 0
+                    && unsafe { libc::setresuid(1000, 1000, 1000) } == 0
+                    && set_effective(all)
+                    && dev.drop_privileges(65534, 65534).is_ok()
+                    && unsafe { libc::getgroups(0, std::ptr::null_mut()) } == 0;
+                unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+            }
+            -1 => panic!("fork failed: {}", io::Error::last_os_error()),
+            pid => {
+                let mut status = 0;
+                assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+                assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_create_failure_keeps_os_error() {
+        // existing interfaces that aren't tun devices can't be attached to
+        match OsTun::create(TunConfig::default().name("lo")) {
+            Err(TunError::DeviceCreateFailed { name, op, source }) => {
+                assert_eq!((name.as_str(), op), ("lo", "TUNSETIFF"));
+                assert_eq!(source.raw_os_error(), Some(libc::EINVAL));
+            }
+            other => panic!("expected DeviceCreateFailed, got {other:?}"),
+        }
+
+        // errors reported over netlink keep their errno
+        let dev = OsTun::create(TunConfig::default().name("linux12")).unwrap();
+        let err = dev
+            .delete_route([10, 99, 0, 0].into(), 16, RT_TABLE_MAIN)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ESRCH));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_lookup_devices() {
+        let dev = OsTun::create(TunConfig::default().name("linux4"))
+            .expect("failed to create linux tun device");
+        assert_eq!(dev.to_string(), "linux4");
+
+        let link = TunLink::find_by_name("linux4").unwrap().unwrap();
+        assert_eq!(link.index, dev.index());
+        assert!(!link.tap);
+        assert_eq!(TunLink::find_by_index(dev.index()).unwrap(), Some(link));
+        assert!(TunLink::list().unwrap().iter().any(|l| l.name == "linux4"));
+
+        // non-tun interfaces are skipped
+        assert_eq!(TunLink::find_by_name("lo").unwrap(), None);
+    }
+
+    #[test]
+    fn parse_link_kind() {
+        // | IFLA_INFO_DATA (len 8) | IFLA_INFO_KIND "tun\0" |
+        let mut linkinfo = Vec::new();
+        linkinfo.extend_from_slice(&8u16.to_ne_bytes());
+        linkinfo.extend_from_slice(&2u16.to_ne_bytes());
+        linkinfo.extend_from_slice(&[1, 2, 3, 4]);
+        linkinfo.extend_from_slice(&8u16.to_ne_bytes());
+        linkinfo.extend_from_slice(&IFLA_INFO_KIND.to_ne_bytes());
+        linkinfo.extend_from_slice(b"tun\0");
+
+        assert_eq!(link_kind(&linkinfo), Some(&b"tun"[..]));
+        assert_eq!(link_kind(&linkinfo[..8]), None);
+        assert_eq!(link_kind(&linkinfo[..10]), None);
+    }
+
+    #[test]
+    fn from_fd_rejects_non_tun() {
+        let (a, _b) = std::os::unix::net::UnixStream::pair().unwrap();
+        assert!(matches!(
+            OsTun::from_fd(a.as_raw_fd()),
+            Err(TunError::IO(_))
+        ));
+    }
 }
@@ -0,0 +1,323 @@
+//! Scriptable [`Tun`] implementation for unit tests
+//!
+//! [`MockTun`] returns queued packets and errors from `read_packet`, records every packet
+//! passed to `write_packet` and counts calls to `up` / `down`. This allows code generic over
+//! [`Tun`] (e.g., forwarding loops) to be tested without root privileges, including error
+//! paths that are hard to trigger on a real device.
+
+use crate::{Tun, TunError};
+use std::{
+    cmp,
+    collections::VecDeque,
+    io,
+    sync::{Mutex, MutexGuard},
+};
+
+/// Result of a single call to `read_packet`
+enum ReadStep<P> {
+    /// Returns the packet (truncated to the caller's buffer)
+    Packet(Vec<u8>, P),
+
+    /// Returns only the first `n` bytes of the packet
+    Short(Vec<u8>, usize, P),
+
+    /// Fails with the error
+    Error(TunError),
+}
+
+/// Result of a single call to `write_packet`
+enum WriteStep {
+    /// Accepts only the first `n` bytes of the packet
+    Short(usize),
+
+    /// Fails with the error
+    Error(io::Error),
+}
+
+/// Mutable state shared behind the mock's lock
+struct State<P> {
+    reads: VecDeque<ReadStep<P>>,
+    writes: VecDeque<WriteStep>,
+    written: Vec<(Vec<u8>, P)>,
+    up_calls: usize,
+    down_calls: usize,
+    up: bool,
+}
+
+/// A tun device whose behavior is scripted by the test
+///
+/// Once the read script is exhausted, `read_packet` fails with `UnexpectedEof`. Writes
+/// succeed (and are recorded) unless a write error or short write has been queued.
+///
+/// `P` is the packet info type returned from reads and accepted by writes, allowing the
+/// mock to stand in for devices with non-trivial packet info.
+pub struct MockTun<P = ()> {
+    state: Mutex<State<P>>,
+}
+
+impl<P> Default for MockTun<P> {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                reads: VecDeque::new(),
+                writes: VecDeque::new(),
+                written: Vec::new(),
+                up_calls: 0,
+                down_calls: 0,
+                up: false,
+            }),
+        }
+    }
+}
+
+impl<P> std::fmt::Debug for MockTun<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.lock();
+        f.debug_struct("MockTun")
+            .field("pending_reads", &state.reads.len())
+            .field("pending_writes", &state.writes.len())
+            .field("written", &state.written.len())
+            .field("up", &state.up)
+            .finish()
+    }
+}
+
+impl MockTun<()> {
+    /// Creates a mock with an empty script
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<P: Clone + Default> MockTun<P> {
+    /// Queues a packet to be returned by `read_packet` (with the default packet info)
+    ///
+    /// # Arguments
+    /// * `pkt` - Packet to return
+    pub fn push_packet(&self, pkt: impl Into<Vec<u8>>) -> &Self {
+        self.push_packet_with_info(pkt, P::default())
+    }
+
+    /// Queues a packet to be returned by `read_packet` along with packet info
+    ///
+    /// # Arguments
+    /// * `pkt` - Packet to return
+    /// * `pi` - Packet info to return with the packet
+    pub fn push_packet_with_info(&self, pkt: impl Into<Vec<u8>>, pi: P) -> &Self {
+        self.lock()
+            .reads
+            .push_back(ReadStep::Packet(pkt.into(), pi));
+        self
+    }
+
+    /// Queues a read that only returns the first `len` bytes of `pkt`
+    ///
+    /// # Arguments
+    /// * `pkt` - Packet to (partially) return
+    /// * `len` - Number of bytes to return
+    pub fn push_short_read(&self, pkt: impl Into<Vec<u8>>, len: usize) -> &Self {
+        self.lock()
+            .reads
+            .push_back(ReadStep::Short(pkt.into(), len, P::default()));
+        self
+    }
+
+    /// Queues an error to be returned by `read_packet`
+    ///
+    /// # Arguments
+    /// * `error` - Error to return (e.g., `io::Error::from_raw_os_error(libc::EAGAIN)`)
+    pub fn push_error(&self, error: impl Into<TunError>) -> &Self {
+        self.lock().reads.push_back(ReadStep::Error(error.into()));
+        self
+    }
+
+    /// Queues an error to be returned by the next call to `write_packet`
+    ///
+    /// # Arguments
+    /// * `error` - Error to return
+    pub fn push_write_error(&self, error: io::Error) -> &Self {
+        self.lock().writes.push_back(WriteStep::Error(error));
+        self
+    }
+
+    /// Queues a write that only accepts the first `len` bytes of the packet
+    ///
+    /// # Arguments
+    /// * `len` - Number of bytes to accept
+    pub fn push_short_write(&self, len: usize) -> &Self {
+        self.lock().writes.push_back(WriteStep::Short(len));
+        self
+    }
+
+    /// Returns the number of scripted reads and writes that have not been consumed yet
+    pub fn remaining(&self) -> usize {
+        let state = self.lock();
+        state.reads.len() + state.writes.len()
+    }
+
+    /// Returns a copy of every packet (and packet info) accepted by `write_packet`
+    pub fn written(&self) -> Vec<(Vec<u8>, P)> {
+        self.lock().written.clone()
+    }
+
+    /// Removes and returns every packet accepted by `write_packet`
+    pub fn take_written(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.lock().written)
+            .into_iter()
+            .map(|(pkt, _)| pkt)
+            .collect()
+    }
+
+    /// Returns the number of times `up` was called
+    pub fn up_calls(&self) -> usize {
+        self.lock().up_calls
+    }
+
+    /// Returns the number of times `down` was called
+    pub fn down_calls(&self) -> usize {
+        self.lock().down_calls
+    }
+
+    /// Returns true if `up` was called more recently than `down`
+    pub fn is_up(&self) -> bool {
+        self.lock().up
+    }
+
+    /// Panics if any scripted read or write has not been consumed
+    #[track_caller]
+    pub fn assert_done(&self) {
+        let state = self.lock();
+        assert!(
+            state.reads.is_empty() && state.writes.is_empty(),
+            "mock tun has {} unconsumed reads and {} unconsumed writes",
+            state.reads.len(),
+            state.writes.len()
+        );
+    }
+}
+
+impl<P> MockTun<P> {
+    fn lock(&self) -> MutexGuard<'_, State<P>> {
+        // a failed assertion in another thread shouldn't hide the state from this one
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<P: Clone + Default> Tun for MockTun<P> {
+    type PktInfo = P;
+
+    fn up(&self) -> Result<(), TunError> {
+        let mut state = self.lock();
+        state.up_calls += 1;
+        state.up = true;
+        Ok(())
+    }
+
+    fn down(&self) -> Result<(), TunError> {
+        let mut state = self.lock();
+        state.down_calls += 1;
+        state.up = false;
+        Ok(())
+    }
+
+    /// Returns the next scripted packet or error
+    ///
+    /// # Errors
+    /// * Any error queued with `push_error`
+    /// * I/O (`UnexpectedEof`) once the read script is exhausted
+    fn read_packet(&self, buf: &mut [u8]) -> Result<(usize, Self::PktInfo), TunError> {
+        let step = self.lock().reads.pop_front().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "mock read script exhausted")
+        })?;
+
+        let (pkt, len, pi) = match step {
+            ReadStep::Packet(pkt, pi) => {
+                let len = pkt.len();
+                (pkt, len, pi)
+            }
+            ReadStep::Short(pkt, len, pi) => (pkt, len, pi),
+            ReadStep::Error(error) => return Err(error),
+        };
+
+        let len = cmp::min(cmp::min(len, pkt.len()), buf.len());
+        buf[..len].copy_from_slice(&pkt[..len]);
+        Ok((len, pi))
+    }
+
+    fn write_packet(&self, buf: &[u8], pi: Self::PktInfo) -> Result<usize, io::Error> {
+        let mut state = self.lock();
+        let len = match state.writes.pop_front() {
+            Some(WriteStep::Error(error)) => return Err(error),
+            Some(WriteStep::Short(len)) => cmp::min(len, buf.len()),
+            None => buf.len(),
+        };
+
+        state.written.push((buf[..len].to_vec(), pi));
+        Ok(len)
+    }
+
+    fn blank_pktinfo(&self) -> Self::PktInfo {
+        P::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripted_reads() {
+        let tun = MockTun::new();
+        tun.push_packet(&b"first"[..])
+            .push_error(io::Error::from_raw_os_error(libc::EAGAIN))
+            .push_short_read(&b"second"[..], 3)
+            .push_error(io::Error::from_raw_os_error(libc::EIO));
+
+        let mut buf = [0u8; 16];
+        let (n, _) = tun.read_packet(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"first");
+
+        match tun.read_packet(&mut buf) {
+            Err(TunError::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::WouldBlock),
+            other => panic!("expected EAGAIN, got {:?}", other),
+        }
+
+        let (n, _) = tun.read_packet(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"sec");
+
+        match tun.read_packet(&mut buf) {
+            Err(TunError::IO(e)) => assert_eq!(e.raw_os_error(), Some(libc::EIO)),
+            other => panic!("expected EIO, got {:?}", other),
+        }
+
+        tun.assert_done();
+        assert!(matches!(
+            tun.read_packet(&mut buf),
+            Err(TunError::IO(e)) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn records_writes_and_state() {
+        let tun = MockTun::<(u16, u16)>::default();
+        tun.push_write_error(io::Error::from_raw_os_error(libc::ENOBUFS))
+            .push_short_write(2);
+
+        tun.up().unwrap();
+        assert!(tun.write_packet(b"dropped", (0, 0x0800)).is_err());
+        assert_eq!(tun.write_packet(b"short", (0, 0x0800)).unwrap(), 2);
+        assert_eq!(tun.write_packet(b"full", (0, 0x86DD)).unwrap(), 4);
+        tun.down().unwrap();
+
+        assert_eq!(
+            tun.written(),
+            vec![
+                (b"sh".to_vec(), (0, 0x0800)),
+                (b"full".to_vec(), (0, 0x86DD))
+            ]
+        );
+        assert_eq!((tun.up_calls(), tun.down_calls()), (1, 1));
+        assert!(!tun.is_up());
+        tun.assert_done();
+    }
+}
@@ -0,0 +1,226 @@
+//! smoltcp integration
+//!
+//! Implements smoltcp's [`Device`] trait for [`TunDevice`] (and [`ChannelTun`]) so a
+//! userspace TCP/IP stack can terminate connections arriving over the tunnel.
+//!
+//! Devices are exposed to smoltcp using [`Medium::Ip`] and packets are written using the
+//! device's blank packet info, so `packet_info` should be disabled on devices used with
+//! smoltcp.
+//!
+//! [`ChannelTun`]: crate::ChannelTun
+
+use crate::{Tun, TunDevice};
+use smoltcp::{
+    phy::{self, Device, DeviceCapabilities, Medium},
+    time::{Duration, Instant},
+};
+use std::{io, os::unix::io::AsRawFd};
+
+/// MTU reported to smoltcp when the device's MTU is unknown (the default MTU of a newly
+/// created tun device)
+pub const DEFAULT_MTU: usize = 1500;
+
+/// Size of the buffer packets are received into, large enough for any IP packet regardless
+/// of the device's MTU
+const MAX_PACKET_LEN: usize = u16::MAX as usize;
+
+/// A token containing a single packet received from a tun device
+#[derive(Debug)]
+pub struct RxToken {
+    buf: Vec<u8>,
+}
+
+/// A token used to transmit a single packet to a tun device
+#[derive(Debug)]
+pub struct TxToken<'a, T: Tun> {
+    tun: &'a T,
+}
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.buf)
+    }
+}
+
+impl<'a, T: Tun> phy::TxToken for TxToken<'a, T> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut buf = vec![0u8; len];
+        let res = f(&mut buf);
+        if let Err(error) = self.tun.write_packet(&buf, self.tun.blank_pktinfo()) {
+            tracing::warn!(?error, "failed to transmit packet");
+        }
+        res
+    }
+}
+
+/// Returns the capabilities shared by all tun-backed devices
+fn capabilities(mtu: usize) -> DeviceCapabilities {
+    let mut caps = DeviceCapabilities::default();
+    caps.medium = Medium::Ip;
+    caps.max_transmission_unit = mtu;
+    caps
+}
+
+impl Device for TunDevice {
+    type RxToken<'a> = RxToken;
+    type TxToken<'a> = TxToken<'a, crate::OsTun>;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        // smoltcp expects receive to never block
+        match crate::poll_fd(self.as_raw_fd(), Some(std::time::Duration::ZERO)) {
+            Ok(true) => (),
+            Ok(false) => return None,
+            Err(error) => {
+                tracing::warn!(?error, "failed to poll tun device");
+                return None;
+            }
+        }
+
+        let mut buf = vec![0u8; MAX_PACKET_LEN];
+        match self.read_packet(&mut buf) {
+            Ok((n, _)) => {
+                buf.truncate(n);
+                Some((RxToken { buf }, TxToken { tun: &**self }))
+            }
+            Err(error) => {
+                tracing::warn!(?error, "failed to read packet");
+                None
+            }
+        }
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        Some(TxToken { tun: &**self })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        #[cfg(target_os = "linux")]
+        let mtu = match self.mtu() {
+            Ok(mtu) => mtu as usize,
+            Err(error) => {
+                tracing::warn!(?error, "failed to query device mtu");
+                DEFAULT_MTU
+            }
+        };
+        #[cfg(not(target_os = "linux"))]
+        let mtu = DEFAULT_MTU;
+
+        capabilities(mtu)
+    }
+}
+
+#[cfg(feature = "channel")]
+impl Device for crate::ChannelTun {
+    type RxToken<'a> = RxToken;
+    type TxToken<'a> = TxToken<'a, crate::ChannelTun>;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let buf = self.try_recv()?;
+        Some((RxToken { buf }, TxToken { tun: self }))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        Some(TxToken { tun: self })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        capabilities(DEFAULT_MTU)
+    }
+}
+
+/// Waits until the tun device has a packet available to read
+///
+/// Intended to be used with [`smoltcp::iface::Interface::poll_delay`] to sleep between
+/// calls to `poll`.
+///
+/// # Arguments
+/// * `tun` - Device to wait on
+/// * `timeout` - Maximum amount of time to wait (or forever if `None`)
+///
+/// # Errors
+/// * I/O if polling the device fails
+pub fn wait(tun: &TunDevice, timeout: Option<Duration>) -> io::Result<()> {
+    let timeout = timeout.map(|t| std::time::Duration::from_micros(t.total_micros()));
+    crate::poll_fd(tun.as_raw_fd(), timeout).map(|_| ())
+}
+
+#[cfg(all(test, feature = "channel"))]
+mod tests {
+    use super::*;
+    use crate::{checksum, ChannelTun, TunConfig};
+    use smoltcp::{
+        iface::{Config, Interface, SocketSet},
+        wire::{HardwareAddress, IpAddress, IpCidr},
+    };
+
+    /// Builds an ICMP echo request from 10.0.0.1 to 10.0.0.2
+    fn echo_request() -> Vec<u8> {
+        let mut pkt = vec![
+            0x45, 0x00, 0x00, 0x20, 0x00, 0x01, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2, 0x08, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00, 0x01, b'p', b'i', b'n', b'g',
+        ];
+        checksum::fill(&mut pkt).expect("failed to fill checksums");
+        pkt
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[cfg_attr(not(feature = "root-tests"), ignore)]
+    fn root_jumbo_mtu() {
+        use smoltcp::phy::RxToken as _;
+
+        let cfg = TunConfig::default()
+            .name("phy0")
+            .ip([10, 95, 0, 1], 24)
+            .mtu(9000)
+            .disable_ipv6(true)
+            .up(true);
+        let mut dev = TunDevice::create(cfg).expect("failed to create linux tun device");
+        assert_eq!(dev.capabilities().max_transmission_unit, 9000);
+
+        // packets larger than the default mtu are received whole
+        let sock = std::net::UdpSocket::bind("10.95.0.1:0").unwrap();
+        sock.send_to(&[0u8; 8000], "10.95.0.2:9").unwrap();
+        wait(&dev, Some(Duration::from_secs(5))).unwrap();
+        let (rx, _) = dev.receive(Instant::now()).expect("no packet received");
+        assert_eq!(rx.consume(|pkt| pkt.len()), 8028);
+    }
+
+    #[test]
+    fn smoltcp_answers_ping() {
+        let (mut local, peer) = ChannelTun::create("smol0", TunConfig::default())
+            .expect("failed to create channel tun device");
+
+        let now = Instant::from_millis(0);
+        let mut iface = Interface::new(Config::new(HardwareAddress::Ip), &mut local, now);
+        iface.update_ip_addrs(|addrs| {
+            addrs
+                .push(IpCidr::new(IpAddress::v4(10, 0, 0, 2), 24))
+                .expect("failed to add ip address");
+        });
+        let mut sockets = SocketSet::new(vec![]);
+
+        peer.write_packet(&echo_request(), ())
+            .expect("failed to write echo request");
+        iface.poll(now, &mut local, &mut sockets);
+
+        let mut buf = [0u8; 1500];
+        let (n, _) = peer
+            .read_packet(&mut buf)
+            .expect("failed to read echo reply");
+        let reply = &buf[..n];
+
+        assert_eq!(reply[12..16], [10, 0, 0, 2]);
+        assert_eq!(reply[16..20], [10, 0, 0, 1]);
+        assert_eq!(reply[20], 0, "expected icmp echo reply");
+        assert_eq!(reply[28..], b"ping"[..]);
+        assert_eq!(checksum::compute(&reply[..20]), 0);
+        assert_eq!(checksum::compute(&reply[20..]), 0);
+    }
+}
@@ -0,0 +1,646 @@
+//! Replays recorded traffic through the [`Tun`] trait
+//!
+//! [`ReplayTun`] reads a pcap or pcapng file and returns its packets from `read_packet`,
+//! while collecting every packet passed to `write_packet`. This allows packet handlers to
+//! be exercised deterministically without a real interface.
+
+use crate::{
+    capture::{Direction, LinkType, PcapngWriter},
+    Tun, TunError,
+};
+use std::{
+    cmp,
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+// classic pcap magic numbers (microsecond / nanosecond resolution)
+const PCAP_MAGIC_US: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NS: u32 = 0xA1B2_3C4D;
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const PCAPNG_PACKET: u32 = 0x0000_0002;
+const PCAPNG_SIMPLE_PACKET: u32 = 0x0000_0003;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x0000_0006;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_EPB_FLAGS: u16 = 2;
+const OPT_IF_TSRESOL: u16 = 9;
+
+// supported link-layer types
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LOOP: u16 = 108;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_IPV6: u16 = 229;
+
+/// A single packet loaded from a capture file
+#[derive(Clone, Debug)]
+pub struct Record {
+    /// Time since the unix epoch the packet was captured (if recorded)
+    pub timestamp: Option<Duration>,
+
+    /// Direction the packet travelled (if recorded)
+    pub direction: Option<Direction>,
+
+    /// IP packet (any link-layer header has been removed)
+    pub data: Vec<u8>,
+}
+
+/// Reads all packets from a pcap or pcapng stream
+///
+/// Link-layer headers (Ethernet, BSD loopback, Linux cooked) are stripped so only the IP
+/// packet remains. Ethernet frames that do not carry IPv4 or IPv6 are skipped.
+///
+/// # Arguments
+/// * `reader` - Stream containing a pcap or pcapng capture
+///
+/// # Errors
+/// * I/O if reading from the stream fails
+/// * The stream is not a valid pcap/pcapng capture or uses an unsupported link type
+pub fn read_capture<R: Read>(mut reader: R) -> Result<Vec<Record>, TunError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let magic = data
+        .get(..4)
+        .map(|m| u32::from_le_bytes([m[0], m[1], m[2], m[3]]))
+        .ok_or(TunError::InvalidCapture("missing file header"))?;
+
+    let records = match magic {
+        PCAPNG_SECTION_HEADER => parse_pcapng(&data)?,
+        _ => parse_pcap(&data)?,
+    };
+
+    records
+        .into_iter()
+        .filter_map(
+            |(link, mut record)| match strip_link_header(link, &record.data) {
+                Ok(Some(start)) => {
+                    record.data.drain(..start);
+                    Some(Ok(record))
+                }
+                Ok(None) => None,
+                Err(error) => Some(Err(error)),
+            },
+        )
+        .collect()
+}
+
+/// Cursor over a capture file with a fixed byte order
+struct Cursor<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Cursor<'a> {
+    fn u16(&self, off: usize) -> Result<u16, TunError> {
+        let b = self.bytes(off, 2)?;
+        Ok(match self.big_endian {
+            true => u16::from_be_bytes([b[0], b[1]]),
+            false => u16::from_le_bytes([b[0], b[1]]),
+        })
+    }
+
+    fn u32(&self, off: usize) -> Result<u32, TunError> {
+        let b = self.bytes(off, 4)?;
+        Ok(match self.big_endian {
+            true => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            false => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        })
+    }
+
+    fn bytes(&self, off: usize, len: usize) -> Result<&'a [u8], TunError> {
+        self.data
+            .get(off..off + len)
+            .ok_or(TunError::InvalidCapture("truncated capture"))
+    }
+}
+
+fn parse_pcap(data: &[u8]) -> Result<Vec<(u16, Record)>, TunError> {
+    let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let (big_endian, nanos) = match magic {
+        PCAP_MAGIC_US => (false, false),
+        PCAP_MAGIC_NS => (false, true),
+        m if m.swap_bytes() == PCAP_MAGIC_US => (true, false),
+        m if m.swap_bytes() == PCAP_MAGIC_NS => (true, true),
+        _ => return Err(TunError::InvalidCapture("unknown file format")),
+    };
+
+    let cur = Cursor { data, big_endian };
+    let link = cur.u32(20)? as u16;
+
+    let mut records = Vec::new();
+    let mut off = 24;
+    while off < data.len() {
+        let secs = cur.u32(off)? as u64;
+        let frac = cur.u32(off + 4)? as u64;
+        let len = cur.u32(off + 8)? as usize;
+        let pkt = cur.bytes(off + 16, len)?;
+
+        let timestamp = match nanos {
+            true => Duration::from_secs(secs) + Duration::from_nanos(frac),
+            false => Duration::from_secs(secs) + Duration::from_micros(frac),
+        };
+
+        records.push((
+            link,
+            Record {
+                timestamp: Some(timestamp),
+                direction: None,
+                data: pkt.to_vec(),
+            },
+        ));
+        off += 16 + len;
+    }
+
+    Ok(records)
+}
+
+/// Interface described by a pcapng interface description block
+struct Interface {
+    link: u16,
+
+    /// Number of timestamp units per second
+    units: u64,
+}
+
+fn parse_pcapng(data: &[u8]) -> Result<Vec<(u16, Record)>, TunError> {
+    let mut records = Vec::new();
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut cur = Cursor {
+        data,
+        big_endian: false,
+    };
+
+    let mut off = 0;
+    while off < data.len() {
+        let block_type = cur.u32(off)?;
+        if block_type == PCAPNG_SECTION_HEADER {
+            // each section may use a different byte order and has its own interfaces
+            let magic = cur.bytes(off + 8, 4)?;
+            cur.big_endian = match u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]) {
+                PCAPNG_BYTE_ORDER_MAGIC => false,
+                m if m.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => true,
+                _ => return Err(TunError::InvalidCapture("invalid byte order magic")),
+            };
+            interfaces.clear();
+        }
+
+        let len = cur.u32(off + 4)? as usize;
+        if len < 12 || !len.is_multiple_of(4) {
+            return Err(TunError::InvalidCapture("invalid block length"));
+        }
+        let body = Cursor {
+            data: cur.bytes(off + 8, len - 12)?,
+            big_endian: cur.big_endian,
+        };
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => {
+                let mut iface = Interface {
+                    link: body.u16(0)?,
+                    units: 1_000_000,
+                };
+
+                for (code, value) in options(&body, 8)? {
+                    if code == OPT_IF_TSRESOL && !value.is_empty() {
+                        let exp = (value[0] & 0x7F) as u32;
+                        iface.units = match value[0] & 0x80 {
+                            0 => 10u64.checked_pow(exp),
+                            _ => 2u64.checked_pow(exp),
+                        }
+                        .ok_or(TunError::InvalidCapture("invalid timestamp resolution"))?;
+                    }
+                }
+
+                interfaces.push(iface);
+            }
+            PCAPNG_ENHANCED_PACKET | PCAPNG_PACKET => {
+                // obsolete packet blocks use a 16-bit interface id followed by drops
+                let iface = match block_type {
+                    PCAPNG_PACKET => body.u16(0)? as usize,
+                    _ => body.u32(0)? as usize,
+                };
+                let iface = interfaces
+                    .get(iface)
+                    .ok_or(TunError::InvalidCapture("unknown interface id"))?;
+
+                let ts = ((body.u32(4)? as u64) << 32) | body.u32(8)? as u64;
+                // fractional part in u128, units can be as fine as 10^19 per second
+                let frac = (ts % iface.units) as u128 * 1_000_000_000 / iface.units as u128;
+                let timestamp =
+                    Duration::from_secs(ts / iface.units) + Duration::from_nanos(frac as u64);
+
+                let caplen = body.u32(12)? as usize;
+                let pkt = body.bytes(20, caplen)?;
+
+                let mut direction = None;
+                for (code, value) in options(&body, 20 + ((caplen + 3) & !3))? {
+                    if code == OPT_EPB_FLAGS && value.len() == 4 {
+                        let flags = Cursor {
+                            data: value,
+                            big_endian: body.big_endian,
+                        }
+                        .u32(0)?;
+                        direction = match flags & 0b11 {
+                            0b01 => Some(Direction::Inbound),
+                            0b10 => Some(Direction::Outbound),
+                            _ => None,
+                        };
+                    }
+                }
+
+                records.push((
+                    iface.link,
+                    Record {
+                        timestamp: Some(timestamp),
+                        direction,
+                        data: pkt.to_vec(),
+                    },
+                ));
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let iface = interfaces
+                    .first()
+                    .ok_or(TunError::InvalidCapture("unknown interface id"))?;
+                let orig_len = body.u32(0)? as usize;
+                let pkt = body.bytes(4, cmp::min(orig_len, body.data.len() - 4))?;
+                records.push((
+                    iface.link,
+                    Record {
+                        timestamp: None,
+                        direction: None,
+                        data: pkt.to_vec(),
+                    },
+                ));
+            }
+            _ => { /* ignore other blocks */ }
+        }
+
+        off += len;
+    }
+
+    Ok(records)
+}
+
+/// Returns the (code, value) options starting at `off`
+fn options<'a>(body: &Cursor<'a>, mut off: usize) -> Result<Vec<(u16, &'a [u8])>, TunError> {
+    let mut opts = Vec::new();
+    while off + 4 <= body.data.len() {
+        let code = body.u16(off)?;
+        let len = body.u16(off + 2)? as usize;
+        if code == OPT_END_OF_OPT {
+            break;
+        }
+
+        opts.push((code, body.bytes(off + 4, len)?));
+        off += 4 + ((len + 3) & !3);
+    }
+    Ok(opts)
+}
+
+/// Returns the offset of the IP packet in a frame, or `None` if the frame should be skipped
+fn strip_link_header(link: u16, frame: &[u8]) -> Result<Option<usize>, TunError> {
+    let (start, ethertype) = match link {
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => return Ok(Some(0)),
+        LINKTYPE_NULL | LINKTYPE_LOOP => return Ok(Some(cmp::min(4, frame.len()))),
+        LINKTYPE_ETHERNET => {
+            let mut start = 12;
+            // skip any 802.1Q / 802.1ad tags
+            while matches!(
+                frame.get(start..start + 2),
+                Some([0x81, 0x00]) | Some([0x88, 0xA8])
+            ) {
+                start += 4;
+            }
+            (start + 2, frame.get(start..start + 2))
+        }
+        LINKTYPE_LINUX_SLL => (16, frame.get(14..16)),
+        _ => return Err(TunError::InvalidCapture("unsupported link type")),
+    };
+
+    match ethertype {
+        Some([0x08, 0x00]) | Some([0x86, 0xDD]) => Ok(Some(start)),
+        _ => Ok(None),
+    }
+}
+
+/// A tun device that replays packets from a capture file
+#[derive(Debug)]
+pub struct ReplayTun {
+    // packets remaining to be returned by read_packet
+    packets: Mutex<VecDeque<Record>>,
+
+    // if set, packets are returned with their original inter-packet timing
+    realtime: bool,
+
+    // (time of first read, timestamp of first packet) used to pace packets
+    clock: Mutex<Option<(Instant, Duration)>>,
+
+    // packets passed to write_packet
+    written: Mutex<Vec<Vec<u8>>>,
+}
+
+impl ReplayTun {
+    /// Loads a pcap or pcapng file to replay
+    ///
+    /// # Arguments
+    /// * `path` - Path to the capture file
+    ///
+    /// # Errors
+    /// * I/O if the file cannot be read
+    /// * The file is not a valid capture (see [`read_capture`])
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TunError> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Loads a pcap or pcapng capture from a stream
+    ///
+    /// # Arguments
+    /// * `reader` - Stream containing the capture
+    ///
+    /// # Errors
+    /// * I/O if reading from the stream fails
+    /// * The stream is not a valid capture (see [`read_capture`])
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, TunError> {
+        Ok(Self::from_records(read_capture(reader)?))
+    }
+
+    /// Creates a device that replays the provided records
+    ///
+    /// # Arguments
+    /// * `records` - Packets to return from `read_packet`
+    pub fn from_records(records: impl IntoIterator<Item = Record>) -> Self {
+        Self {
+            packets: Mutex::new(records.into_iter().collect()),
+            realtime: false,
+            clock: Mutex::new(None),
+            written: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Enables (or disables) honoring the original inter-packet timing
+    ///
+    /// When disabled (the default), packets are returned as fast as they are read.
+    ///
+    /// # Arguments
+    /// * `enabled` - True to delay packets according to their capture timestamps
+    pub fn realtime(mut self, enabled: bool) -> Self {
+        self.realtime = enabled;
+        self
+    }
+
+    /// Only replays packets that travelled in `direction`
+    ///
+    /// Packets without a recorded direction are kept. To replay a capture produced by
+    /// [`Capture`](crate::capture::Capture), use `Direction::Outbound` (the packets the
+    /// application originally read).
+    ///
+    /// # Arguments
+    /// * `direction` - Direction of packets to keep
+    pub fn direction(self, direction: Direction) -> Self {
+        self.lock_packets()
+            .retain(|r| r.direction.map(|d| d == direction).unwrap_or(true));
+        self
+    }
+
+    /// Returns the number of packets that have not been read yet
+    pub fn remaining(&self) -> usize {
+        self.lock_packets().len()
+    }
+
+    /// Returns a copy of every packet passed to `write_packet`
+    pub fn written(&self) -> Vec<Vec<u8>> {
+        self.lock_written().clone()
+    }
+
+    /// Removes and returns every packet passed to `write_packet`
+    pub fn take_written(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut *self.lock_written())
+    }
+
+    /// Writes every packet passed to `write_packet` to a pcapng capture
+    ///
+    /// # Arguments
+    /// * `writer` - Stream to write the capture to
+    ///
+    /// # Errors
+    /// * I/O if writing the capture fails
+    pub fn save_written<W: Write>(&self, writer: W) -> Result<(), TunError> {
+        let mut pcap = PcapngWriter::new(writer, LinkType::Raw)?;
+        let now = SystemTime::now();
+        for pkt in self.lock_written().iter() {
+            pcap.write_packet(pkt, Direction::Inbound, now)?;
+        }
+        pcap.flush()?;
+        Ok(())
+    }
+
+    fn lock_packets(&self) -> std::sync::MutexGuard<'_, VecDeque<Record>> {
+        self.packets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_written(&self) -> std::sync::MutexGuard<'_, Vec<Vec<u8>>> {
+        self.written.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sleeps until `timestamp` is due relative to the first packet read
+    fn pace(&self, timestamp: Duration) {
+        let mut clock = self.clock.lock().unwrap_or_else(|e| e.into_inner());
+        let (start, first) = *clock.get_or_insert((Instant::now(), timestamp));
+        let due = start + timestamp.saturating_sub(first);
+        drop(clock);
+
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+    }
+}
+
+impl Tun for ReplayTun {
+    type PktInfo = ();
+
+    fn up(&self) -> Result<(), TunError> {
+        Ok(())
+    }
+
+    fn down(&self) -> Result<(), TunError> {
+        Ok(())
+    }
+
+    /// Returns the next packet from the capture
+    ///
+    /// # Errors
+    /// * I/O (`UnexpectedEof`) once every packet has been read
+    fn read_packet(&self, buf: &mut [u8]) -> Result<(usize, Self::PktInfo), TunError> {
+        let record = self.lock_packets().pop_front().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "no packets left to replay")
+        })?;
+
+        if let (true, Some(ts)) = (self.realtime, record.timestamp) {
+            self.pace(ts);
+        }
+
+        let len = cmp::min(buf.len(), record.data.len());
+        buf[..len].copy_from_slice(&record.data[..len]);
+        Ok((len, ()))
+    }
+
+    fn write_packet(&self, buf: &[u8], _pi: Self::PktInfo) -> Result<usize, io::Error> {
+        self.lock_written().push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn blank_pktinfo(&self) -> Self::PktInfo {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcapng(packets: &[(&[u8], Direction, u64)]) -> Vec<u8> {
+        let mut pcap = PcapngWriter::new(Vec::new(), LinkType::Raw).unwrap();
+        for (pkt, dir, millis) in packets {
+            let ts = SystemTime::UNIX_EPOCH + Duration::from_millis(*millis);
+            pcap.write_packet(pkt, *dir, ts).unwrap();
+        }
+        pcap.into_inner()
+    }
+
+    #[test]
+    fn replays_pcapng() {
+        let data = pcapng(&[
+            (&[0x45, 1], Direction::Outbound, 0),
+            (&[0x45, 2], Direction::Inbound, 10),
+            (&[0x45, 3], Direction::Outbound, 20),
+        ]);
+
+        let tun = ReplayTun::from_reader(&data[..])
+            .unwrap()
+            .direction(Direction::Outbound);
+        assert_eq!(tun.remaining(), 2);
+
+        let mut buf = [0u8; 16];
+        let (n, _) = tun.read_packet(&mut buf).unwrap();
+        assert_eq!(buf[..n], [0x45, 1]);
+        let (n, _) = tun.read_packet(&mut buf).unwrap();
+        assert_eq!(buf[..n], [0x45, 3]);
+        assert!(
+            matches!(tun.read_packet(&mut buf), Err(TunError::IO(e)) if e.kind() == io::ErrorKind::UnexpectedEof)
+        );
+
+        tun.write_packet(b"reply", ()).unwrap();
+        assert_eq!(tun.take_written(), vec![b"reply".to_vec()]);
+        assert!(tun.written().is_empty());
+    }
+
+    #[test]
+    fn replays_classic_pcap_with_ethernet() {
+        // big-endian, microsecond resolution, LINKTYPE_ETHERNET
+        let mut data = Vec::new();
+        data.extend_from_slice(&PCAP_MAGIC_US.to_be_bytes());
+        data.extend_from_slice(&[0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF]);
+        data.extend_from_slice(&(LINKTYPE_ETHERNET as u32).to_be_bytes());
+
+        for (ethertype, payload) in [([0x08, 0x00], [0x45, 0xAA]), ([0x08, 0x06], [0, 1])] {
+            let mut frame = vec![0u8; 12];
+            frame.extend_from_slice(&ethertype);
+            frame.extend_from_slice(&payload);
+
+            data.extend_from_slice(&1u32.to_be_bytes());
+            data.extend_from_slice(&500u32.to_be_bytes());
+            data.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            data.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            data.extend_from_slice(&frame);
+        }
+
+        // arp frame is skipped
+        let records = read_capture(&data[..]).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].data, [0x45, 0xAA]);
+        assert_eq!(records[0].timestamp, Some(Duration::from_micros(1_000_500)));
+    }
+
+    #[test]
+    fn picosecond_timestamps() {
+        fn block(data: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+            let len = (body.len() + 12) as u32;
+            data.extend_from_slice(&block_type.to_le_bytes());
+            data.extend_from_slice(&len.to_le_bytes());
+            data.extend_from_slice(body);
+            data.extend_from_slice(&len.to_le_bytes());
+        }
+
+        let mut data = Vec::new();
+        let mut body = PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+        body.extend_from_slice(&[1, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        block(&mut data, PCAPNG_SECTION_HEADER, &body);
+
+        // if_tsresol of 10^-12
+        let mut body = LINKTYPE_RAW.to_le_bytes().to_vec();
+        body.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        body.extend_from_slice(&OPT_IF_TSRESOL.to_le_bytes());
+        body.extend_from_slice(&[1, 0, 12, 0, 0, 0]);
+        body.extend_from_slice(&[0, 0, 0, 0]);
+        block(&mut data, PCAPNG_INTERFACE_DESCRIPTION, &body);
+
+        // one day and a quarter second
+        let ts: u64 = 86_400_250_000_000_000;
+        let mut body = 0u32.to_le_bytes().to_vec();
+        body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ts as u32).to_le_bytes());
+        body.extend_from_slice(&[2, 0, 0, 0, 2, 0, 0, 0, 0x45, 0, 0, 0]);
+        block(&mut data, PCAPNG_ENHANCED_PACKET, &body);
+
+        let records = read_capture(&data[..]).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].data, [0x45, 0]);
+        assert_eq!(
+            records[0].timestamp,
+            Some(Duration::from_secs(86_400) + Duration::from_millis(250))
+        );
+    }
+
+    #[test]
+    fn honors_timing() {
+        let data = pcapng(&[
+            (&[0x45], Direction::Outbound, 1000),
+            (&[0x45], Direction::Outbound, 1050),
+        ]);
+        let tun = ReplayTun::from_reader(&data[..]).unwrap().realtime(true);
+
+        let mut buf = [0u8; 16];
+        let start = Instant::now();
+        tun.read_packet(&mut buf).unwrap();
+        tun.read_packet(&mut buf).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn roundtrip_written() {
+        let tun = ReplayTun::from_records(Vec::new());
+        tun.write_packet(&[0x45, 0x00], ()).unwrap();
+        tun.write_packet(&[0x60, 0x00], ()).unwrap();
+
+        let mut out = Vec::new();
+        tun.save_written(&mut out).unwrap();
+
+        let records = read_capture(&out[..]).unwrap();
+        let data: Vec<_> = records.iter().map(|r| r.data.clone()).collect();
+        assert_eq!(data, tun.written());
+        assert!(records
+            .iter()
+            .all(|r| r.direction == Some(Direction::Inbound)));
+    }
+}
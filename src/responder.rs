@@ -0,0 +1,274 @@
+//! ICMP / ICMPv6 echo responder
+//!
+//! Wraps any [`Tun`] implementor and answers pings addressed to the device without
+//! involving the application. All other packets are passed through to the caller.
+
+use crate::{
+    checksum::{self, PROTO_ICMP, PROTO_ICMPV6},
+    Tun, TunConfig, TunError,
+};
+use std::{io, net::IpAddr, time::Duration};
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// TTL / hop limit set on generated replies
+const REPLY_TTL: u8 = 64;
+
+/// A tun device wrapper that replies to echo requests addressed to the device
+#[derive(Debug)]
+pub struct EchoResponder<T: Tun> {
+    // wrapped device
+    tun: T,
+
+    // addresses we answer echo requests for
+    addrs: Vec<IpAddr>,
+}
+
+impl<T: Tun> EchoResponder<T> {
+    /// Wraps `tun`, answering echo requests sent to the ip configured in `cfg`
+    ///
+    /// # Arguments
+    /// * `tun` - Device to wrap
+    /// * `cfg` - Configuration used to create `tun`
+    pub fn new(tun: T, cfg: &TunConfig) -> Self {
+        Self {
+            tun,
+            addrs: cfg.ip.iter().map(|(ip, _)| *ip).collect(),
+        }
+    }
+
+    /// Answers echo requests sent to `ip` in addition to the configured addresses
+    ///
+    /// # Arguments
+    /// * `ip` - Additional address to respond on
+    pub fn address(mut self, ip: impl Into<IpAddr>) -> Self {
+        self.addrs.push(ip.into());
+        self
+    }
+
+    /// Returns a reference to the wrapped device
+    pub fn get_ref(&self) -> &T {
+        &self.tun
+    }
+
+    /// Consumes the responder, returning the wrapped device
+    pub fn into_inner(self) -> T {
+        self.tun
+    }
+
+    /// Turns an echo request addressed to us into an echo reply (in place)
+    ///
+    /// Returns false if the packet is not an echo request for one of our addresses
+    fn make_reply(&self, pkt: &mut [u8]) -> bool {
+        match pkt.first().map(|b| b >> 4) {
+            Some(4) => self.make_reply_v4(pkt),
+            Some(6) => self.make_reply_v6(pkt),
+            _ => false,
+        }
+    }
+
+    fn make_reply_v4(&self, pkt: &mut [u8]) -> bool {
+        if pkt.len() < 20 {
+            return false;
+        }
+
+        let hdr_len = ((pkt[0] & 0x0F) as usize) * 4;
+        let total_len = u16::from_be_bytes([pkt[2], pkt[3]]) as usize;
+        let fragmented = u16::from_be_bytes([pkt[6], pkt[7]]) & 0x3FFF != 0;
+        if pkt[9] != PROTO_ICMP
+            || fragmented
+            || hdr_len < 20
+            || total_len > pkt.len()
+            || total_len < hdr_len + 8
+            || pkt[hdr_len] != ICMP_ECHO_REQUEST
+        {
+            return false;
+        }
+
+        let dst = IpAddr::from([pkt[16], pkt[17], pkt[18], pkt[19]]);
+        if !self.addrs.contains(&dst) || checksum::swap_endpoints(pkt).is_err() {
+            return false;
+        }
+
+        // reset ttl (shares a 16-bit word with the protocol)
+        let old = u16::from_be_bytes([pkt[8], pkt[9]]);
+        pkt[8] = REPLY_TTL;
+        patch(pkt, 10, old, u16::from_be_bytes([pkt[8], pkt[9]]));
+
+        // echo request -> echo reply (type shares a 16-bit word with the code)
+        let old = u16::from_be_bytes([pkt[hdr_len], pkt[hdr_len + 1]]);
+        pkt[hdr_len] = ICMP_ECHO_REPLY;
+        let new = u16::from_be_bytes([pkt[hdr_len], pkt[hdr_len + 1]]);
+        patch(pkt, hdr_len + 2, old, new);
+
+        true
+    }
+
+    fn make_reply_v6(&self, pkt: &mut [u8]) -> bool {
+        const HDR_LEN: usize = 40;
+        if pkt.len() < HDR_LEN + 8
+            || pkt[6] != PROTO_ICMPV6
+            || pkt[HDR_LEN] != ICMPV6_ECHO_REQUEST
+            || HDR_LEN + u16::from_be_bytes([pkt[4], pkt[5]]) as usize > pkt.len()
+        {
+            return false;
+        }
+
+        let mut dst = [0u8; 16];
+        dst.copy_from_slice(&pkt[24..40]);
+        if !self.addrs.contains(&IpAddr::from(dst)) || checksum::swap_endpoints(pkt).is_err() {
+            return false;
+        }
+
+        // hop limit isn't covered by any checksum
+        pkt[7] = REPLY_TTL;
+
+        let old = u16::from_be_bytes([pkt[HDR_LEN], pkt[HDR_LEN + 1]]);
+        pkt[HDR_LEN] = ICMPV6_ECHO_REPLY;
+        let new = u16::from_be_bytes([pkt[HDR_LEN], pkt[HDR_LEN + 1]]);
+        patch(pkt, HDR_LEN + 2, old, new);
+
+        true
+    }
+}
+
+/// Incrementally updates the checksum stored at `offset` after a 16-bit word changed
+fn patch(pkt: &mut [u8], offset: usize, old: u16, new: u16) {
+    let csum = u16::from_be_bytes([pkt[offset], pkt[offset + 1]]);
+    let csum = checksum::update_u16(csum, old, new);
+    pkt[offset..offset + 2].copy_from_slice(&csum.to_be_bytes());
+}
+
+impl<T> Tun for EchoResponder<T>
+where
+    T: Tun,
+    T::PktInfo: Clone,
+{
+    type PktInfo = T::PktInfo;
+
+    fn up(&self) -> Result<(), TunError> {
+        self.tun.up()
+    }
+
+    fn down(&self) -> Result<(), TunError> {
+        self.tun.down()
+    }
+
+    /// Reads the next packet that isn't an echo request addressed to this device
+    ///
+    /// Echo requests are answered (using the packet info they were received with) and
+    /// never returned to the caller.
+    fn read_packet(&self, buf: &mut [u8]) -> Result<(usize, Self::PktInfo), TunError> {
+        loop {
+            let (n, pi) = self.tun.read_packet(buf)?;
+            if !self.make_reply(&mut buf[..n]) {
+                return Ok((n, pi));
+            }
+
+            tracing::trace!("answering echo request");
+            if let Err(error) = self.tun.write_packet(&buf[..n], pi) {
+                tracing::warn!(?error, "failed to write echo reply");
+            }
+        }
+    }
+
+    fn write_packet(&self, buf: &[u8], pi: Self::PktInfo) -> Result<usize, io::Error> {
+        self.tun.write_packet(buf, pi)
+    }
+
+    fn blank_pktinfo(&self) -> Self::PktInfo {
+        self.tun.blank_pktinfo()
+    }
+
+    fn poll_readable(&self, timeout: Duration) -> Result<bool, TunError> {
+        self.tun.poll_readable(timeout)
+    }
+}
+
+#[cfg(all(test, feature = "channel"))]
+mod tests {
+    use super::*;
+    use crate::ChannelTun;
+    use std::net::Ipv6Addr;
+
+    fn echo_request_v4(dst: [u8; 4]) -> Vec<u8> {
+        let mut pkt = vec![
+            0x45, 0x00, 0x00, 0x20, 0x00, 0x01, 0x00, 0x00, 0x08, 0x01, 0x00, 0x00, 10, 0, 0, 1,
+            dst[0], dst[1], dst[2], dst[3], 0x08, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00, 0x01, b'p',
+            b'i', b'n', b'g',
+        ];
+        checksum::fill(&mut pkt).unwrap();
+        pkt
+    }
+
+    fn echo_request_v6(src: Ipv6Addr, dst: Ipv6Addr) -> Vec<u8> {
+        let mut pkt = vec![0u8; 52];
+        pkt[0] = 0x60;
+        pkt[4..6].copy_from_slice(&12u16.to_be_bytes());
+        pkt[6] = PROTO_ICMPV6;
+        pkt[7] = 1;
+        pkt[8..24].copy_from_slice(&src.octets());
+        pkt[24..40].copy_from_slice(&dst.octets());
+        pkt[40] = ICMPV6_ECHO_REQUEST;
+        pkt[44..52].copy_from_slice(&[0x12, 0x34, 0x00, 0x01, b'p', b'i', b'n', b'g']);
+        checksum::fill(&mut pkt).unwrap();
+        pkt
+    }
+
+    #[test]
+    fn answers_ipv4_echo() {
+        let cfg = TunConfig::default().ip([10, 0, 0, 2], 24);
+        let (local, peer) = ChannelTun::create("echo0", cfg.clone()).unwrap();
+        let responder = EchoResponder::new(local, &cfg);
+
+        // echo request for another host should be passed through
+        let other = echo_request_v4([10, 0, 0, 3]);
+        peer.write_packet(&echo_request_v4([10, 0, 0, 2]), ())
+            .unwrap();
+        peer.write_packet(&other, ()).unwrap();
+
+        let mut buf = [0u8; 1500];
+        let (n, _) = responder.read_packet(&mut buf).unwrap();
+        assert_eq!(buf[..n], other[..]);
+
+        let (n, _) = peer.read_packet(&mut buf).unwrap();
+        let reply = &buf[..n];
+        assert_eq!(reply[8], REPLY_TTL);
+        assert_eq!(reply[12..16], [10, 0, 0, 2]);
+        assert_eq!(reply[16..20], [10, 0, 0, 1]);
+        assert_eq!(reply[20], ICMP_ECHO_REPLY);
+
+        let mut expected = reply.to_vec();
+        checksum::fill(&mut expected).unwrap();
+        assert_eq!(reply, &expected[..]);
+    }
+
+    #[test]
+    fn answers_ipv6_echo() {
+        let ours: Ipv6Addr = "fd00::2".parse().unwrap();
+        let theirs: Ipv6Addr = "fd00::1".parse().unwrap();
+        let (local, peer) = ChannelTun::create("echo0", TunConfig::default()).unwrap();
+        let responder = EchoResponder::new(local, &TunConfig::default()).address(ours);
+
+        peer.write_packet(&echo_request_v6(theirs, ours), ())
+            .unwrap();
+        peer.write_packet(b"not a packet", ()).unwrap();
+
+        let mut buf = [0u8; 1500];
+        let (n, _) = responder.read_packet(&mut buf).unwrap();
+        assert_eq!(buf[..n], b"not a packet"[..]);
+
+        let (n, _) = peer.read_packet(&mut buf).unwrap();
+        let reply = &buf[..n];
+        assert_eq!(reply[8..24], ours.octets());
+        assert_eq!(reply[24..40], theirs.octets());
+        assert_eq!(reply[40], ICMPV6_ECHO_REPLY);
+
+        let mut expected = reply.to_vec();
+        checksum::fill(&mut expected).unwrap();
+        assert_eq!(reply, &expected[..]);
+    }
+}
@@ -0,0 +1,384 @@
+//! Restricting packet workers to the device's data path with seccomp-bpf
+//!
+//! Once a device is created and configured, a worker that only moves packets needs a handful
+//! of syscalls. Installing a [`SeccompFilter`] rejects everything else, limiting what an
+//! attacker can do with a compromised packet parser.
+//!
+//! ```no_run
+//! use tun_rs::{seccomp::SeccompFilter, OsTun, TunConfig};
+//!
+//! let tun = OsTun::create(TunConfig::default().name("tun0").ip([10, 0, 0, 1], 24).up(true))?;
+//! tun.commit();
+//! SeccompFilter::data_path().install()?;
+//! # Ok::<(), tun_rs::TunError>(())
+//! ```
+
+use crate::TunError;
+use std::{io, mem::offset_of};
+
+// AUDIT_ARCH_* (linux/audit.h) of the syscall ABI this crate is compiled for
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "x86")]
+const AUDIT_ARCH: Option<u32> = Some(0x4000_0003);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(target_arch = "arm")]
+const AUDIT_ARCH: Option<u32> = Some(0x4000_0028);
+#[cfg(target_arch = "riscv64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00f3);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "x86",
+    target_arch = "aarch64",
+    target_arch = "arm",
+    target_arch = "riscv64"
+)))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// Syscalls used to move packets through a device (`OsTun`'s `Read` / `Write` and
+/// `Tun::read_packet` / `Tun::write_packet`)
+const DATA_PATH: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_ppoll,
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    libc::SYS_poll,
+];
+
+/// Syscalls the standard library and allocator need to keep running
+const RUNTIME: &[libc::c_long] = &[
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_futex,
+    libc::SYS_sched_yield,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_sigaltstack,
+    libc::SYS_restart_syscall,
+    libc::SYS_close,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+];
+
+/// Syscalls used by epoll based async runtimes (e.g., `mio` / `tokio`) to wait for devices
+const ASYNC_RUNTIME: &[libc::c_long] = &[
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    libc::SYS_epoll_wait,
+    libc::SYS_eventfd2,
+];
+
+/// What happens when a syscall that isn't allowed is made
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// The syscall fails with this errno (e.g., `libc::EPERM`)
+    Errno(i32),
+
+    /// The process is killed with `SIGSYS`
+    KillProcess,
+}
+
+/// A seccomp-bpf filter that only allows an explicit list of syscalls
+#[derive(Clone, Debug)]
+pub struct SeccompFilter {
+    syscalls: Vec<libc::c_long>,
+    violation: Violation,
+    all_threads: bool,
+}
+
+impl SeccompFilter {
+    /// Creates a filter allowing the syscalls used by the device's data path
+    ///
+    /// Reading and writing (including vectored I/O), polling, and the syscalls the standard
+    /// library needs to allocate memory, synchronize threads, handle signals and exit are
+    /// allowed. Other syscalls fail with `EPERM`, including creating sockets or opening
+    /// files, so the device can't be reconfigured once the filter is installed.
+    pub fn data_path() -> Self {
+        Self {
+            syscalls: [DATA_PATH, RUNTIME].concat(),
+            violation: Violation::Errno(libc::EPERM),
+            all_threads: true,
+        }
+    }
+
+    /// Allows (or stops allowing) the syscalls used by epoll based async runtimes
+    ///
+    /// # Arguments
+    /// * `enabled` - True to allow `epoll_*` and `eventfd2`
+    pub fn async_runtime(mut self, enabled: bool) -> Self {
+        self.syscalls.retain(|nr| !ASYNC_RUNTIME.contains(nr));
+        if enabled {
+            self.syscalls.extend_from_slice(ASYNC_RUNTIME);
+        }
+        self
+    }
+
+    /// Allows an additional syscall
+    ///
+    /// # Arguments
+    /// * `nr` - Syscall number (e.g., `libc::SYS_getrandom`)
+    pub fn allow(mut self, nr: libc::c_long) -> Self {
+        if !self.syscalls.contains(&nr) {
+            self.syscalls.push(nr);
+        }
+        self
+    }
+
+    /// Sets what happens when a syscall that isn't allowed is made
+    ///
+    /// Defaults to failing the syscall with `EPERM`.
+    ///
+    /// # Arguments
+    /// * `violation` - Action taken by the kernel
+    pub fn on_violation(mut self, violation: Violation) -> Self {
+        self.violation = violation;
+        self
+    }
+
+    /// Applies the filter to every thread of the process (default), or only the calling one
+    ///
+    /// Threads spawned later always inherit the filter of the thread spawning them.
+    ///
+    /// # Arguments
+    /// * `enabled` - True to synchronize the filter across all threads
+    pub fn all_threads(mut self, enabled: bool) -> Self {
+        self.all_threads = enabled;
+        self
+    }
+
+    /// Returns the syscalls allowed by this filter
+    pub fn syscalls(&self) -> &[libc::c_long] {
+        &self.syscalls
+    }
+
+    /// Installs the filter
+    ///
+    /// Sets `no_new_privs` first (required to install filters without `CAP_SYS_ADMIN`).
+    /// Filters can't be removed, installing another filter can only restrict the process
+    /// further.
+    ///
+    /// # Errors
+    /// * `SeccompFailed` if seccomp isn't supported on this architecture or kernel, or
+    ///   another thread can't be synchronized (e.g., it installed its own filter)
+    pub fn install(&self) -> Result<(), TunError> {
+        let program = self.program()?;
+        let prog = libc::sock_fprog {
+            len: program.len() as _,
+            filter: program.as_ptr() as *mut _,
+        };
+
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } == -1 {
+            return Err(TunError::SeccompFailed {
+                op: "prctl(PR_SET_NO_NEW_PRIVS)",
+                source: io::Error::last_os_error(),
+            });
+        }
+
+        let flags = match self.all_threads {
+            true => libc::SECCOMP_FILTER_FLAG_TSYNC,
+            false => 0,
+        };
+
+        tracing::debug!(
+            "installing seccomp filter allowing {} syscalls",
+            self.syscalls.len()
+        );
+        // SAFETY: prog points to a valid filter program that outlives the call
+        match unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                flags,
+                &prog as *const libc::sock_fprog,
+            )
+        } {
+            0 => Ok(()),
+            -1 => Err(TunError::SeccompFailed {
+                op: "seccomp(SECCOMP_SET_MODE_FILTER)",
+                source: io::Error::last_os_error(),
+            }),
+            // id of a thread that couldn't be synchronized
+            tid => Err(TunError::SeccompFailed {
+                op: "seccomp(SECCOMP_FILTER_FLAG_TSYNC)",
+                source: io::Error::other(format!("thread {tid} has a conflicting filter")),
+            }),
+        }
+    }
+
+    /// Builds the BPF program
+    ///
+    /// Syscalls from other ABIs kill the process: 32-bit syscalls on x86_64 fail the
+    /// architecture check, x32 syscalls don't match any number (`__X32_SYSCALL_BIT` is set)
+    /// and get the violation action.
+    fn program(&self) -> Result<Vec<libc::sock_filter>, TunError> {
+        let arch = AUDIT_ARCH.ok_or_else(|| TunError::SeccompFailed {
+            op: "seccomp",
+            source: io::ErrorKind::Unsupported.into(),
+        })?;
+
+        let violation = match self.violation {
+            Violation::Errno(errno) => {
+                libc::SECCOMP_RET_ERRNO | (errno as u32 & libc::SECCOMP_RET_DATA)
+            }
+            Violation::KillProcess => libc::SECCOMP_RET_KILL_PROCESS,
+        };
+
+        let mut program = vec![
+            stmt(
+                libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+                offset_of!(libc::seccomp_data, arch) as u32,
+            ),
+            jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, arch, 1, 0),
+            stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(
+                libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+                offset_of!(libc::seccomp_data, nr) as u32,
+            ),
+        ];
+        for &nr in &self.syscalls {
+            program.push(jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                nr as u32,
+                0,
+                1,
+            ));
+            program.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+        }
+        program.push(stmt(libc::BPF_RET | libc::BPF_K, violation));
+
+        Ok(program)
+    }
+}
+
+/// Builds a BPF statement (`BPF_STMT`)
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+/// Builds a BPF jump (`BPF_JUMP`), skipping `jt` / `jf` instructions
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs::File,
+        io::{Read, Write},
+        os::unix::io::FromRawFd,
+    };
+
+    /// Runs `f` in a forked child (filters can't be removed), returning its wait status
+    fn in_child(f: impl FnOnce() -> bool) -> i32 {
+        match unsafe { libc::fork() } {
+            0 => {
+                let ok = f();
+                unsafe { libc::_exit(if ok { 0 } else { 1 }) }
+            }
+            -1 => panic!("fork failed: {}", io::Error::last_os_error()),
+            pid => {
+                let mut status = 0;
+                assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+                status
+            }
+        }
+    }
+
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn forbidden_syscalls_fail() {
+        let status = in_child(|| {
+            let (mut rx, mut tx) = pipe();
+            if SeccompFilter::data_path().install().is_err() {
+                return false;
+            }
+
+            // the data path keeps working
+            let mut buf = [0u8; 5];
+            let data_path = tx.write_all(b"hello").is_ok()
+                && rx.read_exact(&mut buf).is_ok()
+                && &buf == b"hello";
+
+            // everything else is rejected
+            let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+            let socket_denied =
+                socket == -1 && io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+            let open_denied =
+                File::open("/dev/null").is_err_and(|e| e.kind() == io::ErrorKind::PermissionDenied);
+            let epoll = unsafe { libc::epoll_create1(0) } == -1;
+
+            data_path && socket_denied && open_denied && epoll
+        });
+        assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+
+        let status = in_child(|| {
+            SeccompFilter::data_path()
+                .async_runtime(true)
+                .install()
+                .is_ok()
+                && unsafe { libc::epoll_create1(0) } >= 0
+        });
+        assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+    }
+
+    #[test]
+    fn forbidden_syscalls_kill() {
+        let status = in_child(|| {
+            let filter = SeccompFilter::data_path().on_violation(Violation::KillProcess);
+            if filter.install().is_err() {
+                return false;
+            }
+            unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+            true
+        });
+        assert!(libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGSYS);
+    }
+
+    #[test]
+    fn builds_program() {
+        let filter = SeccompFilter::data_path()
+            .allow(libc::SYS_getrandom)
+            .allow(libc::SYS_getrandom)
+            .async_runtime(true)
+            .async_runtime(true);
+        let count = DATA_PATH.len() + RUNTIME.len() + ASYNC_RUNTIME.len() + 1;
+        assert_eq!(filter.syscalls().len(), count);
+
+        // arch check, syscall load, a jump and return per syscall, default action
+        let program = filter.program().unwrap();
+        assert_eq!(program.len(), 3 + 1 + 2 * count + 1);
+        assert_eq!(
+            program.last().unwrap().k,
+            libc::SECCOMP_RET_ERRNO | libc::EPERM as u32
+        );
+        assert_eq!(
+            SeccompFilter::data_path()
+                .async_runtime(false)
+                .syscalls()
+                .len(),
+            DATA_PATH.len() + RUNTIME.len()
+        );
+    }
+}
@@ -0,0 +1,682 @@
+//! UDP encapsulation tunnel
+//!
+//! [`UdpTunnel`] pumps packets between a tun device and a [`UdpSocket`]: every packet read
+//! from the device is sent to the peer as a single datagram and every datagram received from
+//! the peer is written to the device. Optionally, datagrams carry a small header with the
+//! payload length and a sequence number so loss and reordering can be detected.
+
+use crate::{DynTun, PacketInfo, TunError};
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Size of the largest packet that can be forwarded
+const MAX_PACKET_SIZE: usize = 65535;
+
+/// Size of the header added when framing is enabled
+pub const FRAME_HEADER_LEN: usize = 8;
+
+const FRAME_DATA: u8 = 0;
+const FRAME_KEEPALIVE: u8 = 1;
+
+/// Frames further behind the expected sequence number than this are taken as the peer
+/// having restarted rather than as late or duplicate frames
+const MAX_REORDER: u32 = 1024;
+
+/// How often the receiving thread wakes up to check for keepalives / shutdown
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Encapsulation used for packets sent over UDP
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// Each datagram contains exactly one packet, keepalives are empty datagrams
+    None,
+
+    /// Each datagram starts with a header containing the frame type, payload length and a
+    /// sequence number
+    ///
+    /// Header layout (network byte order):
+    /// `| type (1) | reserved (1) | length (2) | sequence (4) |`
+    LengthSequence,
+}
+
+/// Counters describing the traffic forwarded by a tunnel
+#[derive(Debug, Default)]
+pub struct TunnelStats {
+    tx_packets: AtomicU64,
+    rx_packets: AtomicU64,
+    keepalives: AtomicU64,
+    dropped: AtomicU64,
+    lost: AtomicU64,
+}
+
+impl TunnelStats {
+    /// Returns the number of packets sent to the peer
+    pub fn tx_packets(&self) -> u64 {
+        self.tx_packets.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of packets received from the peer and written to the device
+    pub fn rx_packets(&self) -> u64 {
+        self.rx_packets.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of keepalives sent to the peer
+    pub fn keepalives(&self) -> u64 {
+        self.keepalives.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of packets dropped (no peer, malformed or unexpected datagrams)
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of packets detected as lost (gaps in sequence numbers)
+    ///
+    /// Only tracked when [`Framing::LengthSequence`] is used.
+    pub fn lost(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+/// Forwards packets between a tun device and a UDP socket
+#[derive(Debug)]
+pub struct UdpTunnel<T> {
+    tun: T,
+    socket: UdpSocket,
+
+    // fixed peer, or the last address a valid datagram was received from
+    peer: Mutex<Option<SocketAddr>>,
+    fixed_peer: bool,
+
+    keepalive: Option<Duration>,
+    framing: Framing,
+
+    // sequence number of the next frame sent and next frame expected
+    tx_seq: AtomicU64,
+    rx_seq: Mutex<Option<u32>>,
+
+    // time of the last datagram sent (used to schedule keepalives)
+    last_tx: Mutex<Instant>,
+
+    stats: Arc<TunnelStats>,
+}
+
+impl<T: DynTun> UdpTunnel<T> {
+    /// Creates a new tunnel between `tun` and `socket`
+    ///
+    /// Unless a peer is set with [`peer`](Self::peer), the peer is learned from the source
+    /// address of received datagrams and packets read before then are dropped. Learning is
+    /// unauthenticated: anyone able to send a well-formed datagram (any datagram with
+    /// [`Framing::None`]) to the socket redirects the tunnel to themselves.
+    ///
+    /// # Arguments
+    /// * `tun` - Device to forward packets from / to
+    /// * `socket` - Bound UDP socket used to reach the peer
+    pub fn new(tun: T, socket: UdpSocket) -> Self {
+        Self {
+            tun,
+            socket,
+            peer: Mutex::new(None),
+            fixed_peer: false,
+            keepalive: None,
+            framing: Framing::None,
+            tx_seq: AtomicU64::new(0),
+            rx_seq: Mutex::new(None),
+            last_tx: Mutex::new(Instant::now()),
+            stats: Arc::new(TunnelStats::default()),
+        }
+    }
+
+    /// Sets the address of the remote end of the tunnel
+    ///
+    /// Datagrams from any other address are dropped.
+    ///
+    /// # Arguments
+    /// * `peer` - Address of the remote tunnel endpoint
+    pub fn peer(mut self, peer: SocketAddr) -> Self {
+        self.peer = Mutex::new(Some(peer));
+        self.fixed_peer = true;
+        self
+    }
+
+    /// Sends a keepalive to the peer whenever nothing was sent for `interval`
+    ///
+    /// Keepalives keep NAT / firewall mappings open and let a peer without a fixed address
+    /// learn ours. They are never written to the device.
+    ///
+    /// # Arguments
+    /// * `interval` - Maximum idle time before a keepalive is sent
+    pub fn keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+        self
+    }
+
+    /// Sets the encapsulation used for datagrams (default: [`Framing::None`])
+    ///
+    /// Both ends of the tunnel must use the same framing.
+    ///
+    /// # Arguments
+    /// * `framing` - Encapsulation to use
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Returns the traffic counters for this tunnel
+    pub fn stats(&self) -> Arc<TunnelStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Returns the current peer (fixed or learned), if any
+    pub fn current_peer(&self) -> Option<SocketAddr> {
+        *self.peer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns a reference to the tun device
+    pub fn get_ref(&self) -> &T {
+        &self.tun
+    }
+
+    /// Returns a reference to the UDP socket
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Reads one packet from the device and sends it to the peer
+    ///
+    /// Returns the size of the packet read, which is dropped if no peer is known yet.
+    ///
+    /// # Arguments
+    /// * `buf` - Scratch buffer, must be at least [`FRAME_HEADER_LEN`] bytes larger than the
+    ///   largest packet
+    ///
+    /// # Errors
+    /// * I/O if reading from the device or sending the datagram fails
+    pub fn forward_from_tun(&self, buf: &mut [u8]) -> Result<usize, TunError> {
+        let offset = match self.framing {
+            Framing::None => 0,
+            Framing::LengthSequence => FRAME_HEADER_LEN,
+        };
+        if buf.len() <= offset {
+            return Err(TunError::BufferTooSmall);
+        }
+
+        let (n, _pi) = self.tun.read_packet(&mut buf[offset..])?;
+        let peer = match self.current_peer() {
+            Some(peer) => peer,
+            None => {
+                tracing::trace!("no peer known, dropping packet");
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                return Ok(n);
+            }
+        };
+
+        if self.framing == Framing::LengthSequence {
+            self.write_header(&mut buf[..FRAME_HEADER_LEN], FRAME_DATA, n as u16);
+        }
+
+        self.send(&buf[..offset + n], peer)?;
+        self.stats.tx_packets.fetch_add(1, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    /// Receives one datagram from the peer and writes its packet to the device
+    ///
+    /// Returns the size of the packet written, or 0 if the datagram was a keepalive or was
+    /// dropped.
+    ///
+    /// # Arguments
+    /// * `buf` - Scratch buffer, must be large enough for the largest datagram
+    ///
+    /// # Errors
+    /// * I/O if receiving the datagram or writing to the device fails (including timeouts
+    ///   if the socket has a read timeout set)
+    pub fn forward_to_tun(&self, buf: &mut [u8]) -> Result<usize, TunError> {
+        let (n, from) = self.socket.recv_from(buf)?;
+
+        if self.fixed_peer && self.current_peer().is_some_and(|addr| addr != from) {
+            tracing::debug!(%from, "dropping datagram from unexpected address");
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            return Ok(0);
+        }
+
+        let (range, seq) = match self.framing {
+            Framing::None => (0..n, None),
+            Framing::LengthSequence => match parse_header(&buf[..n]) {
+                Some(frame) => frame,
+                None => {
+                    self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Ok(0);
+                }
+            },
+        };
+
+        // malformed frames are never learned from, but with `Framing::None` every datagram
+        // is well-formed (see `new`)
+        {
+            let mut peer = self.peer.lock().unwrap_or_else(|e| e.into_inner());
+            if *peer != Some(from) {
+                tracing::debug!(%from, "learned tunnel peer");
+                *peer = Some(from);
+
+                // the new peer numbers its frames independently
+                *self.rx_seq.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            }
+        }
+
+        if let Some(seq) = seq {
+            if !self.accept_seq(seq) {
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                return Ok(0);
+            }
+        }
+
+        let pkt = &buf[range];
+
+        if pkt.is_empty() {
+            // keepalive
+            return Ok(0);
+        }
+
+        self.tun.write_packet(pkt, PacketInfo::for_packet(pkt))?;
+        self.stats.rx_packets.fetch_add(1, Ordering::Relaxed);
+        Ok(pkt.len())
+    }
+
+    /// Sends a keepalive if nothing has been sent for the keepalive interval
+    ///
+    /// # Errors
+    /// * I/O if sending the keepalive fails
+    pub fn send_keepalive_if_idle(&self) -> Result<(), TunError> {
+        let (interval, peer) = match (self.keepalive, self.current_peer()) {
+            (Some(interval), Some(peer)) => (interval, peer),
+            _ => return Ok(()),
+        };
+
+        let idle = self
+            .last_tx
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .elapsed();
+        if idle < interval {
+            return Ok(());
+        }
+
+        let mut hdr = [0u8; FRAME_HEADER_LEN];
+        let frame = match self.framing {
+            Framing::None => &hdr[..0],
+            Framing::LengthSequence => {
+                self.write_header(&mut hdr, FRAME_KEEPALIVE, 0);
+                &hdr[..]
+            }
+        };
+
+        tracing::trace!(%peer, "sending keepalive");
+        self.send(frame, peer)?;
+        self.stats.keepalives.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn send(&self, datagram: &[u8], peer: SocketAddr) -> io::Result<()> {
+        self.socket.send_to(datagram, peer)?;
+        *self.last_tx.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+        Ok(())
+    }
+
+    fn write_header(&self, hdr: &mut [u8], kind: u8, len: u16) {
+        // keepalives don't consume a sequence number so they don't look like loss
+        let seq = match kind {
+            FRAME_DATA => self.tx_seq.fetch_add(1, Ordering::Relaxed) as u32,
+            _ => 0,
+        };
+
+        hdr[0] = kind;
+        hdr[1] = 0;
+        hdr[2..4].copy_from_slice(&len.to_be_bytes());
+        hdr[4..8].copy_from_slice(&seq.to_be_bytes());
+    }
+
+    /// Checks the sequence number of a data frame from the peer, counting any frames lost
+    /// before it
+    ///
+    /// Returns false if the frame is late or a duplicate.
+    fn accept_seq(&self, seq: u32) -> bool {
+        let mut expected = self.rx_seq.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(expected) = *expected {
+            let gap = seq.wrapping_sub(expected);
+            if gap > u32::MAX / 2 {
+                let behind = expected.wrapping_sub(seq);
+                if behind <= MAX_REORDER {
+                    tracing::debug!(seq, expected, "dropping late or duplicate frame");
+                    return false;
+                }
+                tracing::debug!(seq, expected, "peer restarted its sequence numbers");
+            } else {
+                self.stats.lost.fetch_add(gap as u64, Ordering::Relaxed);
+            }
+        }
+        *expected = Some(seq.wrapping_add(1));
+        true
+    }
+}
+
+/// Validates a framed datagram, returning the range of its payload and, for data frames, its
+/// sequence number
+fn parse_header(datagram: &[u8]) -> Option<(std::ops::Range<usize>, Option<u32>)> {
+    if datagram.len() < FRAME_HEADER_LEN {
+        tracing::debug!("dropping datagram shorter than frame header");
+        return None;
+    }
+
+    let len = u16::from_be_bytes([datagram[2], datagram[3]]) as usize;
+    let seq = u32::from_be_bytes([datagram[4], datagram[5], datagram[6], datagram[7]]);
+    if FRAME_HEADER_LEN + len != datagram.len() {
+        tracing::debug!(len, "dropping datagram with invalid frame length");
+        return None;
+    }
+
+    match datagram[0] {
+        FRAME_KEEPALIVE => Some((0..0, None)),
+        FRAME_DATA => Some((FRAME_HEADER_LEN..datagram.len(), Some(seq))),
+        kind => {
+            tracing::debug!(kind, "dropping datagram with unknown frame type");
+            None
+        }
+    }
+}
+
+impl<T> UdpTunnel<T>
+where
+    T: DynTun + Send + Sync + 'static,
+{
+    /// Starts forwarding in both directions on background threads
+    ///
+    /// # Errors
+    /// * I/O if the socket's read timeout cannot be set or a thread cannot be spawned
+    pub fn spawn(self) -> Result<TunnelHandle, TunError> {
+        let poll = self
+            .keepalive
+            .map(|k| k.min(POLL_INTERVAL))
+            .unwrap_or(POLL_INTERVAL);
+        self.socket.set_read_timeout(Some(poll))?;
+
+        let tunnel = Arc::new(self);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let outbound = thread::Builder::new().name("tunnel-tx".into()).spawn({
+            let tunnel = Arc::clone(&tunnel);
+            let stop = Arc::clone(&stop);
+            move || tunnel.pump_outbound(&stop)
+        })?;
+
+        let inbound = thread::Builder::new().name("tunnel-rx".into()).spawn({
+            let tunnel = Arc::clone(&tunnel);
+            let stop = Arc::clone(&stop);
+            move || tunnel.pump_inbound(&stop)
+        })?;
+
+        Ok(TunnelHandle {
+            stop,
+            stats: tunnel.stats(),
+            threads: vec![outbound, inbound],
+        })
+    }
+
+    fn pump_outbound(&self, stop: &AtomicBool) -> Result<(), TunError> {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE + FRAME_HEADER_LEN];
+        while !stop.load(Ordering::Relaxed) {
+            match self.forward_from_tun(&mut buf) {
+                Ok(_) => (),
+                Err(TunError::IO(e)) if is_transient(&e) => (),
+                Err(error) => {
+                    tracing::warn!(?error, "tunnel failed to forward packet from device");
+                    return Err(error);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn pump_inbound(&self, stop: &AtomicBool) -> Result<(), TunError> {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE + FRAME_HEADER_LEN];
+        while !stop.load(Ordering::Relaxed) {
+            match self.forward_to_tun(&mut buf) {
+                Ok(_) => (),
+                // ICMP port unreachable from a peer that isn't listening (yet)
+                Err(TunError::IO(e)) if e.kind() == io::ErrorKind::ConnectionRefused => (),
+                Err(TunError::IO(e)) if is_transient(&e) => (),
+                Err(error) => {
+                    tracing::warn!(?error, "tunnel failed to forward packet to device");
+                    return Err(error);
+                }
+            }
+
+            self.send_keepalive_if_idle()?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns true for errors that should not stop the tunnel
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+    )
+}
+
+/// Handle to a tunnel running on background threads
+#[derive(Debug)]
+pub struct TunnelHandle {
+    stop: Arc<AtomicBool>,
+    stats: Arc<TunnelStats>,
+    threads: Vec<JoinHandle<Result<(), TunError>>>,
+}
+
+impl TunnelHandle {
+    /// Returns the traffic counters for the tunnel
+    pub fn stats(&self) -> &TunnelStats {
+        &self.stats
+    }
+
+    /// Signals both forwarding threads to stop
+    ///
+    /// The UDP receiving thread stops within a fraction of a second. The device reading
+    /// thread stops after its current read returns (i.e., the next packet or error).
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Waits for both forwarding threads to exit
+    ///
+    /// # Errors
+    /// * The first error that caused a forwarding thread to exit
+    pub fn join(self) -> Result<(), TunError> {
+        let mut result = Ok(());
+        for thread in self.threads {
+            let res = thread
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("tunnel thread panicked").into()));
+            if result.is_ok() {
+                result = res;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(all(test, feature = "channel"))]
+mod tests {
+    use super::*;
+    use crate::{ChannelTun, TunConfig};
+
+    fn udp() -> UdpSocket {
+        UdpSocket::bind("127.0.0.1:0").unwrap()
+    }
+
+    #[test]
+    fn end_to_end() {
+        let (app_a, tun_a) = ChannelTun::create("tun-a", TunConfig::default()).unwrap();
+        let (app_b, tun_b) = ChannelTun::create("tun-b", TunConfig::default()).unwrap();
+        let (sock_a, sock_b) = (udp(), udp());
+        let addr_b = sock_b.local_addr().unwrap();
+
+        // a knows b, b learns a from the first datagram
+        let a = UdpTunnel::new(tun_a, sock_a)
+            .peer(addr_b)
+            .framing(Framing::LengthSequence)
+            .spawn()
+            .unwrap();
+        let b = UdpTunnel::new(tun_b, sock_b)
+            .framing(Framing::LengthSequence)
+            .spawn()
+            .unwrap();
+
+        let mut buf = [0u8; 1500];
+        for i in 0..3u8 {
+            app_a
+                .write_packet(&[0x45, i], PacketInfo::default())
+                .unwrap();
+            let (n, _) = app_b.read_packet(&mut buf).unwrap();
+            assert_eq!(buf[..n], [0x45, i]);
+        }
+
+        app_b
+            .write_packet(&[0x60, 0xFF], PacketInfo::default())
+            .unwrap();
+        let (n, _) = app_a.read_packet(&mut buf).unwrap();
+        assert_eq!(buf[..n], [0x60, 0xFF]);
+
+        assert_eq!((a.stats().tx_packets(), a.stats().rx_packets()), (3, 1));
+        assert_eq!((b.stats().tx_packets(), b.stats().rx_packets()), (1, 3));
+        assert_eq!(b.stats().lost(), 0);
+
+        // dropping the application ends unblocks the device reading threads
+        a.stop();
+        b.stop();
+        drop((app_a, app_b));
+        assert!(a.join().is_err());
+        assert!(b.join().is_err());
+    }
+
+    #[test]
+    fn keepalives_and_framing() {
+        let (app, tun) = ChannelTun::create("tun-k", TunConfig::default()).unwrap();
+        let remote = udp();
+        remote
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let tunnel = UdpTunnel::new(tun, udp())
+            .peer(remote.local_addr().unwrap())
+            .keepalive(Duration::from_millis(20))
+            .framing(Framing::LengthSequence);
+        let local = tunnel.socket().local_addr().unwrap();
+        let handle = tunnel.spawn().unwrap();
+
+        let mut buf = [0u8; 64];
+        let (n, _) = remote.recv_from(&mut buf).unwrap();
+        assert_eq!(buf[..n], [FRAME_KEEPALIVE, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(handle.stats().keepalives() >= 1);
+
+        // a gap in sequence numbers is counted as loss, a bogus length is dropped
+        remote
+            .send_to(&[FRAME_DATA, 0, 0, 1, 0, 0, 0, 5, 0x45], local)
+            .unwrap();
+        remote
+            .send_to(&[FRAME_DATA, 0, 0, 9, 0, 0, 0, 6, 0x45], local)
+            .unwrap();
+        remote
+            .send_to(&[FRAME_DATA, 0, 0, 1, 0, 0, 0, 8, 0x45], local)
+            .unwrap();
+
+        let (n, _) = app.read_packet(&mut buf).unwrap();
+        assert_eq!(buf[..n], [0x45]);
+        let (n, _) = app.read_packet(&mut buf).unwrap();
+        assert_eq!(buf[..n], [0x45]);
+
+        assert_eq!(handle.stats().rx_packets(), 2);
+        assert_eq!(handle.stats().dropped(), 1);
+        assert_eq!(handle.stats().lost(), 2);
+
+        handle.stop();
+        drop(app);
+        let _ = handle.join();
+    }
+
+    #[test]
+    fn peer_learned_from_valid_datagrams() {
+        let (_app, tun) = ChannelTun::create("tun-p", TunConfig::default()).unwrap();
+        let tunnel = UdpTunnel::new(tun, udp()).framing(Framing::LengthSequence);
+        tunnel
+            .socket()
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let local = tunnel.socket().local_addr().unwrap();
+        let (peer, stray) = (udp(), udp());
+        let mut buf = [0u8; 64];
+
+        // malformed datagrams are not learned from
+        stray.send_to(&[FRAME_DATA, 0, 0, 9], local).unwrap();
+        assert_eq!(tunnel.forward_to_tun(&mut buf).unwrap(), 0);
+        assert_eq!(tunnel.current_peer(), None);
+
+        // a keepalive is enough
+        peer.send_to(&[FRAME_KEEPALIVE, 0, 0, 0, 0, 0, 0, 0], local)
+            .unwrap();
+        assert_eq!(tunnel.forward_to_tun(&mut buf).unwrap(), 0);
+        assert_eq!(tunnel.current_peer(), peer.local_addr().ok());
+
+        // and a bogus datagram from a third address does not redirect the tunnel
+        stray
+            .send_to(&[FRAME_DATA, 0, 0, 9, 0, 0, 0, 0, 0x45], local)
+            .unwrap();
+        assert_eq!(tunnel.forward_to_tun(&mut buf).unwrap(), 0);
+        assert_eq!(tunnel.current_peer(), peer.local_addr().ok());
+        assert_eq!(tunnel.stats().dropped(), 2);
+    }
+
+    #[test]
+    fn sequence_restarts() {
+        let (_app, tun) = ChannelTun::create("tun-s", TunConfig::default()).unwrap();
+        let tunnel = UdpTunnel::new(tun, udp()).framing(Framing::LengthSequence);
+        tunnel
+            .socket()
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let local = tunnel.socket().local_addr().unwrap();
+        let (peer, other) = (udp(), udp());
+        let mut buf = [0u8; 64];
+
+        let mut send = |from: &UdpSocket, seq: u32| {
+            let mut frame = vec![FRAME_DATA, 0, 0, 1];
+            frame.extend_from_slice(&seq.to_be_bytes());
+            frame.push(0x45);
+            from.send_to(&frame, local).unwrap();
+            tunnel.forward_to_tun(&mut buf).unwrap()
+        };
+
+        // late frames are dropped, a large jump backwards is the peer restarting
+        assert_eq!(send(&peer, 5000), 1);
+        assert_eq!(send(&peer, 4999), 0);
+        assert_eq!(send(&peer, 5000), 0);
+        assert_eq!(send(&peer, 0), 1);
+        assert_eq!(send(&peer, 1), 1);
+
+        // a frame far ahead from another address does not poison the sequence numbers of
+        // the peer once it is heard from again
+        assert_eq!(send(&other, u32::MAX / 2), 1);
+        assert_eq!(send(&peer, 2), 1);
+        assert_eq!(tunnel.current_peer(), peer.local_addr().ok());
+        assert_eq!(tunnel.stats().dropped(), 2);
+        assert_eq!(tunnel.stats().lost(), 0);
+    }
+}